@@ -0,0 +1,66 @@
+use std::io::{self, IoSlice, Write};
+
+use bytes::{Buf, Bytes, BytesMut};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio_util::codec::Encoder;
+
+use como_mqtt::v5::property::PublishProperties;
+use como_mqtt::v5::string::MqttString;
+use como_mqtt::v5::types::{ControlPacket, MqttCodec, Publish, QoS};
+
+fn publish(payload: Bytes) -> Publish {
+    Publish {
+        dup: false,
+        qos: QoS::AtLeastOnce,
+        retain: false,
+        topic_name: MqttString::from("firmware/image"),
+        packet_identifier: Some(1),
+        properties: PublishProperties::default(),
+        payload,
+    }
+}
+
+fn encode_publish(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_publish");
+    for size in [1024, 1024 * 1024, 8 * 1024 * 1024] {
+        let payload = Bytes::from(vec![0xA5; size]);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("copy", size), &payload, |b, payload| {
+            let mut codec = MqttCodec::new(None);
+            let mut writer = BytesMut::new();
+            let mut sink = io::sink();
+            b.iter(|| {
+                writer.clear();
+                codec
+                    .encode(
+                        ControlPacket::Publish(publish(payload.clone())),
+                        &mut writer,
+                    )
+                    .unwrap();
+                sink.write_all(black_box(&writer)).unwrap();
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("vectored", size),
+            &payload,
+            |b, payload| {
+                let mut codec = MqttCodec::new(None);
+                let mut sink = io::sink();
+                b.iter(|| {
+                    let mut buf = codec.encode_publish(publish(payload.clone())).unwrap();
+                    // same sink as "copy", so both include writing the packet out
+                    while buf.has_remaining() {
+                        let mut slices = [IoSlice::new(&[]); 2];
+                        let n = buf.chunks_vectored(&mut slices);
+                        let written = sink.write_vectored(black_box(&slices[..n])).unwrap();
+                        buf.advance(written);
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode_publish);
+criterion_main!(benches);
@@ -1,93 +1,602 @@
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::{anyhow, bail, Error, Result};
 use bytes::Bytes;
-use futures::{SinkExt, StreamExt};
-use tokio::net::{TcpSocket, TcpStream};
-use tokio::time::timeout;
+use futures::{stream, SinkExt, Stream, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::time::Duration;
+use tokio::time::{timeout, timeout_at};
 use tokio_util::codec::Framed;
-use tracing::trace;
 use tracing::instrument;
+use tracing::{trace, warn};
 
-use crate::identifier::PacketIdentifier;
-use crate::v5::property::{PropertiesBuilder, SubscribeProperties};
+use crate::dispatch::{Dispatcher, PENDING_CAPACITY, SUBSCRIPTION_CAPACITY};
+use crate::lifecycle::{ConnectionState, DisconnectCause, Lifecycle, LifecycleEvent};
+use crate::message::{AckToken, Message};
+use crate::offline::{Blocked, OfflineQueue, Pushed};
+use crate::presence::Presence;
+use crate::session::{Event, Exhausted, RetryPolicy, Session};
+use crate::store::SessionStore;
+use crate::v5::encoder::RemainingLength;
+use crate::v5::error::MqttError;
+use crate::v5::property::{
+    ConnectProperties, DisconnectProperties, PropertiesSize, PublishProperties,
+    PublishPropertiesBuilder, SubscribeProperties,
+};
+use crate::v5::reason::{DisconnectReason, PubAckReason, SubAckReason};
 use crate::v5::string::MqttString;
+use crate::v5::topic;
 use crate::v5::types::{
-    ConnAck, Connect, ControlPacket, Disconnect, MqttCodec, Publish, PublishResponse, QoS,
-    ReasonCode, Retain, SubAck, Subscribe, SubscriptionOptions, Will,
+    ConnAck, Connect, ControlPacket, Disconnect, MqttCodec, Publish, QoS, ReasonCode, Retain,
+    SubAck, Subscribe, SubscriptionOptions, Will,
 };
 
+/// Property setter which overwrites a value already set, and its `try_` variant which fails
+/// with `MoreThanOnceProperty` instead.
+macro_rules! property_setter {
+    ($(#[$meta:meta])* $property:ident, $try_property:ident: into $type:ty) => {
+        $(#[$meta])*
+        pub fn $property(mut self, value: impl Into<$type>) -> Self {
+            self.properties.$property = Some(value.into());
+            self
+        }
+
+        pub fn $try_property(self, value: impl Into<$type>) -> Result<Self, MqttError> {
+            if self.properties.$property.is_some() {
+                return Err(MqttError::MoreThanOnceProperty);
+            }
+            Ok(self.$property(value))
+        }
+    };
+    ($(#[$meta:meta])* $property:ident, $try_property:ident: $type:ty) => {
+        $(#[$meta])*
+        pub fn $property(mut self, value: $type) -> Self {
+            self.properties.$property = Some(value);
+            self
+        }
+
+        pub fn $try_property(self, value: $type) -> Result<Self, MqttError> {
+            if self.properties.$property.is_some() {
+                return Err(MqttError::MoreThanOnceProperty);
+            }
+            Ok(self.$property(value))
+        }
+    };
+}
+
 pub struct Client {
+    address: SocketAddr,
     stream: Framed<TcpStream, MqttCodec>,
+    connected: bool,
+    lifecycle: Lifecycle,
+    offline: Option<OfflineQueue>,
+    retry_policy: RetryPolicy,
+    subscription_identifier: u32,
+    subscription_identifier_available: bool,
+    manual_ack: bool,
+    response_information: Option<MqttString>,
+    maximum_packet_size: Option<u32>,
+    maximum_qos: Option<QoS>,
+    retain_available: bool,
+    presence: Option<Presence>,
+    max_redirects: usize,
+    /// `UseAnotherServer` or `ServerMoved` DISCONNECT to follow on the next reconnect
+    server_reference: Option<(ReasonCode, MqttString)>,
+    /// Subscribed response topic of `request`
+    response_topic: Option<MqttString>,
+    correlation: u64,
+    dispatcher: Dispatcher,
+    /// Messages received before any subscription reading them was made
+    pending: VecDeque<Message>,
+    /// Ack tokens dropped without ack or nack, nacked by `recv`
+    dropped_sender: mpsc::UnboundedSender<u16>,
+    dropped: mpsc::UnboundedReceiver<u16>,
     client_id: Option<MqttString>,
     clean_start: bool,
     keep_alive: u16,
-    properties_builder: PropertiesBuilder,
+    properties: ConnectProperties,
     timeout: Option<Duration>,
-    packet_identifier: PacketIdentifier,
+    session: Session,
     username: Option<MqttString>,
     password: Option<Bytes>,
     will: Option<Will>,
 }
 
-#[derive(Clone)]
+/// Connection options of a [`Client`].
+///
+/// Property setters overwrite a value already set, their `try_` variants fail with
+/// `MoreThanOnceProperty` instead.
 pub struct MQTTOptions {
     address: SocketAddr,
     client_id: Option<MqttString>,
     keep_alive: Option<u16>,
     timeout: Option<Duration>,
-    properties_builder: PropertiesBuilder,
+    properties: ConnectProperties,
     username: Option<MqttString>,
     password: Option<Bytes>,
     will: Option<Will>,
+    validate_payload_format: bool,
+    manual_ack: bool,
+    session_store: Option<Arc<dyn SessionStore>>,
+    offline_queue: Option<OfflineQueue>,
+    retry_policy: RetryPolicy,
+    presence: Option<Presence>,
+    max_redirects: usize,
     pub clean_start: bool,
 }
 
+/// Publishes messages with the same flags and properties.
+///
+/// Property setters overwrite a value already set, their `try_` variants fail with
+/// `MoreThanOnceProperty` instead.
 pub struct Publisher {
     dup: bool,
     qos: QoS,
     retain: bool,
-    properties_builder: PropertiesBuilder,
+    properties: PublishProperties,
+    client: Client,
+}
+
+/// DISCONNECT sent by the server, returned as the error of the operation it interrupted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerDisconnect {
+    pub reason_code: ReasonCode,
+    pub reason_string: Option<MqttString>,
+    /// Other server to use, with `UseAnotherServer` or `ServerMoved`.
+    pub server_reference: Option<MqttString>,
+    pub user_properties: Vec<(MqttString, MqttString)>,
+}
+
+impl From<Disconnect> for ServerDisconnect {
+    fn from(disconnect: Disconnect) -> Self {
+        ServerDisconnect {
+            reason_code: disconnect.reason_code,
+            reason_string: disconnect.properties.reason_string,
+            server_reference: disconnect.properties.server_reference,
+            user_properties: disconnect.properties.user_properties,
+        }
+    }
+}
+
+impl fmt::Display for ServerDisconnect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "disconnected by server: {:?}", self.reason_code)?;
+        if let Some(reason_string) = &self.reason_string {
+            write!(f, " {}", String::from_utf8_lossy(reason_string))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ServerDisconnect {}
+
+/// Will the server does not support, returned by `connect` before the CONNECT when the limits
+/// of the server are known, or else after the CONNACK, disconnecting without publishing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WillUnsupported {
+    /// Will QoS above the maximum QoS of the server.
+    QoS { qos: QoS, maximum_qos: QoS },
+    /// Retained will while the server does not support retained messages.
+    Retain,
+}
+
+impl fmt::Display for WillUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WillUnsupported::QoS { qos, maximum_qos } => write!(
+                f,
+                "will QoS {} above server maximum QoS {}",
+                qos, maximum_qos
+            ),
+            WillUnsupported::Retain => write!(f, "will retain not available on server"),
+        }
+    }
+}
+
+impl std::error::Error for WillUnsupported {}
+
+/// Outcome of a publish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery<T> {
+    /// Sent on the connection, with the packet identifier or the acknowledgement reason code.
+    Sent(T),
+    /// Buffered in the offline queue until the next connection.
+    Queued,
+    /// Dropped by the overflow policy of the offline queue.
+    Dropped,
+}
+
+/// Operations of the handles waiting in the channel to the driver.
+const COMMAND_CAPACITY: usize = 64;
+
+type Reply<T> = oneshot::Sender<Result<T>>;
+
+/// Operation of a [`ClientHandle`], carried out by the [`Driver`].
+enum Command {
+    Publish(Publish, Reply<Delivery<ReasonCode>>),
+    Subscribe(MqttString, QoS, Reply<mpsc::Receiver<Message>>),
+    Ack(AckToken, ReasonCode),
+}
+
+/// Client shared between tasks, made with [`Client::into_handle`]. The operations are sent to
+/// the [`Driver`] and wait for their outcome without blocking the other handles.
+#[derive(Clone)]
+pub struct ClientHandle {
+    commands: mpsc::Sender<Command>,
+    state: watch::Receiver<ConnectionState>,
+}
+
+/// Task side of [`ClientHandle`]s, owning the client. It receives the packets of the server
+/// and carries out the operations of the handles as they come.
+pub struct Driver {
     client: Client,
+    commands: mpsc::Receiver<Command>,
+    /// Publishes held back by the full offline queue with [`Overflow::Block`](crate::offline::Overflow::Block)
+    blocked: VecDeque<(Publish, Reply<Delivery<ReasonCode>>)>,
+    /// Publishes waiting for their PUBACK or PUBCOMP
+    published: HashMap<u16, Reply<Delivery<ReasonCode>>>,
+    /// Subscriptions waiting for their SUBACK
+    subscribed: HashMap<u16, (Reply<mpsc::Receiver<Message>>, mpsc::Receiver<Message>)>,
 }
 
 pub struct Subscriber<'a> {
+    capacity: usize,
     qos: QoS,
     nl: bool,
     rap: bool,
     retain: Retain,
-    properties_builder: PropertiesBuilder,
+    properties: SubscribeProperties,
+    client: &'a mut Client,
+}
+
+/// Messages of a single subscription made with [`Subscriber::subscribe`].
+///
+/// A received PUBLISH is routed by the subscription identifiers it carries, or by matching the
+/// topic filter when the server does not support subscription identifiers. Each subscription
+/// buffers its messages in a bounded channel while it is not read. Messages beyond its
+/// capacity, and those of dropped subscriptions, are kept by the client up to a limit, the
+/// oldest is dropped when it is reached.
+pub struct Subscription<'a> {
+    topic_filter: MqttString,
+    identifier: Option<u32>,
+    ack: SubAck,
+    messages: mpsc::Receiver<Message>,
     client: &'a mut Client,
 }
 
 impl Client {
     pub async fn connect(&mut self) -> Result<ConnAck> {
+        if let Some(will) = &self.will {
+            will.validate()?;
+        }
+        // limits known from the CONNACK of a previous connection
+        self.check_will()?;
         let connect = Connect {
             reserved: false,
             clean_start_flag: self.clean_start,
             keep_alive: self.keep_alive,
-            properties: self.properties_builder.to_owned().connect(),
+            properties: self.properties.clone(),
             client_identifier: self.client_id.clone(),
             username: self.username.clone(),
             password: self.password.clone(),
             will: self.will.to_owned(),
         };
         trace!("send {:?}", connect);
-        self.stream.send(ControlPacket::Connect(connect)).await?;
-        self.recv().await.and_then(|packet| match packet {
-            ControlPacket::ConnAck(ack) => Ok(ack),
-            _ => Err(anyhow!("unexpected: {}", packet)),
-        })
+        self.session.connect(connect);
+        let ack = self.recv().await.and_then(|event| match event {
+            Event::Connected(ack) => Ok(ack),
+            _ => Err(anyhow!("unexpected: {:?}", event)),
+        })?;
+        if ack.reason_code == ReasonCode::Success {
+            self.connected = true;
+            self.lifecycle.set_state(ConnectionState::Connected);
+            self.lifecycle.emit(LifecycleEvent::Connected(ack.clone()));
+        } else {
+            self.closed(DisconnectCause::Refused(ack.reason_code));
+        }
+        self.subscription_identifier_available = ack
+            .properties
+            .subscription_identifier_available
+            .unwrap_or(true);
+        if let Some(client_id) = &ack.properties.assigned_client_identifier {
+            self.client_id = Some(client_id.clone());
+        }
+        self.response_information = ack.properties.response_information.clone();
+        self.maximum_packet_size = ack.properties.maximum_packet_size;
+        self.maximum_qos = ack.properties.maximum_qos;
+        self.retain_available = ack.properties.retain_available.unwrap_or(true);
+        if self.connected {
+            if let Err(err) = self.check_will() {
+                // a normal disconnect discards the will
+                self.session.disconnect(Disconnect {
+                    reason_code: ReasonCode::Success,
+                    properties: Default::default(),
+                });
+                self.transmit().await?;
+                self.closed(DisconnectCause::Client(ReasonCode::Success));
+                return Err(err.into());
+            }
+        }
+        if !ack.session_present {
+            self.response_topic = None;
+            // tokens of the previous session must not ack messages reusing their identifiers
+            (self.dropped_sender, self.dropped) = mpsc::unbounded_channel();
+        }
+        if self.connected {
+            if let Some(presence) = &self.presence {
+                self.session
+                    .publish(Instant::now(), presence.birth().into())?;
+            }
+            self.send_offline()?;
+            self.transmit().await?;
+        }
+        Ok(ack)
+    }
+
+    /// Checks the will against the maximum QoS and retain availability of the server.
+    fn check_will(&self) -> Result<(), WillUnsupported> {
+        let Some(will) = &self.will else {
+            return Ok(());
+        };
+        if let Some(maximum_qos) = self.maximum_qos.filter(|qos| will.qos > *qos) {
+            return Err(WillUnsupported::QoS {
+                qos: will.qos,
+                maximum_qos,
+            });
+        }
+        if will.retain && !self.retain_available {
+            return Err(WillUnsupported::Retain);
+        }
+        Ok(())
+    }
+
+    /// Opens a new connection to the server and resumes the session, then sends the messages
+    /// queued while disconnected. The connection goes to the server referenced by the last
+    /// DISCONNECT when redirects are followed.
+    pub async fn reconnect(&mut self) -> Result<ConnAck> {
+        let address = match self.server_reference.take() {
+            Some((reason_code, reference)) => self.redirect(reason_code, &reference).await?,
+            None => self.address,
+        };
+        self.open(address).await?;
+        self.clean_start = false;
+        self.connect_redirected().await
+    }
+
+    /// Connects and follows at most `max_redirects` server references of CONNACKs with
+    /// `UseAnotherServer` or `ServerMoved`.
+    async fn connect_redirected(&mut self) -> Result<ConnAck> {
+        let mut redirects = 0;
+        loop {
+            let ack = self.connect().await?;
+            match &ack.properties.server_reference {
+                Some(reference)
+                    if redirects < self.max_redirects && is_redirect(ack.reason_code) =>
+                {
+                    redirects += 1;
+                    let address = self.redirect(ack.reason_code, reference).await?;
+                    self.open(address).await?;
+                }
+                _ => return Ok(ack),
+            }
+        }
+    }
+
+    /// Resolves the referenced server, which replaces the configured one for all later
+    /// reconnects when the server moved.
+    async fn redirect(
+        &mut self,
+        reason_code: ReasonCode,
+        reference: &MqttString,
+    ) -> Result<SocketAddr> {
+        let address = resolve(reference).await?;
+        trace!("redirect {:?} to {}", reason_code, address);
+        if reason_code == ReasonCode::ServerMoved {
+            self.address = address;
+        }
+        Ok(address)
+    }
+
+    /// Opens a new connection to `address` for a reconnect or redirect.
+    async fn open(&mut self, address: SocketAddr) -> Result<()> {
+        self.lifecycle.set_state(ConnectionState::Reconnecting);
+        self.lifecycle
+            .emit(LifecycleEvent::ReconnectAttempt(address));
+        match open(address).await {
+            Ok(stream) => {
+                self.stream = Framed::new(stream, MqttCodec::new(None));
+                Ok(())
+            }
+            Err(err) => {
+                self.closed(DisconnectCause::Lost(err.to_string()));
+                Err(err)
+            }
+        }
+    }
+
+    /// Marks the connection closed, the cause is reported once per connection.
+    fn closed(&mut self, cause: DisconnectCause) {
+        self.connected = false;
+        if self.lifecycle.state() != ConnectionState::Closed {
+            self.lifecycle.set_state(ConnectionState::Closed);
+            self.lifecycle.emit(LifecycleEvent::Disconnected(cause));
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Watches the connection state, e.g. for health checks.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.lifecycle.watch()
+    }
+
+    /// Stream of the connection lifecycle events from now on, independent of the messages
+    /// received. The events are emitted while the client is driven by its operations, an
+    /// observer which does not keep up skips the oldest events.
+    pub fn lifecycle(&self) -> impl Stream<Item = LifecycleEvent> + Send + 'static {
+        self.lifecycle.events()
+    }
+
+    /// Moves the messages queued while disconnected into the session, as many as the receive
+    /// maximum of the server allows. A message leaves the queue once the session stored it.
+    fn send_offline(&mut self) -> Result<()> {
+        if let Some(offline) = &mut self.offline {
+            while let Some(publish) = offline.front()? {
+                if publish.qos != QoS::AtMostOnce && self.session.send_quota() == 0 {
+                    break;
+                }
+                self.session.publish(Instant::now(), publish)?;
+                offline.pop()?;
+            }
+        }
+        Ok(())
     }
 
-    //#[instrument(skip(self), err)]
-    pub async fn send(&mut self, msg: ControlPacket) -> Result<()> {
+    /// Buffers `publish` in the offline queue, failing when there is none or when it is full
+    /// with [`Overflow::Block`](crate::offline::Overflow::Block).
+    fn enqueue<T>(&mut self, publish: Publish) -> Result<Delivery<T>> {
+        let Some(offline) = &mut self.offline else {
+            bail!("disconnected");
+        };
+        match offline.push(publish)? {
+            Pushed::Queued => Ok(Delivery::Queued),
+            Pushed::Dropped => Ok(Delivery::Dropped),
+            Pushed::Full(publish) => Err(Blocked(publish).into()),
+        }
+    }
+
+    /// Writes a packet polled from the session, all packets go through the session so its
+    /// acknowledgement and inflight state stays consistent.
+    async fn send(&mut self, msg: ControlPacket) -> Result<()> {
         self.stream.send(msg).await.map_err(Error::msg)
     }
 
-    pub async fn recv(&mut self) -> Result<ControlPacket, Error> {
+    /// Sends a PUBLISH with a vectored write so the payload is not copied into the write buffer.
+    async fn send_publish(&mut self, msg: Publish) -> Result<()> {
+        let mut buf = self.stream.codec_mut().encode_publish(msg)?;
+        // keep packet order with anything still buffered by the framed sink
+        SinkExt::<ControlPacket>::flush(&mut self.stream).await?;
+        self.stream
+            .get_mut()
+            .write_all_buf(&mut buf)
+            .await
+            .map_err(Error::msg)
+    }
+
+    /// Drives the session until it produces the next event: sends the queued packets, feeds
+    /// received packets into the session and ticks it when its timer expires.
+    pub async fn recv(&mut self) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.poll_event().await? {
+                return Ok(event);
+            }
+            let packet = self.next_input().await?;
+            self.handle_input(packet).await?;
+        }
+    }
+
+    /// Sends the queued packets and returns the next event of the session, if any.
+    async fn poll_event(&mut self) -> Result<Option<Event>> {
+        if self.connected {
+            self.send_offline()?;
+        }
+        while let Ok(packet_identifier) = self.dropped.try_recv() {
+            if let Err(err) = self
+                .session
+                .ack(packet_identifier, ReasonCode::UnspecifiedError)
+            {
+                trace!("dropped ack token: {}", err);
+            }
+        }
+        self.transmit().await?;
+        let Some(event) = self.session.poll_event() else {
+            return Ok(None);
+        };
+        if let Event::Disconnected(disconnect) = event {
+            if let Some(reference) = &disconnect.properties.server_reference {
+                if self.max_redirects > 0 && is_redirect(disconnect.reason_code) {
+                    self.server_reference = Some((disconnect.reason_code, reference.clone()));
+                }
+            }
+            let disconnect = ServerDisconnect::from(disconnect);
+            self.closed(DisconnectCause::Server(disconnect.clone()));
+            return Err(disconnect.into());
+        }
+        if let Event::Timeout { .. } = event {
+            if self.retry_policy.get_exhausted() == Exhausted::Disconnect {
+                // the publish is resent when `reconnect` resumes the session
+                self.closed(DisconnectCause::Lost("acknowledgement timeout".into()));
+                let _ = self.stream.get_mut().shutdown().await;
+            }
+        }
+        if let Event::KeepAliveTimeout = event {
+            // the server is unreachable, the connection is closed for `reconnect`
+            self.lifecycle.emit(LifecycleEvent::PingTimeout);
+            self.closed(DisconnectCause::Lost("keep alive timeout".into()));
+            let _ = self.stream.get_mut().shutdown().await;
+            bail!("keep alive timeout");
+        }
+        Ok(Some(event))
+    }
+
+    /// Waits for the next packet, or for the session timer with `None`. Nothing is lost when
+    /// the wait is cancelled.
+    async fn next_input(&mut self) -> Result<Option<ControlPacket>> {
+        match self.session.poll_timeout() {
+            Some(deadline) => match timeout_at(deadline.into(), self.next_packet()).await {
+                Ok(packet) => packet.map(Some),
+                Err(_) => Ok(None),
+            },
+            None => self.next_packet().await.map(Some),
+        }
+    }
+
+    /// Feeds a received packet into the session, or ticks it without one.
+    async fn handle_input(&mut self, packet: Option<ControlPacket>) -> Result<()> {
+        let Some(packet) = packet else {
+            self.session.handle_tick(Instant::now());
+            return Ok(());
+        };
+        if let Err(err) = self.session.handle_packet(Instant::now(), packet) {
+            // send the rejecting acknowledgement before reporting the error
+            self.transmit().await?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    async fn transmit(&mut self) -> Result<()> {
+        while let Some(packet) = self.session.poll_transmit(Instant::now()) {
+            trace!("send {}", packet);
+            let sent = match packet {
+                ControlPacket::Publish(publish) => self.send_publish(publish).await,
+                packet => self.send(packet).await,
+            };
+            if let Err(err) = sent {
+                self.closed(DisconnectCause::Lost(err.to_string()));
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn next_packet(&mut self) -> Result<ControlPacket, Error> {
+        let packet = self.read_packet().await;
+        if let Err(err) = &packet {
+            self.closed(DisconnectCause::Lost(err.to_string()));
+        }
+        packet
+    }
+
+    async fn read_packet(&mut self) -> Result<ControlPacket, Error> {
         if let Some(time) = self.timeout {
             timeout(time, self.stream.next())
                 .await
@@ -113,15 +622,41 @@ impl Client {
         &mut self,
         reason_code: ReasonCode,
     ) -> Result<Option<ControlPacket>> {
+        self.disconnect_with(reason_code, DisconnectProperties::default())
+            .await
+    }
+
+    /// Disconnects with a reason string, user properties or a new session expiry interval,
+    /// `DisconnectWithWill` asks the server to publish the will.
+    pub async fn disconnect_with(
+        &mut self,
+        reason_code: ReasonCode,
+        properties: DisconnectProperties,
+    ) -> Result<Option<ControlPacket>> {
+        DisconnectReason::try_from(reason_code)?;
+        if properties.session_expire_interval.unwrap_or(0) > 0 {
+            // a session which expires on close can not be extended on DISCONNECT [MQTT-3.14.2-2]
+            if self.properties.session_expire_interval.unwrap_or(0) == 0 {
+                bail!("session expire interval was 0 on connect");
+            }
+        }
         let disconnect = Disconnect {
             reason_code,
-            properties: Default::default(),
+            properties,
         };
 
+        // the server publishes the will itself or discards it on a normal disconnect
+        let death = self.presence.as_ref().map(Presence::death);
+        if let Some(death) = death {
+            if self.connected && reason_code != ReasonCode::DisconnectWithWill {
+                // acknowledged before the DISCONNECT, so it is not left in the session store
+                self.publish_and_wait(death.into()).await?;
+            }
+        }
         trace!("send {}", disconnect);
-        self.stream
-            .send(ControlPacket::Disconnect(disconnect))
-            .await?;
+        self.session.disconnect(disconnect);
+        self.transmit().await?;
+        self.closed(DisconnectCause::Client(reason_code));
         // expected None on socket close
         self.stream.next().await.transpose().map_err(Error::msg)
     }
@@ -131,18 +666,19 @@ impl Client {
             dup: false,
             qos: QoS::AtMostOnce,
             retain: false,
-            properties_builder: self.properties_builder.clone(),
+            properties: PublishProperties::default(),
             client: self,
         }
     }
 
-    pub fn subscriber(&mut self) -> Subscriber {
+    pub fn subscriber(&mut self) -> Subscriber<'_> {
         Subscriber {
+            capacity: SUBSCRIPTION_CAPACITY,
             qos: QoS::AtMostOnce,
             nl: false,
             rap: false,
             retain: Retain::SendAtTime,
-            properties_builder: self.properties_builder.clone(),
+            properties: SubscribeProperties::default(),
             client: self,
         }
     }
@@ -153,32 +689,27 @@ impl Client {
         topic_name: &str,
         payload: Vec<u8>,
         retain: bool,
-    ) -> Result<Option<u16>> {
-        let packet_identifier = (qos != QoS::AtMostOnce)
-            .then(|| self.packet_identifier.next())
-            .flatten();
-
+    ) -> Result<Delivery<Option<u16>>> {
         let publish = Publish {
             dup: false,
             qos,
             retain,
             topic_name: MqttString::from(topic_name.to_owned()),
-            packet_identifier,
+            packet_identifier: None,
             properties: Default::default(),
             payload: Bytes::from(payload),
         };
-        trace!("send {}", publish);
-        self.stream
-            .send(ControlPacket::Publish(publish))
-            .await
-            .map_err(Error::msg)?;
-        Ok(packet_identifier)
+        if !self.connected {
+            return self.enqueue(publish);
+        }
+        let packet_identifier = self.session.publish(Instant::now(), publish)?;
+        self.transmit().await?;
+        Ok(Delivery::Sent(packet_identifier))
     }
 
     pub async fn subscribe(&mut self, qos: QoS, topic_filter: &str) -> Result<SubAck> {
-        let packet_identifier = self.packet_identifier.next();
         let subscribe = Subscribe {
-            packet_identifier: packet_identifier.unwrap(),
+            packet_identifier: 0,
             properties: SubscribeProperties::default(),
             topic_filters: vec![(
                 MqttString::from(topic_filter.to_owned()),
@@ -190,139 +721,510 @@ impl Client {
                 },
             )],
         };
-        trace!("send {}", subscribe);
-        self.stream
-            .send(ControlPacket::Subscribe(subscribe))
-            .await?;
-        self.recv().await.and_then(|packet| match packet {
-            ControlPacket::SubAck(ack) => Ok(ack),
-            _ => Err(anyhow!("unexpected: {}", packet)),
-        })
+        self.send_subscribe(subscribe).await
     }
 
-    pub async fn puback(&mut self, packet_identifier: u16) -> Result<()> {
-        trace!("send PUBACK packet_identifier: {}", packet_identifier);
-        self.stream
-            .send(ControlPacket::PubAck(PublishResponse {
-                packet_identifier,
-                reason_code: ReasonCode::Success,
-                properties: Default::default(),
-            }))
-            .await
-            .map_err(Error::msg)
-    }
-
-    pub async fn pubrel(&mut self, packet_identifier: u16) -> Result<()> {
-        trace!("send PUBREL packet_identifier: {}", packet_identifier);
-        self.stream
-            .send(ControlPacket::PubRel(PublishResponse {
-                packet_identifier,
-                reason_code: ReasonCode::Success,
-                properties: Default::default(),
-            }))
-            .await
-            .map_err(Error::msg)
-    }
-}
+    /// Subscribes to many topic filters with their own options in as few SUBSCRIBE packets as
+    /// the maximum packet size of the server allows. Returns the granted QoS or the failure
+    /// reason code of each filter, in order. A filter too large to be sent fails with
+    /// `PacketTooLarge`.
+    pub async fn subscribe_many<I>(
+        &mut self,
+        topic_filters: I,
+    ) -> Result<Vec<(MqttString, Result<QoS, ReasonCode>)>>
+    where
+        I: IntoIterator<Item = (MqttString, SubscriptionOptions)>,
+    {
+        let maximum_packet_size = self.maximum_packet_size.unwrap_or(u32::MAX) as usize;
+        let mut pending = vec![];
+        for subscribe in split_subscribe(topic_filters, maximum_packet_size) {
+            let subscribe = match subscribe {
+                Ok(subscribe) => subscribe,
+                Err(topic_filter) => {
+                    warn!(
+                        "topic filter exceeds the maximum packet size {:?}",
+                        topic_filter
+                    );
+                    let reason_codes = vec![ReasonCode::PacketTooLarge];
+                    pending.push((None, vec![topic_filter], Some(reason_codes)));
+                    continue;
+                }
+            };
+            let topic_filters: Vec<_> = subscribe
+                .topic_filters
+                .iter()
+                .map(|(topic_filter, _)| topic_filter.clone())
+                .collect();
+            pending.push((
+                Some(self.session.subscribe(subscribe)?),
+                topic_filters,
+                None,
+            ));
+        }
+        while pending.iter().any(|(_, _, ack)| ack.is_none()) {
+            match self.recv().await? {
+                Event::Subscribed(ack) => {
+                    match pending.iter_mut().find(|(packet_identifier, _, _)| {
+                        *packet_identifier == Some(ack.packet_identifier)
+                    }) {
+                        Some((_, _, pending)) => *pending = Some(ack.reason_codes),
+                        None => bail!("unexpected: SUBACK {}", ack.packet_identifier),
+                    }
+                }
+                Event::Message(publish) => self.dispatch(publish)?,
+                event => trace!("skip {:?}", event),
+            }
+        }
 
-impl fmt::Display for Client {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            self.stream.get_ref().local_addr().map_err(|_| fmt::Error)?
-        )
+        let mut results = vec![];
+        for (_, topic_filters, reason_codes) in pending {
+            let reason_codes = reason_codes.unwrap_or_default();
+            if reason_codes.len() != topic_filters.len() {
+                bail!("SUBACK reason codes do not match the topic filters");
+            }
+            for (topic_filter, reason_code) in topic_filters.into_iter().zip(reason_codes) {
+                let granted = SubAckReason::try_from(reason_code)
+                    .ok()
+                    .and_then(SubAckReason::granted_qos)
+                    .ok_or(reason_code);
+                results.push((topic_filter, granted));
+            }
+        }
+        Ok(results)
     }
-}
 
-impl MQTTOptions {
-    pub fn client_id(mut self, value: &'static str) -> Self {
-        self.client_id = Some(MqttString::from(value));
-        self
+    async fn send_subscribe(&mut self, subscribe: Subscribe) -> Result<SubAck> {
+        let packet_identifier = self.session.subscribe(subscribe)?;
+        loop {
+            match self.recv().await? {
+                Event::Subscribed(ack) if ack.packet_identifier == packet_identifier => {
+                    return Ok(ack)
+                }
+                Event::Message(publish) => self.dispatch(publish)?,
+                Event::Connected(ack) => bail!("unexpected: {:?}", ack),
+                // e.g. completion of a publish made without waiting, like the birth message
+                event => trace!("skip {:?}", event),
+            }
+        }
     }
 
-    pub fn keep_alive(mut self, value: u16) -> Self {
-        self.keep_alive = Some(value);
-        self
+    /// SUBSCRIBE to `topic_filter` and the receiver of its messages, with a subscription
+    /// identifier assigned when none is set and the server supports them. The messages are
+    /// routed before the SUBSCRIBE is sent, a retained message may follow the SUBACK immediately.
+    fn route(
+        &mut self,
+        topic_filter: MqttString,
+        options: SubscriptionOptions,
+        mut properties: SubscribeProperties,
+        capacity: usize,
+    ) -> (Subscribe, mpsc::Receiver<Message>) {
+        if properties.subscription_identifier.is_none() && self.subscription_identifier_available {
+            // 1 to 268,435,455
+            self.subscription_identifier = self.subscription_identifier % 268_435_455 + 1;
+            properties.subscription_identifier = Some(self.subscription_identifier);
+        }
+        let messages = self.dispatcher.route(
+            topic_filter.clone(),
+            properties.subscription_identifier,
+            capacity,
+        );
+        let subscribe = Subscribe {
+            packet_identifier: 0,
+            properties,
+            topic_filters: vec![(topic_filter, options)],
+        };
+        (subscribe, messages)
     }
 
-    pub fn clean_start(mut self, value: bool) -> Self {
-        self.clean_start = value;
-        self
+    /// Acknowledges a message received in manual ack mode.
+    pub async fn ack(&mut self, token: AckToken) -> Result<()> {
+        self.nack(token, ReasonCode::Success).await
     }
 
-    pub fn session_expire_interval(mut self, value: u32) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .session_expire_interval(value)
-            .unwrap();
-        self
+    /// Rejects a message received in manual ack mode with a PUBACK or PUBREC `reason_code`.
+    pub async fn nack(&mut self, token: AckToken, reason_code: ReasonCode) -> Result<()> {
+        self.session.ack(token.take(), reason_code)?;
+        self.transmit().await
     }
 
-    pub fn receive_maximum(mut self, value: u16) -> Self {
-        self.properties_builder = self.properties_builder.receive_maximum(value).unwrap();
-        self
+    /// Routes a received message to its subscriptions, or keeps it for a subscription made
+    /// later. When too many are kept, the oldest is dropped and rejected in manual ack mode.
+    fn dispatch(&mut self, publish: Publish) -> Result<()> {
+        let message = self.message(publish);
+        if let Some(message) = self.dispatcher.dispatch(message) {
+            if self.pending.len() >= PENDING_CAPACITY {
+                if let Some(dropped) = self.pending.pop_front() {
+                    warn!("too many pending messages, drop {:?}", dropped.topic);
+                    if let Some(token) = dropped.ack {
+                        self.session.ack(token.take(), ReasonCode::QuotaExceeded)?;
+                    }
+                }
+            }
+            self.pending.push_back(message);
+        }
+        Ok(())
     }
 
-    pub fn content_type(mut self, value: &'static str) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .content_type(Some(MqttString::from(value)))
-            .unwrap();
-        self
+    fn message(&self, publish: Publish) -> Message {
+        let token = match publish.packet_identifier {
+            Some(packet_identifier) if self.manual_ack => Some(AckToken::new(
+                packet_identifier,
+                self.dropped_sender.clone(),
+            )),
+            _ => None,
+        };
+        Message {
+            ack: token,
+            ..publish.into()
+        }
     }
 
-    pub fn user_property(mut self, (key, value): (&'static str, &'static str)) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .user_property((MqttString::from(key), MqttString::from(value)));
-        self
+    /// Publishes `message` with its own flags and properties, queuing it while disconnected,
+    /// and waits for the acknowledgement of a QoS 1 or QoS 2 message.
+    pub async fn publish_message(&mut self, message: Message) -> Result<Delivery<ReasonCode>> {
+        self.publish_and_wait(message.into()).await
     }
 
-    pub fn will(mut self, will: Will) -> Self {
-        self.will = Some(will);
-        self
+    /// Shares the client between tasks, e.g. to publish messages from several of them. The
+    /// returned [`Driver`] owns the client and must be run, e.g. on its own task, for the
+    /// operations of the handles to make progress.
+    pub fn into_handle(self) -> (ClientHandle, Driver) {
+        let (commands, receiver) = mpsc::channel(COMMAND_CAPACITY);
+        let handle = ClientHandle {
+            commands,
+            state: self.state(),
+        };
+        let driver = Driver {
+            client: self,
+            commands: receiver,
+            blocked: VecDeque::new(),
+            published: HashMap::new(),
+            subscribed: HashMap::new(),
+        };
+        (handle, driver)
     }
 
-    pub fn timeout(mut self, timeout: Duration) -> Self {
-        self.timeout = Some(timeout);
-        self
+    /// Publishes `publish` or queues it while disconnected, and waits for the PUBACK or PUBCOMP
+    /// of a QoS 1 or QoS 2 message.
+    async fn publish_and_wait(&mut self, publish: Publish) -> Result<Delivery<ReasonCode>> {
+        if !self.connected {
+            return self.enqueue(publish);
+        }
+        let packet_identifier = match self.session.publish(Instant::now(), publish)? {
+            Some(packet_identifier) => packet_identifier,
+            None => {
+                self.transmit().await?;
+                return Ok(Delivery::Sent(ReasonCode::Success));
+            }
+        };
+        loop {
+            match self.recv().await? {
+                Event::Published {
+                    packet_identifier: id,
+                    reason_code,
+                } if id == packet_identifier => return Ok(Delivery::Sent(reason_code)),
+                Event::Timeout {
+                    packet_identifier: id,
+                } if id == packet_identifier => {
+                    bail!("acknowledgement timeout: {}", packet_identifier)
+                }
+                // completion of a message resent from the session store or offline queue
+                event @ (Event::Published { .. }
+                | Event::Timeout { .. }
+                | Event::Discarded { .. }) => {
+                    warn!("skip {:?}", event)
+                }
+                Event::Message(publish) => self.dispatch(publish)?,
+                Event::Connected(ack) => bail!("unexpected: {:?}", ack),
+                event => trace!("skip {:?}", event),
+            }
+        }
     }
 
-    pub fn username(mut self, value: &'static str) -> Self {
-        self.username = Some(MqttString::from(value));
-        self
+    /// Publishes a request to `topic` and waits up to `timeout` for the reply carrying its
+    /// correlation data. Replies are received on a response topic derived from the response
+    /// information of the server, or else from the client identifier.
+    pub async fn request(
+        &mut self,
+        topic: &str,
+        payload: impl Into<Bytes>,
+        timeout: Duration,
+    ) -> Result<Message> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let response_topic = self.response_topic().await?;
+        self.correlation += 1;
+        let correlation_data = Bytes::copy_from_slice(&self.correlation.to_be_bytes());
+        let properties = PublishPropertiesBuilder::default()
+            .response_topic(response_topic.clone())?
+            .correlation_data(correlation_data.clone())?
+            .build();
+        let request = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic_name: MqttString::from(topic.to_owned()),
+            packet_identifier: None,
+            properties,
+            payload: payload.into(),
+        };
+        self.session.publish(Instant::now(), request)?;
+        loop {
+            let event = match timeout_at(deadline, self.recv()).await {
+                Ok(event) => event?,
+                Err(_) => bail!("request timeout: {}", topic),
+            };
+            match event {
+                Event::Message(publish) if publish.topic_name == response_topic => {
+                    if publish.properties.correlation_data.as_ref() == Some(&correlation_data) {
+                        return Ok(self.message(publish));
+                    }
+                    warn!("drop late reply {:?}", publish.properties.correlation_data);
+                    if let Some(token) = self.message(publish).ack {
+                        self.session.ack(token.take(), ReasonCode::Success)?;
+                    }
+                }
+                Event::Message(publish) => self.dispatch(publish)?,
+                event => trace!("skip {:?}", event),
+            }
+        }
     }
 
-    pub fn password(mut self, value: &'static [u8]) -> Self {
-        self.password = Some(Bytes::from_static(value));
-        self
+    /// Answers the requests received on `topic_filter` with the payload returned by `handler`,
+    /// until receiving fails. Requests without a response topic are dropped.
+    pub async fn serve<F>(&mut self, topic_filter: &str, mut handler: F) -> Result<()>
+    where
+        F: FnMut(Message) -> Bytes,
+    {
+        let mut subscription = self
+            .subscriber()
+            .qos(QoS::AtLeastOnce)
+            .subscribe(topic_filter)
+            .await?;
+        loop {
+            let mut request = subscription.recv().await?;
+            let token = request.ack.take();
+            let correlation_data = request.properties.correlation_data.clone();
+            let Some(response_topic) = request.properties.response_topic.clone() else {
+                warn!("drop request without response topic {:?}", request.topic);
+                if let Some(token) = token {
+                    subscription.ack(token).await?;
+                }
+                continue;
+            };
+            let mut properties = PublishPropertiesBuilder::default();
+            if let Some(correlation_data) = correlation_data {
+                properties = properties.correlation_data(correlation_data)?;
+            }
+            let reply = Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                topic_name: response_topic,
+                packet_identifier: None,
+                properties: properties.build(),
+                payload: handler(request),
+            };
+            subscription.client.session.publish(Instant::now(), reply)?;
+            match token {
+                Some(token) => subscription.ack(token).await?,
+                None => subscription.client.transmit().await?,
+            }
+        }
     }
 
-    pub async fn connect(self) -> Result<Client> {
-        let socket = if self.address.is_ipv4() {
-            TcpSocket::new_v4()?
-        } else {
-            TcpSocket::new_v6()?
+    /// Response topic of `request`, subscribed on first use.
+    async fn response_topic(&mut self) -> Result<MqttString> {
+        if let Some(response_topic) = &self.response_topic {
+            return Ok(response_topic.clone());
+        }
+        let response_topic = match (&self.response_information, &self.client_id) {
+            (Some(information), _) => format!("{}/reply", std::str::from_utf8(information)?),
+            (None, Some(client_id)) => format!("reply/{}", std::str::from_utf8(client_id)?),
+            (None, None) => bail!("no response information or client identifier"),
         };
+        let ack = self.subscribe(QoS::AtLeastOnce, &response_topic).await?;
+        if let Some(reason_code) = ack.reason_codes.first() {
+            if u8::from(*reason_code) >= u8::from(ReasonCode::UnspecifiedError) {
+                bail!("response topic subscription: {:?}", reason_code);
+            }
+        }
+        let response_topic = MqttString::from(response_topic);
+        self.response_topic = Some(response_topic.clone());
+        Ok(response_topic)
+    }
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.stream.get_ref().local_addr().map_err(|_| fmt::Error)?
+        )
+    }
+}
+
+impl MQTTOptions {
+    pub fn client_id(mut self, value: impl Into<MqttString>) -> Self {
+        self.client_id = Some(value.into());
+        self
+    }
+
+    pub fn keep_alive(mut self, value: u16) -> Self {
+        self.keep_alive = Some(value);
+        self
+    }
+
+    pub fn clean_start(mut self, value: bool) -> Self {
+        self.clean_start = value;
+        self
+    }
+
+    property_setter!(session_expire_interval, try_session_expire_interval: u32);
+    property_setter!(receive_maximum, try_receive_maximum: u16);
+
+    pub fn user_property<K, V>(mut self, (key, value): (K, V)) -> Self
+    where
+        K: Into<MqttString>,
+        V: Into<MqttString>,
+    {
+        self.properties
+            .user_properties
+            .push((key.into(), value.into()));
+        self
+    }
 
-        let stream = socket.connect(self.address).await?;
-        let stream = Framed::new(stream, MqttCodec::new(None));
+    pub fn will(mut self, will: Will) -> Self {
+        self.will = Some(will);
+        self
+    }
+
+    /// Announces the client on a presence topic, replacing the will with its death message.
+    pub fn presence(mut self, presence: Presence) -> Self {
+        self.will = Some(presence.will());
+        self.presence = Some(presence);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn username(mut self, value: impl Into<MqttString>) -> Self {
+        self.username = Some(value.into());
+        self
+    }
+
+    pub fn password(mut self, value: impl Into<Bytes>) -> Self {
+        self.password = Some(value.into());
+        self
+    }
+
+    property_setter!(
+        /// Ask the server for the response information used to derive the response topic of
+        /// [`Client::request`].
+        request_response_information, try_request_response_information: bool
+    );
+
+    /// Send the PUBACK or PUBREC of a received QoS 1 or QoS 2 message only when its
+    /// [`AckToken`] is acked or nacked, instead of on receipt. A dropped token is nacked with
+    /// [`ReasonCode::UnspecifiedError`] by the next `recv`.
+    pub fn manual_ack(mut self, value: bool) -> Self {
+        self.manual_ack = value;
+        self
+    }
+
+    /// Acknowledgement timeout and retransmission of outgoing QoS 1 and QoS 2 publishes.
+    pub fn retry_policy(mut self, value: RetryPolicy) -> Self {
+        self.retry_policy = value;
+        self
+    }
+
+    /// Buffers publishes made while disconnected in `queue` until the next connection.
+    pub fn offline_queue(mut self, queue: OfflineQueue) -> Self {
+        self.offline_queue = Some(queue);
+        self
+    }
+
+    /// Answer UTF-8 marked PUBLISH payloads that are not valid UTF-8 with
+    /// `PayloadFormatInvalid` instead of delivering them.
+    pub fn validate_payload_format(mut self, value: bool) -> Self {
+        self.validate_payload_format = value;
+        self
+    }
+
+    /// Keeps unacknowledged outgoing QoS 1 and QoS 2 messages in `store`, to be retransmitted
+    /// when a later connection with `clean_start(false)` resumes the session.
+    pub fn session_store(mut self, store: impl SessionStore + 'static) -> Self {
+        self.session_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Follows at most `max_redirects` server references of a CONNACK or DISCONNECT with
+    /// `UseAnotherServer` or `ServerMoved`. The client connects to the first referenced server
+    /// which resolves, for the current connection with `UseAnotherServer` and for all later
+    /// reconnects with `ServerMoved`. Redirects are not followed by default.
+    pub fn redirect(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    pub async fn connect(self) -> Result<Client> {
+        if let Some(will) = &self.will {
+            let will_delay_interval = will.properties.will_delay_interval;
+            // the server publishes the will no later than the session expires, which is not
+            // lengthened here as that would keep the session on the server
+            if will_delay_interval > 0 && self.properties.session_expire_interval.unwrap_or(0) == 0
+            {
+                bail!("will delay interval needs a session expire interval")
+            }
+        }
+        let stream = Framed::new(open(self.address).await?, MqttCodec::new(None));
+        let mut session = Session::default()
+            .validate_payload_format(self.validate_payload_format)
+            .manual_ack(self.manual_ack)
+            .retry_policy(self.retry_policy);
+        if let Some(store) = self.session_store {
+            session = session.store(store);
+        }
+        let (dropped_sender, dropped) = mpsc::unbounded_channel();
 
         let mut client = Client {
+            address: self.address,
             stream,
+            connected: false,
+            lifecycle: Lifecycle::new(),
+            offline: self.offline_queue,
+            retry_policy: self.retry_policy,
+            subscription_identifier: 0,
+            subscription_identifier_available: true,
+            manual_ack: self.manual_ack,
+            response_information: None,
+            maximum_packet_size: None,
+            maximum_qos: None,
+            retain_available: true,
+            presence: self.presence,
+            max_redirects: self.max_redirects,
+            server_reference: None,
+            response_topic: None,
+            correlation: 0,
+            dispatcher: Dispatcher::default(),
+            pending: VecDeque::new(),
+            dropped_sender,
+            dropped,
             client_id: self.client_id,
             clean_start: self.clean_start,
             keep_alive: self.keep_alive.unwrap_or(0),
-            properties_builder: self.properties_builder,
+            properties: self.properties,
             timeout: self.timeout,
-            packet_identifier: Default::default(),
+            session,
             username: self.username,
             password: self.password,
             will: self.will,
         };
 
-        let ack = client.connect().await?;
+        let ack = client.connect_redirected().await?;
         if ack.reason_code != ReasonCode::Success {
             bail!("Connect error: {:?}", ack.reason_code)
         } else {
@@ -336,10 +1238,17 @@ impl MQTTOptions {
             client_id: None,
             keep_alive: None,
             timeout: None,
-            properties_builder: PropertiesBuilder::default(),
+            properties: ConnectProperties::default(),
             username: None,
             password: None,
             will: None,
+            validate_payload_format: false,
+            manual_ack: false,
+            session_store: None,
+            offline_queue: None,
+            retry_policy: RetryPolicy::default(),
+            presence: None,
+            max_redirects: 0,
         }
     }
 }
@@ -360,247 +1269,246 @@ impl Publisher {
         self
     }
 
-    pub fn payload_format_indicator(mut self, value: u8) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .payload_format_indicator(value)
-            .unwrap();
-        self
-    }
-    pub fn message_expire_interval(mut self, value: u32) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .message_expire_interval(value)
-            .unwrap();
-        self
-    }
-    pub fn content_type(mut self, value: &'static str) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .content_type(Some(MqttString::from(value)))
-            .unwrap();
-        self
-    }
-    pub fn response_topic(mut self, value: &'static str) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .response_topic(Some(MqttString::from(value)))
-            .unwrap();
-        self
-    }
-    pub fn correlation_data(mut self, value: &'static [u8]) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .correlation_data(Some(Bytes::from_static(value)))
-            .unwrap();
-        self
-    }
-    pub fn topic_alias(mut self, value: u16) -> Self {
-        self.properties_builder = self.properties_builder.topic_alias(value).unwrap();
-        self
-    }
+    property_setter!(payload_format_indicator, try_payload_format_indicator: bool);
+    property_setter!(message_expire_interval, try_message_expire_interval: u32);
+    property_setter!(content_type, try_content_type: into MqttString);
+    property_setter!(response_topic, try_response_topic: into MqttString);
+    property_setter!(correlation_data, try_correlation_data: into Bytes);
+    property_setter!(topic_alias, try_topic_alias: u16);
+
     pub fn subscription_identifier(mut self, value: u32) -> Self {
-        self.properties_builder = self
-            .properties_builder
-            .subscription_identifier(value)
-            .unwrap();
+        self.properties.subscription_identifiers.push(value);
         self
     }
 
     #[instrument(skip(self, payload), err)]
-    pub async fn publish(&mut self, topic_name: String, payload: Vec<u8>) -> Result<ReasonCode> {
-        match self.qos {
-            QoS::AtMostOnce => self.at_most_once_publish(topic_name, payload).await,
-            QoS::AtLeastOnce => self.at_least_once_publish(topic_name, payload).await,
-            QoS::ExactlyOnce => self.exactly_once_publish(topic_name, payload).await,
-        }
-    }
-
-    async fn at_most_once_publish(
+    pub async fn publish(
         &mut self,
         topic_name: String,
         payload: Vec<u8>,
-    ) -> Result<ReasonCode> {
+    ) -> Result<Delivery<ReasonCode>> {
         let msg = Publish {
             dup: self.dup,
             qos: self.qos,
             retain: self.retain,
             topic_name: MqttString::from(topic_name),
             packet_identifier: None,
-            properties: self.properties_builder.clone().publish(),
+            properties: self.properties.clone(),
             payload: Bytes::from(payload),
         };
-        trace!("send {}", msg);
-        self.client.send(ControlPacket::Publish(msg)).await?;
-        Ok(ReasonCode::Success)
+        self.client.publish_and_wait(msg).await
     }
 
-    async fn at_least_once_publish(
-        &mut self,
-        topic_name: String,
-        payload: Vec<u8>,
-    ) -> Result<ReasonCode> {
-        let mut dup = false;
-        let mut retries = 3;
-        let packet_identifier = self.client.packet_identifier.next();
-        let topic_name = MqttString::from(topic_name);
-        let properties = self.properties_builder.clone().publish();
-        let payload = Bytes::from(payload);
+    pub async fn disconnect(&mut self) -> Result<Option<ControlPacket>> {
+        self.client
+            .disconnect_with_reason(ReasonCode::Success)
+            .await
+    }
+}
 
-        loop {
-            let msg = Publish {
-                dup,
-                qos: self.qos,
-                retain: self.retain,
-                topic_name: topic_name.clone(),
-                packet_identifier,
-                properties: properties.clone(),
-                payload: payload.clone(),
-            };
-            trace!("send {}", msg);
+impl ClientHandle {
+    /// Publishes `message` and waits for its acknowledgement. With the offline queue full and
+    /// [`Overflow::Block`](crate::offline::Overflow::Block), it waits until the client is
+    /// connected again.
+    pub async fn publish(&self, message: Message) -> Result<Delivery<ReasonCode>> {
+        let (reply, delivery) = oneshot::channel();
+        self.send(Command::Publish(message.into(), reply)).await?;
+        delivery.await?
+    }
 
-            self.client.send(ControlPacket::Publish(msg)).await?;
+    /// Subscribes to `topic_filter`, returning the receiver of its messages.
+    pub async fn subscribe(&self, qos: QoS, topic_filter: &str) -> Result<mpsc::Receiver<Message>> {
+        let topic_filter = MqttString::from(topic_filter.to_owned());
+        let (reply, messages) = oneshot::channel();
+        self.send(Command::Subscribe(topic_filter, qos, reply))
+            .await?;
+        messages.await?
+    }
 
-            match timeout(
-                Duration::from_secs(3),
-                self.recv_ack(packet_identifier.unwrap()),
-            )
-            .await
-            {
-                Ok(res) => return res.map(|res| res.reason_code),
-                Err(elapsed) => {
-                    if retries > 0 {
-                        retries = retries - 1;
-                        dup = true;
-                        continue;
-                    } else {
-                        bail!("PUBACK error: {}", elapsed)
-                    }
-                }
-            }
-        }
+    /// Acknowledges a message received in manual ack mode.
+    pub async fn ack(&self, token: AckToken) -> Result<()> {
+        self.nack(token, ReasonCode::Success).await
     }
 
-    async fn exactly_once_publish(
-        &mut self,
-        topic_name: String,
-        payload: Vec<u8>,
-    ) -> Result<ReasonCode> {
-        let mut dup = false;
-        let mut retries = 3;
-        let packet_identifier = self.client.packet_identifier.next();
-        let topic_name = MqttString::from(topic_name);
-        let properties = self.properties_builder.clone().publish();
-        let payload = Bytes::from(payload);
+    /// Rejects a message received in manual ack mode with a PUBACK or PUBREC `reason_code`.
+    pub async fn nack(&self, token: AckToken, reason_code: ReasonCode) -> Result<()> {
+        PubAckReason::try_from(reason_code)?;
+        self.send(Command::Ack(token, reason_code)).await
+    }
+
+    /// Current connection state, see [`Client::state`].
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
 
+    async fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("client driver stopped"))
+    }
+}
+
+impl Driver {
+    /// Serves the handles until all of them are dropped, or returns the error of the
+    /// connection. The client can then be reconnected through [`Driver::client`] and run again,
+    /// the operations waiting for it are kept.
+    pub async fn run(&mut self) -> Result<()> {
         loop {
-            let msg = Publish {
-                dup,
-                qos: self.qos,
-                retain: self.retain,
-                topic_name: topic_name.clone(),
-                packet_identifier,
-                properties: properties.clone(),
-                payload: payload.clone(),
+            if self.client.connected {
+                while let Some((publish, reply)) = self.blocked.pop_front() {
+                    self.publish(publish, reply);
+                }
+            }
+            while let Some(event) = self.client.poll_event().await? {
+                self.handle_event(event)?;
+            }
+            let packet = tokio::select! {
+                packet = self.client.next_input() => packet?,
+                command = self.commands.recv() => match command {
+                    Some(command) => {
+                        self.handle_command(command);
+                        continue;
+                    }
+                    None => return Ok(()),
+                },
             };
-            trace!("send {}", msg);
+            self.client.handle_input(packet).await?;
+        }
+    }
 
-            self.client.send(ControlPacket::Publish(msg)).await?;
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
 
-            match timeout(
-                Duration::from_secs(3),
-                self.recv_rec(packet_identifier.unwrap()),
-            )
-            .await
-            {
-                Ok(res) => {
-                    let res = res?;
-                    if res.reason_code != ReasonCode::Success {
-                        self.client.packet_identifier.release(res.packet_identifier);
-                        return Ok(res.reason_code);
-                    } else {
-                        return self
-                            .send_rel(packet_identifier.unwrap(), ReasonCode::Success)
-                            .await;
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Publish(publish, reply) => self.publish(publish, reply),
+            Command::Subscribe(topic_filter, qos, reply) => {
+                let options = SubscriptionOptions {
+                    qos,
+                    nl: false,
+                    rap: false,
+                    retain: Retain::SendAtTime,
+                };
+                let (subscribe, messages) = self.client.route(
+                    topic_filter,
+                    options,
+                    SubscribeProperties::default(),
+                    SUBSCRIPTION_CAPACITY,
+                );
+                // messages received before, e.g. on the resumed session
+                for message in std::mem::take(&mut self.client.pending) {
+                    if let Some(message) = self.client.dispatcher.dispatch(message) {
+                        self.client.pending.push_back(message);
                     }
                 }
-                Err(elapsed) => {
-                    if retries > 0 {
-                        retries = retries - 1;
-                        dup = true;
-                        continue;
-                    } else {
-                        bail!("PUBREC error: {}", elapsed)
+                match self.client.session.subscribe(subscribe) {
+                    Ok(packet_identifier) => {
+                        self.subscribed.insert(packet_identifier, (reply, messages));
+                    }
+                    Err(err) => {
+                        let _ = reply.send(Err(err));
                     }
                 }
             }
+            Command::Ack(token, reason_code) => {
+                let packet_identifier = token.take();
+                if let Err(err) = self.client.session.ack(packet_identifier, reason_code) {
+                    warn!("ack {}: {}", packet_identifier, err);
+                }
+            }
         }
     }
 
-    async fn recv_ack(&mut self, packet_identifier: u16) -> Result<PublishResponse, Error> {
-        self.client.recv().await.and_then(|packet| match packet {
-            ControlPacket::PubAck(res) if res.packet_identifier == packet_identifier => {
-                self.client.packet_identifier.release(res.packet_identifier);
-                Ok(res)
+    /// Hands `publish` to the session, or to the offline queue while disconnected. The reply is
+    /// sent once the outcome is known.
+    fn publish(&mut self, publish: Publish, reply: Reply<Delivery<ReasonCode>>) {
+        if !self.client.connected {
+            if !self.blocked.is_empty() {
+                // keep the order of the publishes waiting for the connection
+                self.blocked.push_back((publish, reply));
+                return;
             }
-            unexpected => bail!("unexpected: {}", unexpected),
-        })
-    }
-
-    async fn recv_rec(&mut self, packet_identifier: u16) -> Result<PublishResponse, Error> {
-        self.client.recv().await.and_then(|packet| match packet {
-            ControlPacket::PubRec(res) if res.packet_identifier == packet_identifier => Ok(res),
-            unexpected => bail!("unexpected: {}", unexpected),
-        })
-    }
-
-    async fn send_rel(
-        &mut self,
-        packet_identifier: u16,
-        reason_code: ReasonCode,
-    ) -> Result<ReasonCode> {
-        let mut retries = 3;
-        loop {
-            self.client
-                .send(ControlPacket::PubRel(PublishResponse {
-                    packet_identifier,
-                    reason_code,
-                    properties: self.properties_builder.clone().pubres(),
-                }))
-                .await?;
-
-            match timeout(Duration::from_secs(3), self.res_comp(packet_identifier)).await {
-                Ok(res) => return res.map(|res| res.reason_code),
-                Err(elapsed) => {
-                    if retries > 0 {
-                        retries = retries - 1;
-                        continue;
-                    } else {
-                        bail!("PUBCOMP error: {}", elapsed)
+            match self.client.enqueue(publish) {
+                Err(err) => match err.downcast::<Blocked>() {
+                    Ok(Blocked(publish)) => self.blocked.push_back((publish, reply)),
+                    Err(err) => {
+                        let _ = reply.send(Err(err));
                     }
+                },
+                delivery => {
+                    let _ = reply.send(delivery);
                 }
             }
+            return;
+        }
+        match self.client.session.publish(Instant::now(), publish) {
+            Ok(Some(packet_identifier)) => {
+                self.published.insert(packet_identifier, reply);
+            }
+            Ok(None) => {
+                let _ = reply.send(Ok(Delivery::Sent(ReasonCode::Success)));
+            }
+            Err(err) => {
+                let _ = reply.send(Err(err));
+            }
         }
     }
 
-    async fn res_comp(&mut self, packet_identifier: u16) -> Result<PublishResponse, Error> {
-        self.client.recv().await.and_then(|packet| match packet {
-            ControlPacket::PubComp(res) if res.packet_identifier == packet_identifier => {
-                self.client.packet_identifier.release(res.packet_identifier);
-                Ok(res)
+    fn handle_event(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Published {
+                packet_identifier,
+                reason_code,
+            } => {
+                if let Some(reply) = self.published.remove(&packet_identifier) {
+                    let _ = reply.send(Ok(Delivery::Sent(reason_code)));
+                }
             }
-            unexpected => Err(anyhow!("unexpected: {}", unexpected)),
-        })
+            Event::Timeout { packet_identifier } => {
+                if let Some(reply) = self.published.remove(&packet_identifier) {
+                    let _ = reply.send(Err(anyhow!(
+                        "acknowledgement timeout: {}",
+                        packet_identifier
+                    )));
+                }
+            }
+            Event::Discarded { packet_identifier } => {
+                if let Some(reply) = self.published.remove(&packet_identifier) {
+                    let _ = reply.send(Err(anyhow!(
+                        "session not resumed, publish discarded: {}",
+                        packet_identifier
+                    )));
+                }
+            }
+            Event::Subscribed(ack) => {
+                if let Some((reply, messages)) = self.subscribed.remove(&ack.packet_identifier) {
+                    let granted = match ack.reason_codes.first() {
+                        Some(reason_code)
+                            if u8::from(*reason_code) >= u8::from(ReasonCode::UnspecifiedError) =>
+                        {
+                            Err(anyhow!("subscription: {:?}", reason_code))
+                        }
+                        _ => Ok(messages),
+                    };
+                    let _ = reply.send(granted);
+                }
+            }
+            Event::Message(publish) => self.client.dispatch(publish)?,
+            event => trace!("skip {:?}", event),
+        }
+        Ok(())
     }
+}
 
-    pub async fn disconnect(&mut self) -> Result<Option<ControlPacket>> {
-        self.client
-            .disconnect_with_reason(ReasonCode::Success)
-            .await
+impl<'a> Subscriber<'a> {
+    /// Messages buffered for the subscription while it is not read, 64 by default.
+    pub fn capacity(mut self, value: usize) -> Self {
+        self.capacity = value;
+        self
     }
-}
 
-impl Subscriber<'_> {
     pub fn qos(mut self, qos: QoS) -> Self {
         self.qos = qos;
         self
@@ -631,32 +1539,985 @@ impl Subscriber<'_> {
         self
     }
 
-    pub async fn subscribe(self, topic_filter: &str) -> Result<SubAck> {
-        let packet_identifier = self.client.packet_identifier.next();
-        let subscribe = Subscribe {
-            packet_identifier: packet_identifier.unwrap(),
-            properties: self.properties_builder.subscribe(),
-            topic_filters: vec![(
-                MqttString::from(topic_filter.to_owned()),
-                SubscriptionOptions {
-                    qos: self.qos,
-                    nl: self.nl,
-                    rap: self.rap,
-                    retain: self.retain,
-                },
-            )],
+    property_setter!(
+        /// Identifier of the subscription, one is assigned automatically when not set and the
+        /// server supports subscription identifiers.
+        subscription_identifier, try_subscription_identifier: u32
+    );
+
+    pub async fn subscribe(self, topic_filter: &str) -> Result<Subscription<'a>> {
+        let topic_filter = MqttString::from(topic_filter.to_owned());
+        let options = SubscriptionOptions {
+            qos: self.qos,
+            nl: self.nl,
+            rap: self.rap,
+            retain: self.retain,
         };
-        trace!("send {}", subscribe);
-        self.client
-            .send(ControlPacket::Subscribe(subscribe))
-            .await?;
-        self.client.recv().await.and_then(|packet| match packet {
-            ControlPacket::SubAck(ack) => Ok(ack),
-            _ => Err(anyhow!("unexpected: {}", packet)),
+        let (subscribe, messages) = self.client.route(
+            topic_filter.clone(),
+            options,
+            self.properties,
+            self.capacity,
+        );
+        let identifier = subscribe.properties.subscription_identifier;
+        let ack = self.client.send_subscribe(subscribe).await?;
+        Ok(Subscription {
+            topic_filter,
+            identifier,
+            ack,
+            messages,
+            client: self.client,
+        })
+    }
+}
+
+impl<'a> Subscription<'a> {
+    pub fn suback(&self) -> &SubAck {
+        &self.ack
+    }
+
+    /// Whether `message` was delivered for this subscription.
+    pub fn matches(&self, message: &Message) -> bool {
+        match self.identifier {
+            Some(identifier) => message
+                .properties
+                .subscription_identifiers
+                .contains(&identifier),
+            None => topic::matches(&self.topic_filter, &message.topic),
+        }
+    }
+
+    /// Next message of this subscription.
+    pub async fn recv(&mut self) -> Result<Message> {
+        loop {
+            if let Ok(message) = self.messages.try_recv() {
+                return Ok(message);
+            }
+            if let Some(pos) = self.client.pending.iter().position(|m| self.matches(m)) {
+                if let Some(message) = self.client.pending.remove(pos) {
+                    return Ok(message);
+                }
+            }
+            match self.client.recv().await? {
+                Event::Message(publish) => self.client.dispatch(publish)?,
+                event => trace!("skip {:?}", event),
+            }
+        }
+    }
+
+    pub async fn ack(&mut self, token: AckToken) -> Result<()> {
+        self.client.ack(token).await
+    }
+
+    pub async fn nack(&mut self, token: AckToken, reason_code: ReasonCode) -> Result<()> {
+        self.client.nack(token, reason_code).await
+    }
+
+    /// Stream of the messages of this subscription, ending after the first error.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Message>> + 'a {
+        stream::unfold(Some(self), |subscription| async move {
+            let mut subscription = subscription?;
+            match subscription.recv().await {
+                Ok(message) => Some((Ok(message), Some(subscription))),
+                Err(err) => Some((Err(err), None)),
+            }
         })
     }
+}
+
+async fn open(address: SocketAddr) -> Result<TcpStream> {
+    let socket = if address.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    Ok(socket.connect(address).await?)
+}
+
+fn is_redirect(reason_code: ReasonCode) -> bool {
+    matches!(
+        reason_code,
+        ReasonCode::UseAnotherServer | ReasonCode::ServerMoved
+    )
+}
+
+/// Splits a server reference, a space separated list of `host[:port]` entries with IPv6 hosts
+/// in brackets, into hosts and ports, the port defaults to 1883. Entries with an invalid port
+/// are skipped.
+fn server_references(reference: &str) -> Vec<(&str, u16)> {
+    reference
+        .split_whitespace()
+        .filter_map(|server| match server.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                match port.parse() {
+                    Ok(port) => Some((host.trim_matches(['[', ']']), port)),
+                    Err(err) => {
+                        warn!("server reference {}: {}", server, err);
+                        None
+                    }
+                }
+            }
+            _ => Some((server.trim_matches(['[', ']']), 1883)),
+        })
+        .collect()
+}
 
-    pub async fn recv(&mut self) -> Result<ControlPacket, Error> {
-        self.client.recv().await
+/// First address of the referenced servers which resolves.
+async fn resolve(reference: &MqttString) -> Result<SocketAddr> {
+    let reference = std::str::from_utf8(reference)?;
+    for (host, port) in server_references(reference) {
+        match lookup_host((host, port)).await {
+            Ok(mut addresses) => {
+                if let Some(address) = addresses.next() {
+                    return Ok(address);
+                }
+            }
+            Err(err) => warn!("resolve server reference {}: {}", host, err),
+        }
+    }
+    bail!("no referenced server resolved: {}", reference)
+}
+
+/// Packs the topic filters into SUBSCRIBE packets no larger than `maximum_packet_size`, a
+/// filter too large on its own still gets a packet.
+fn split_subscribe<I>(
+    topic_filters: I,
+    maximum_packet_size: usize,
+) -> Vec<Result<Subscribe, MqttString>>
+where
+    I: IntoIterator<Item = (MqttString, SubscriptionOptions)>,
+{
+    // the encoded size is tracked as filters are added, instead of encoding each candidate
+    let packet_size = |remaining_length: usize| 1 + remaining_length.size() + remaining_length;
+    let mut packets = vec![];
+    let mut subscribe = Subscribe {
+        packet_identifier: 0,
+        properties: SubscribeProperties::default(),
+        topic_filters: vec![],
+    };
+    let empty_length = subscribe.remaining_length();
+    let mut remaining_length = empty_length;
+    for topic_filter in topic_filters {
+        let filter_length = 2 + topic_filter.0.len() + 1;
+        if packet_size(remaining_length + filter_length) > maximum_packet_size {
+            if !subscribe.topic_filters.is_empty() {
+                let topic_filters = std::mem::take(&mut subscribe.topic_filters);
+                packets.push(Ok(Subscribe {
+                    topic_filters,
+                    ..subscribe.clone()
+                }));
+            }
+            remaining_length = empty_length;
+            if packet_size(remaining_length + filter_length) > maximum_packet_size {
+                // too large even alone
+                packets.push(Err(topic_filter.0));
+                continue;
+            }
+        }
+        remaining_length += filter_length;
+        subscribe.topic_filters.push(topic_filter);
+    }
+    if !subscribe.topic_filters.is_empty() {
+        packets.push(Ok(subscribe));
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::*;
+    use test_case::test_case;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::v5::property::ConnAckProperties;
+    use crate::v5::types::PublishResponse;
+
+    /// Broker side of a client connection.
+    struct Broker(Framed<TcpStream, MqttCodec>);
+
+    impl Broker {
+        /// Accepts a client connection and its CONNECT.
+        async fn accept(listener: &TcpListener) -> Self {
+            Self::accept_with(listener, Default::default()).await.0
+        }
+
+        /// Accepts a client connection, answering its CONNECT with `properties`.
+        async fn accept_with(
+            listener: &TcpListener,
+            properties: ConnAckProperties,
+        ) -> (Self, Connect) {
+            let (stream, _) = assert_ok!(listener.accept().await);
+            let mut broker = Broker(Framed::new(stream, MqttCodec::new(None)));
+            let ControlPacket::Connect(connect) = broker.recv().await else {
+                panic!("CONNECT expected");
+            };
+            broker
+                .send(ControlPacket::ConnAck(ConnAck {
+                    session_present: false,
+                    reason_code: ReasonCode::Success,
+                    properties,
+                }))
+                .await;
+            (broker, connect)
+        }
+
+        /// Accepts a client connection and refuses its CONNECT with `reason_code`, referencing
+        /// `server`.
+        async fn redirect(listener: &TcpListener, reason_code: ReasonCode, server: SocketAddr) {
+            let (stream, _) = assert_ok!(listener.accept().await);
+            let mut broker = Broker(Framed::new(stream, MqttCodec::new(None)));
+            assert_matches!(broker.recv().await, ControlPacket::Connect(_));
+            broker
+                .send(ControlPacket::ConnAck(ConnAck {
+                    session_present: false,
+                    reason_code,
+                    properties: ConnAckProperties {
+                        server_reference: Some(MqttString::from(server.to_string())),
+                        ..Default::default()
+                    },
+                }))
+                .await;
+        }
+
+        async fn recv(&mut self) -> ControlPacket {
+            assert_ok!(assert_some!(self.0.next().await))
+        }
+
+        async fn send(&mut self, packet: ControlPacket) {
+            assert_ok!(self.0.send(packet).await)
+        }
+
+        /// Receives a SUBSCRIBE and grants it.
+        async fn subscribe(&mut self) -> Subscribe {
+            let ControlPacket::Subscribe(subscribe) = self.recv().await else {
+                panic!("SUBSCRIBE expected");
+            };
+            self.send(ControlPacket::SubAck(SubAck {
+                packet_identifier: subscribe.packet_identifier,
+                properties: Default::default(),
+                reason_codes: vec![ReasonCode::GrantedQoS1; subscribe.topic_filters.len()],
+            }))
+            .await;
+            subscribe
+        }
+
+        /// Receives a QoS 1 PUBLISH and acknowledges it.
+        async fn publish(&mut self) -> Publish {
+            let ControlPacket::Publish(publish) = self.recv().await else {
+                panic!("PUBLISH expected");
+            };
+            self.puback(assert_some!(publish.packet_identifier)).await;
+            publish
+        }
+
+        async fn puback(&mut self, packet_identifier: u16) {
+            self.send(ControlPacket::PubAck(PublishResponse {
+                packet_identifier,
+                reason_code: ReasonCode::Success,
+                properties: Default::default(),
+            }))
+            .await
+        }
+
+        /// Sends the QoS 1 reply to `request`, with the correlation data of another request
+        /// when `correlation_data` is set.
+        async fn reply(
+            &mut self,
+            request: &Publish,
+            packet_identifier: u16,
+            correlation_data: Option<Bytes>,
+        ) {
+            let mut properties = PublishPropertiesBuilder::default();
+            if let Some(correlation_data) = correlation_data
+                .clone()
+                .or_else(|| request.properties.correlation_data.clone())
+            {
+                properties = assert_ok!(properties.correlation_data(correlation_data));
+            }
+            self.send(ControlPacket::Publish(Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                topic_name: assert_some!(request.properties.response_topic.clone()),
+                packet_identifier: Some(packet_identifier),
+                properties: properties.build(),
+                payload: Bytes::from_static(b"pong"),
+            }))
+            .await
+        }
+    }
+
+    async fn listen() -> (TcpListener, SocketAddr) {
+        let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+        let address = assert_ok!(listener.local_addr());
+        (listener, address)
+    }
+
+    #[test_case("broker" => vec![("broker", 1883)])]
+    #[test_case("a:1884 b" => vec![("a", 1884), ("b", 1883)])]
+    #[test_case("[::1]:1884 [::2] ::3" => vec![("::1", 1884), ("::2", 1883), ("::3", 1883)])]
+    #[test_case("a:port b:1884" => vec![("b", 1884)])]
+    #[test_case("a:65536 [::1]:" => Vec::<(&str, u16)>::new())]
+    fn test_server_references(reference: &str) -> Vec<(&str, u16)> {
+        server_references(reference)
+    }
+
+    #[test]
+    fn test_split_subscribe() {
+        let options = SubscriptionOptions {
+            qos: QoS::AtLeastOnce,
+            nl: false,
+            rap: false,
+            retain: Retain::SendAtTime,
+        };
+        let topic_filters: Vec<_> = (0..10)
+            .map(|i| (MqttString::from(format!("topic/{}", i)), options.clone()))
+            .collect();
+        // fixed header 2, packet identifier 2, properties 1, 10 bytes per filter
+        let packets: Vec<_> = split_subscribe(topic_filters.clone(), 35)
+            .into_iter()
+            .map(|p| assert_ok!(p))
+            .collect();
+        assert_eq!(
+            vec![3, 3, 3, 1],
+            packets
+                .iter()
+                .map(|p| p.topic_filters.len())
+                .collect::<Vec<_>>()
+        );
+        assert!(packets
+            .iter()
+            .all(|p| ControlPacket::Subscribe(p.clone()).encoded_len() <= 35));
+        assert_eq!(1, split_subscribe(topic_filters, usize::MAX).len());
+    }
+
+    #[test_case(129; "remaining length 127")]
+    #[test_case(130; "one byte short of the two byte remaining length")]
+    #[test_case(131; "remaining length 128")]
+    fn test_split_subscribe_remaining_length_boundary(maximum_packet_size: usize) {
+        let options = SubscriptionOptions {
+            qos: QoS::AtLeastOnce,
+            nl: false,
+            rap: false,
+            retain: Retain::SendAtTime,
+        };
+        // 10 bytes per filter, 12 filters give a remaining length of 123
+        let mut topic_filters: Vec<_> = (0..12)
+            .map(|i| (MqttString::from(format!("topic/{:x}", i)), options.clone()))
+            .collect();
+        // 5 more bytes give 128, which needs two bytes
+        topic_filters.push((MqttString::from("tt"), options.clone()));
+        topic_filters.push((MqttString::from("topic/c"), options));
+        let packets: Vec<_> = split_subscribe(topic_filters.clone(), maximum_packet_size)
+            .into_iter()
+            .map(|p| assert_ok!(p))
+            .collect();
+        for (i, packet) in packets.iter().enumerate() {
+            let size = ControlPacket::Subscribe(packet.clone()).encoded_len();
+            assert!(
+                size <= maximum_packet_size,
+                "{} > {}",
+                size,
+                maximum_packet_size
+            );
+            // each packet is filled up before the next one is started
+            if let Some(next) = packets.get(i + 1) {
+                let mut filled = packet.clone();
+                filled.topic_filters.push(next.topic_filters[0].clone());
+                assert!(ControlPacket::Subscribe(filled).encoded_len() > maximum_packet_size);
+            }
+        }
+        assert_eq!(
+            topic_filters,
+            packets
+                .into_iter()
+                .flat_map(|p| p.topic_filters)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_publish_while_receiving() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            let subscribe = broker.subscribe().await;
+            let publish = broker.publish().await;
+            assert_eq!(MqttString::from("b"), publish.topic_name);
+            let mut properties = PublishProperties::default();
+            properties
+                .subscription_identifiers
+                .extend(subscribe.properties.subscription_identifier);
+            broker
+                .send(ControlPacket::Publish(Publish {
+                    dup: false,
+                    qos: QoS::AtMostOnce,
+                    retain: false,
+                    topic_name: MqttString::from("a"),
+                    packet_identifier: None,
+                    properties,
+                    payload: Bytes::from_static(b"received"),
+                }))
+                .await;
+            broker
+        });
+        let client = assert_ok!(MQTTOptions::new(address).connect().await);
+        let (handle, mut driver) = client.into_handle();
+        let driver = tokio::spawn(async move { driver.run().await });
+        let mut messages = assert_ok!(handle.subscribe(QoS::AtMostOnce, "a").await);
+        let receiver = tokio::spawn(async move { messages.recv().await });
+        // published while another task waits for a message
+        let message = Message::new("b", "published").qos(QoS::AtLeastOnce);
+        assert_eq!(
+            Delivery::Sent(ReasonCode::Success),
+            assert_ok!(handle.publish(message).await)
+        );
+        let received = assert_some!(assert_ok!(receiver.await));
+        assert_eq!(Bytes::from_static(b"received"), received.payload);
+        drop(handle);
+        assert_ok!(assert_ok!(driver.await));
+        drop(assert_ok!(broker.await));
+    }
+
+    #[tokio::test]
+    async fn test_handle_publish_discarded_on_new_session() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            // the connection is lost before the PUBACK
+            assert_matches!(broker.recv().await, ControlPacket::Publish(_));
+            drop(broker);
+            // the session is not resumed on the new connection
+            Broker::accept(&listener).await
+        });
+        let client = assert_ok!(MQTTOptions::new(address).connect().await);
+        let (handle, mut driver) = client.into_handle();
+        let publisher = tokio::spawn(async move {
+            handle
+                .publish(Message::new("topic", "lost").qos(QoS::AtLeastOnce))
+                .await
+        });
+        assert_err!(driver.run().await);
+        assert!(!assert_ok!(driver.client().reconnect().await).session_present);
+        let driver = tokio::spawn(async move { driver.run().await });
+        let err = assert_err!(assert_ok!(publisher.await));
+        assert!(err.to_string().starts_with("session not resumed"));
+        assert_ok!(assert_ok!(driver.await));
+        drop(assert_ok!(broker.await));
+    }
+
+    #[tokio::test]
+    async fn test_will_unsupported_after_connack() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let properties = ConnAckProperties {
+                maximum_qos: Some(QoS::AtMostOnce),
+                ..Default::default()
+            };
+            let (mut broker, _) = Broker::accept_with(&listener, properties).await;
+            // disconnected without publishing the will
+            assert_matches!(
+                broker.recv().await,
+                ControlPacket::Disconnect(disconnect) if disconnect.reason_code == ReasonCode::Success
+            );
+        });
+        let will = assert_ok!(Will::builder("will", "gone").qos(QoS::AtLeastOnce).build());
+        let Err(err) = MQTTOptions::new(address).will(will).connect().await else {
+            panic!("connected with an unsupported will");
+        };
+        assert_eq!(
+            Some(&WillUnsupported::QoS {
+                qos: QoS::AtLeastOnce,
+                maximum_qos: QoS::AtMostOnce
+            }),
+            err.downcast_ref::<WillUnsupported>()
+        );
+        assert_ok!(broker.await);
+    }
+
+    #[tokio::test]
+    async fn test_presence_birth_on_reconnect() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            for _ in 0..2 {
+                let mut broker = Broker::accept(&listener).await;
+                let birth = broker.publish().await;
+                assert_eq!(Bytes::from_static(b"online"), birth.payload);
+                assert!(birth.retain);
+                // the connection is closed after the birth message
+            }
+        });
+        let presence = Presence::new("presence/client", "online", "offline");
+        let mut client = assert_ok!(MQTTOptions::new(address).presence(presence).connect().await);
+        assert_matches!(client.recv().await, Ok(Event::Published { .. }));
+        assert_ok!(client.reconnect().await);
+        assert_matches!(client.recv().await, Ok(Event::Published { .. }));
+        assert_ok!(broker.await);
+    }
+
+    #[tokio::test]
+    async fn test_presence_death_before_disconnect() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let (mut broker, connect) = Broker::accept_with(&listener, Default::default()).await;
+            // the will delay needs a session which outlives the connection
+            assert_eq!(Some(30), connect.properties.session_expire_interval);
+            broker.publish().await;
+            let death = broker.publish().await;
+            assert_eq!(Bytes::from_static(b"offline"), death.payload);
+            assert!(death.retain);
+            assert_matches!(
+                broker.recv().await,
+                ControlPacket::Disconnect(disconnect) if disconnect.reason_code == ReasonCode::Success
+            );
+        });
+        let store = Arc::new(MemoryStore::default());
+        let presence =
+            Presence::new("presence/client", "online", "offline").will_delay_interval(30);
+        let mut client = assert_ok!(
+            MQTTOptions::new(address)
+                .presence(presence)
+                .session_expire_interval(30)
+                .session_store(store.clone())
+                .connect()
+                .await
+        );
+        assert_ok!(client.disconnect().await);
+        assert_ok!(broker.await);
+        // the death message was acknowledged before the DISCONNECT
+        assert!(assert_ok!(store.load()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_will_delay_without_session_expiry() {
+        let (_listener, address) = listen().await;
+        let presence =
+            Presence::new("presence/client", "online", "offline").will_delay_interval(30);
+        // neither an unset nor a zero session expiry interval is lengthened to the will delay
+        let connected = MQTTOptions::new(address)
+            .presence(presence.clone())
+            .connect()
+            .await;
+        assert!(connected.is_err());
+        let connected = MQTTOptions::new(address)
+            .presence(presence)
+            .session_expire_interval(0)
+            .connect()
+            .await;
+        assert!(connected.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_session_expiry_override() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            for expected in [Some(0), Some(60)] {
+                let mut broker = Broker::accept(&listener).await;
+                let ControlPacket::Disconnect(disconnect) = broker.recv().await else {
+                    panic!("DISCONNECT expected");
+                };
+                assert_eq!(expected, disconnect.properties.session_expire_interval);
+                assert_eq!(
+                    Some(MqttString::from("bye")),
+                    disconnect.properties.reason_string
+                );
+            }
+        });
+        let properties = |session_expire_interval| DisconnectProperties {
+            session_expire_interval: Some(session_expire_interval),
+            reason_string: Some(MqttString::from("bye")),
+            ..Default::default()
+        };
+
+        // connected without session expiry
+        let mut client = assert_ok!(MQTTOptions::new(address).connect().await);
+        assert_err!(
+            client
+                .disconnect_with(ReasonCode::Success, properties(60))
+                .await
+        );
+        assert_ok!(
+            client
+                .disconnect_with(ReasonCode::Success, properties(0))
+                .await
+        );
+
+        let mut client = assert_ok!(
+            MQTTOptions::new(address)
+                .session_expire_interval(10)
+                .connect()
+                .await
+        );
+        assert_ok!(
+            client
+                .disconnect_with(ReasonCode::Success, properties(60))
+                .await
+        );
+        assert_ok!(broker.await);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_with_will() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            broker.publish().await;
+            // the server publishes the death message as the will
+            assert_matches!(
+                broker.recv().await,
+                ControlPacket::Disconnect(disconnect)
+                    if disconnect.reason_code == ReasonCode::DisconnectWithWill
+            );
+        });
+        let presence = Presence::new("presence/client", "online", "offline");
+        let mut client = assert_ok!(MQTTOptions::new(address).presence(presence).connect().await);
+        assert_ok!(
+            client
+                .disconnect_with_reason(ReasonCode::DisconnectWithWill)
+                .await
+        );
+        assert_ok!(broker.await);
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_timeout() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            // the PINGREQ is not answered
+            assert_matches!(broker.recv().await, ControlPacket::PingReq);
+            // the client closes the connection
+            assert_none!(broker.0.next().await);
+        });
+        let mut client = assert_ok!(MQTTOptions::new(address).keep_alive(1).connect().await);
+        let mut events = Box::pin(client.lifecycle());
+        let err = assert_err!(client.recv().await);
+        assert_eq!("keep alive timeout", err.to_string());
+        assert!(!client.is_connected());
+        assert_eq!(ConnectionState::Closed, *client.state().borrow());
+        assert_matches!(events.next().await, Some(LifecycleEvent::PingTimeout));
+        assert_matches!(
+            events.next().await,
+            Some(LifecycleEvent::Disconnected(DisconnectCause::Lost(_)))
+        );
+        assert_ok!(broker.await);
+    }
+
+    #[tokio::test]
+    async fn test_server_disconnect() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            broker
+                .send(ControlPacket::Disconnect(Disconnect {
+                    reason_code: ReasonCode::UseAnotherServer,
+                    properties: DisconnectProperties {
+                        reason_string: Some(MqttString::from("maintenance")),
+                        server_reference: Some(MqttString::from("other:1884")),
+                        ..Default::default()
+                    },
+                }))
+                .await;
+            broker
+        });
+        let mut client = assert_ok!(MQTTOptions::new(address).connect().await);
+        let err = assert_err!(client.recv().await);
+        assert_eq!(
+            Some(&ServerDisconnect {
+                reason_code: ReasonCode::UseAnotherServer,
+                reason_string: Some(MqttString::from("maintenance")),
+                server_reference: Some(MqttString::from("other:1884")),
+                user_properties: vec![],
+            }),
+            err.downcast_ref::<ServerDisconnect>()
+        );
+        assert!(!client.is_connected());
+        assert_eq!(ConnectionState::Closed, *client.state().borrow());
+        drop(assert_ok!(broker.await));
+    }
+
+    #[tokio::test]
+    async fn test_connack_use_another_server() {
+        let (listener, address) = listen().await;
+        let (other, other_address) = listen().await;
+        let broker = tokio::spawn(async move {
+            Broker::redirect(&listener, ReasonCode::UseAnotherServer, other_address).await;
+            drop(Broker::accept(&other).await);
+            // the reference applied to the redirected connection only
+            Broker::accept(&listener).await
+        });
+        let mut client = assert_ok!(MQTTOptions::new(address).redirect(1).connect().await);
+        assert_err!(client.recv().await);
+        assert_ok!(client.reconnect().await);
+        drop(assert_ok!(broker.await));
+    }
+
+    #[tokio::test]
+    async fn test_connack_server_moved() {
+        let (listener, address) = listen().await;
+        let (other, other_address) = listen().await;
+        let broker = tokio::spawn(async move {
+            Broker::redirect(&listener, ReasonCode::ServerMoved, other_address).await;
+            drop(Broker::accept(&other).await);
+            // the moved server replaces the configured one
+            Broker::accept(&other).await
+        });
+        let mut client = assert_ok!(MQTTOptions::new(address).redirect(1).connect().await);
+        assert_err!(client.recv().await);
+        assert_ok!(client.reconnect().await);
+        drop(assert_ok!(broker.await));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_redirect() {
+        let (listener, address) = listen().await;
+        let (other, other_address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            broker
+                .send(ControlPacket::Disconnect(Disconnect {
+                    reason_code: ReasonCode::UseAnotherServer,
+                    properties: DisconnectProperties {
+                        server_reference: Some(MqttString::from(other_address.to_string())),
+                        ..Default::default()
+                    },
+                }))
+                .await;
+            Broker::accept(&other).await
+        });
+        let mut client = assert_ok!(MQTTOptions::new(address).redirect(1).connect().await);
+        let err = assert_err!(client.recv().await);
+        assert_some!(err.downcast_ref::<ServerDisconnect>());
+        assert_ok!(client.reconnect().await);
+        drop(assert_ok!(broker.await));
+    }
+
+    #[tokio::test]
+    async fn test_max_redirects() {
+        let (listener, address) = listen().await;
+        let (other, other_address) = listen().await;
+        let broker = tokio::spawn(async move {
+            // not followed by default
+            Broker::redirect(&listener, ReasonCode::UseAnotherServer, other_address).await;
+            // followed once, the second reference is not
+            Broker::redirect(&listener, ReasonCode::UseAnotherServer, other_address).await;
+            Broker::redirect(&other, ReasonCode::UseAnotherServer, address).await;
+            assert_err!(timeout(Duration::from_millis(100), listener.accept()).await);
+        });
+        assert!(MQTTOptions::new(address).connect().await.is_err());
+        assert!(MQTTOptions::new(address)
+            .redirect(1)
+            .connect()
+            .await
+            .is_err());
+        assert_ok!(broker.await);
+    }
+
+    #[test]
+    fn test_options_properties() {
+        let options = MQTTOptions::new(SocketAddr::from(([127, 0, 0, 1], 1883)))
+            .client_id(String::from("client"))
+            .username(String::from("user"))
+            .password(vec![1, 2])
+            .session_expire_interval(10)
+            .session_expire_interval(20);
+        assert_eq!(Some(MqttString::from("client")), options.client_id);
+        assert_eq!(Some(MqttString::from("user")), options.username);
+        assert_eq!(Some(Bytes::from_static(&[1, 2])), options.password);
+        // a plain setter overwrites, its try_ variant rejects the duplicate
+        assert_eq!(Some(20), options.properties.session_expire_interval);
+        assert!(matches!(
+            options.try_session_expire_interval(30),
+            Err(MqttError::MoreThanOnceProperty)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_publisher_properties() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move { Broker::accept(&listener).await });
+        let client = assert_ok!(MQTTOptions::new(address).connect().await);
+        let _broker = assert_ok!(broker.await);
+        let publisher = client
+            .publisher()
+            .content_type(String::from("text/plain"))
+            .response_topic(MqttString::from("reply"))
+            .correlation_data(vec![1])
+            .correlation_data(Bytes::from_static(b"id"));
+        assert_eq!(
+            Some(MqttString::from("text/plain")),
+            publisher.properties.content_type
+        );
+        assert_eq!(
+            Some(MqttString::from("reply")),
+            publisher.properties.response_topic
+        );
+        assert_eq!(
+            Some(Bytes::from_static(b"id")),
+            publisher.properties.correlation_data
+        );
+        assert!(matches!(
+            publisher.try_content_type(String::from("text/html")),
+            Err(MqttError::MoreThanOnceProperty)
+        ));
+    }
+
+    #[test]
+    fn test_split_subscribe_too_large() {
+        let options = SubscriptionOptions {
+            qos: QoS::AtLeastOnce,
+            nl: false,
+            rap: false,
+            retain: Retain::SendAtTime,
+        };
+        let topic_filters = vec![
+            (MqttString::from("topic/0"), options.clone()),
+            (MqttString::from("topic/".repeat(10)), options.clone()),
+            (MqttString::from("topic/2"), options),
+        ];
+        let packets = split_subscribe(topic_filters, 35);
+        assert_eq!(3, packets.len());
+        assert_eq!(1, assert_ok!(&packets[0]).topic_filters.len());
+        assert_eq!(
+            &MqttString::from("topic/".repeat(10)),
+            assert_err!(&packets[1])
+        );
+        assert_eq!(1, assert_ok!(&packets[2]).topic_filters.len());
+    }
+
+    #[tokio::test]
+    async fn test_request_correlation() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            let subscribe = broker.subscribe().await;
+            assert_eq!(
+                MqttString::from("reply/client"),
+                subscribe.topic_filters[0].0
+            );
+            let request = broker.publish().await;
+            // a reply to another request is acknowledged and skipped
+            broker
+                .reply(&request, 1, Some(Bytes::from_static(b"other")))
+                .await;
+            broker.reply(&request, 2, None).await;
+            for packet_identifier in [1, 2] {
+                assert_matches!(
+                    broker.recv().await,
+                    ControlPacket::PubAck(ack) if ack.packet_identifier == packet_identifier
+                );
+            }
+        });
+        let mut client = assert_ok!(
+            MQTTOptions::new(address)
+                .client_id("client")
+                .manual_ack(true)
+                .connect()
+                .await
+        );
+        let reply = assert_ok!(
+            client
+                .request("service", "ping", Duration::from_secs(5))
+                .await
+        );
+        assert_eq!(Bytes::from_static(b"pong"), reply.payload);
+        assert_ok!(client.ack(assert_some!(reply.ack)).await);
+        assert_ok!(broker.await);
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_late_reply() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            broker.subscribe().await;
+            let first = broker.publish().await;
+            let second = broker.publish().await;
+            broker.reply(&first, 1, None).await;
+            broker.reply(&second, 2, None).await;
+            for packet_identifier in [1, 2] {
+                assert_matches!(
+                    broker.recv().await,
+                    ControlPacket::PubAck(ack) if ack.packet_identifier == packet_identifier
+                );
+            }
+        });
+        let mut client = assert_ok!(
+            MQTTOptions::new(address)
+                .client_id("client")
+                .manual_ack(true)
+                .connect()
+                .await
+        );
+        let err = assert_err!(
+            client
+                .request("service", "first", Duration::from_millis(100))
+                .await
+        );
+        assert!(err.to_string().starts_with("request timeout"));
+        // the late reply to the first request is acknowledged while waiting for the second
+        let reply = assert_ok!(
+            client
+                .request("service", "second", Duration::from_secs(5))
+                .await
+        );
+        assert_eq!(
+            Some(Bytes::copy_from_slice(&2u64.to_be_bytes())),
+            reply.properties.correlation_data
+        );
+        assert_ok!(client.ack(assert_some!(reply.ack)).await);
+        assert_ok!(broker.await);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_ack_token_nacks() {
+        let (listener, address) = listen().await;
+        let broker = tokio::spawn(async move {
+            let mut broker = Broker::accept(&listener).await;
+            broker.subscribe().await;
+            let first = broker.publish().await;
+            broker.reply(&first, 1, None).await;
+            let mut acks = vec![];
+            while acks.len() < 2 {
+                match broker.recv().await {
+                    ControlPacket::Publish(second) => {
+                        broker.puback(assert_some!(second.packet_identifier)).await;
+                        broker.reply(&second, 2, None).await;
+                    }
+                    ControlPacket::PubAck(ack) => {
+                        acks.push((ack.packet_identifier, ack.reason_code))
+                    }
+                    packet => panic!("unexpected: {}", packet),
+                }
+            }
+            assert_eq!(
+                vec![(1, ReasonCode::UnspecifiedError), (2, ReasonCode::Success)],
+                acks
+            );
+        });
+        let mut client = assert_ok!(
+            MQTTOptions::new(address)
+                .client_id("client")
+                .manual_ack(true)
+                .connect()
+                .await
+        );
+        let reply = assert_ok!(
+            client
+                .request("service", "first", Duration::from_secs(5))
+                .await
+        );
+        // a handler failing before the ack drops the token, the message is not acknowledged
+        // as processed
+        drop(reply);
+        let reply = assert_ok!(
+            client
+                .request("service", "second", Duration::from_secs(5))
+                .await
+        );
+        assert_ok!(client.ack(assert_some!(reply.ack)).await);
+        assert_ok!(broker.await);
     }
 }
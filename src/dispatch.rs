@@ -0,0 +1,140 @@
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::warn;
+
+use crate::message::Message;
+use crate::v5::string::MqttString;
+use crate::v5::topic;
+
+/// Messages buffered for a subscription which does not keep up, newer ones are not delivered
+/// to it.
+pub(crate) const SUBSCRIPTION_CAPACITY: usize = 64;
+
+/// Received messages no subscription took, kept for a subscription made later. The oldest is
+/// dropped when full.
+pub(crate) const PENDING_CAPACITY: usize = 256;
+
+#[derive(Debug)]
+struct Route {
+    topic_filter: MqttString,
+    identifier: Option<u32>,
+    messages: mpsc::Sender<Message>,
+}
+
+impl Route {
+    fn matches(&self, message: &Message) -> bool {
+        match self.identifier {
+            Some(identifier) => message
+                .properties
+                .subscription_identifiers
+                .contains(&identifier),
+            None => topic::matches(&self.topic_filter, &message.topic),
+        }
+    }
+}
+
+/// Routes received messages to the subscriptions reading them, each with its own bounded
+/// channel.
+///
+/// A message is routed by the subscription identifiers it carries, or by matching the topic
+/// filter of a subscription without identifier. A subscription is forgotten once its receiver
+/// is dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Dispatcher {
+    routes: Vec<Route>,
+}
+
+impl Dispatcher {
+    /// Adds a subscription, returning the receiver of its messages.
+    pub(crate) fn route(
+        &mut self,
+        topic_filter: MqttString,
+        identifier: Option<u32>,
+        capacity: usize,
+    ) -> mpsc::Receiver<Message> {
+        let (messages, receiver) = mpsc::channel(capacity.max(1));
+        self.routes.push(Route {
+            topic_filter,
+            identifier,
+            messages,
+        });
+        receiver
+    }
+
+    /// Sends `message` to every subscription it was delivered for, only the first one gets its
+    /// ack token. The message is handed back when no subscription took it.
+    pub(crate) fn dispatch(&mut self, mut message: Message) -> Option<Message> {
+        self.routes.retain(|route| !route.messages.is_closed());
+        let mut ack = message.ack.take();
+        let mut delivered = false;
+        for route in self.routes.iter().filter(|route| route.matches(&message)) {
+            let mut copy = message.clone();
+            copy.ack = ack.take();
+            match route.messages.try_send(copy) {
+                Ok(()) => delivered = true,
+                Err(TrySendError::Full(copy)) => {
+                    ack = copy.ack;
+                    warn!(
+                        "subscription {:?} full, skip {:?}",
+                        route.topic_filter, message.topic
+                    )
+                }
+                Err(TrySendError::Closed(copy)) => ack = copy.ack,
+            }
+        }
+        if delivered {
+            None
+        } else {
+            message.ack = ack;
+            Some(message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use claims::*;
+
+    use super::*;
+    use crate::message::AckToken;
+    use crate::v5::types::QoS;
+
+    fn message(topic: &'static str, subscription_identifiers: Vec<u32>) -> Message {
+        let mut message = Message::new(topic, Bytes::new()).qos(QoS::AtLeastOnce);
+        message.properties.subscription_identifiers = subscription_identifiers;
+        let (dropped, _) = mpsc::unbounded_channel();
+        message.ack = Some(AckToken::new(1, dropped));
+        message
+    }
+
+    #[test]
+    fn test_dispatch_subscription_identifiers() {
+        let mut dispatcher = Dispatcher::default();
+        let mut first = dispatcher.route(MqttString::from("a/+"), Some(1), 2);
+        let mut second = dispatcher.route(MqttString::from("a/b"), Some(2), 2);
+        let mut third = dispatcher.route(MqttString::from("a/#"), None, 2);
+
+        assert_none!(dispatcher.dispatch(message("a/b", vec![1, 2])));
+        let delivered = assert_ok!(first.try_recv());
+        assert_some!(delivered.ack);
+        // the same message for another subscription is acknowledged with the first one
+        assert_none!(assert_ok!(second.try_recv()).ack);
+        assert_none!(assert_ok!(third.try_recv()).ack);
+        assert_err!(first.try_recv());
+    }
+
+    #[test]
+    fn test_dispatch_unrouted() {
+        let mut dispatcher = Dispatcher::default();
+        let mut subscription = dispatcher.route(MqttString::from("a"), None, 1);
+        assert_none!(dispatcher.dispatch(message("a", vec![])));
+        // full
+        let rejected = assert_some!(dispatcher.dispatch(message("a", vec![])));
+        assert_some!(rejected.ack);
+        assert_ok!(subscription.try_recv());
+        drop(subscription);
+        assert_some!(dispatcher.dispatch(message("a", vec![])));
+        assert!(dispatcher.routes.is_empty());
+    }
+}
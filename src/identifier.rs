@@ -1,7 +1,8 @@
-use std::borrow::Borrow;
-use std::cmp::Ordering;
-use std::fmt;
-use std::mem;
+use alloc::boxed::Box;
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt;
+use core::mem;
 
 use tracing::trace;
 
@@ -19,6 +20,15 @@ impl PacketIdentifier {
         self.identifier
     }
 
+    /// Marks `id` as used, e.g. for the packet identifiers restored from a session store.
+    pub fn acquire(&mut self, id: u16) {
+        let id = id as usize;
+        let value = &mut self.used[id / (mem::size_of::<usize>() * 8)];
+        let mask: usize = 1 << (id % (mem::size_of::<usize>() * 8));
+        *value |= mask;
+        trace!("acquire PacketIdentifier {} ", id);
+    }
+
     pub fn release(&mut self, id: u16) {
         let id = id as usize;
         let value = &mut self.used[id / (mem::size_of::<usize>() * 8)];
@@ -66,22 +76,28 @@ impl PartialEq for PacketIdentifier {
 impl Iterator for PacketIdentifier {
     type Item = u16;
 
+    /// Returns the next free identifier after the last one, wrapping around and skipping 0,
+    /// or `None` if all identifiers are in use.
     fn next(&mut self) -> Option<Self::Item> {
-        let packet_identifier = self.identifier;
-        let (packet_identifier, _) = packet_identifier.overflowing_add(1);
-
-        let id = packet_identifier as usize;
-        let value = &mut self.used[id / (mem::size_of::<usize>() * 8)];
-        let mask: usize = 1 << (id % (mem::size_of::<usize>() * 8));
-
-        if (*value & mask) != 0 {
-            None
-        } else {
-            *value |= mask;
-
-            self.identifier = packet_identifier;
-            Some(packet_identifier)
+        let mut packet_identifier = self.identifier;
+        for _ in 0..u16::MAX {
+            packet_identifier = match packet_identifier.wrapping_add(1) {
+                0 => 1,
+                id => id,
+            };
+
+            let id = packet_identifier as usize;
+            let value = &mut self.used[id / (mem::size_of::<usize>() * 8)];
+            let mask: usize = 1 << (id % (mem::size_of::<usize>() * 8));
+
+            if (*value & mask) == 0 {
+                *value |= mask;
+
+                self.identifier = packet_identifier;
+                return Some(packet_identifier);
+            }
         }
+        None
     }
 }
 
@@ -110,12 +126,31 @@ mod tests {
             sequence.release(i1);
         }
         assert_eq!(65535, sequence.next().unwrap());
-        assert_eq!(0, sequence.next().unwrap());
         assert_eq!(1, sequence.next().unwrap());
         assert_eq!(2, sequence.next().unwrap());
         sequence.release(65535);
-        sequence.release(0);
         sequence.release(1);
         sequence.release(2);
     }
+
+    #[test]
+    fn test_packet_identifier_skips_used() {
+        let mut sequence: PacketIdentifier = Default::default();
+        sequence.acquire(1);
+        sequence.acquire(2);
+        assert_eq!(Some(3), sequence.next());
+        sequence.acquire(4);
+        assert_eq!(Some(5), sequence.next());
+    }
+
+    #[test]
+    fn test_packet_identifier_exhausted() {
+        let mut sequence: PacketIdentifier = Default::default();
+        for _ in 1..=u16::MAX {
+            assert!(sequence.next().is_some());
+        }
+        assert_eq!(None, sequence.next());
+        sequence.release(7);
+        assert_eq!(Some(7), sequence.next());
+    }
 }
@@ -1,3 +1,22 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod client;
+#[cfg(feature = "std")]
+mod dispatch;
 pub mod identifier;
+#[cfg(feature = "std")]
+pub mod lifecycle;
+#[cfg(feature = "std")]
+pub mod message;
+#[cfg(feature = "std")]
+pub mod offline;
+#[cfg(feature = "std")]
+pub mod presence;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
+pub mod store;
 pub mod v5;
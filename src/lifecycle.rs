@@ -0,0 +1,119 @@
+use std::net::SocketAddr;
+
+use futures::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tracing::warn;
+
+use crate::client::ServerDisconnect;
+use crate::v5::types::{ConnAck, ReasonCode};
+
+/// Events kept for an observer which does not keep up, older ones are skipped.
+const CAPACITY: usize = 16;
+
+/// Connection state of a [`Client`](crate::client::Client).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// First connection in progress
+    Connecting,
+    Connected,
+    /// New connection of a reconnect or redirect in progress
+    Reconnecting,
+    /// No connection until the next reconnect
+    Closed,
+}
+
+/// Why a connection was closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectCause {
+    /// DISCONNECT sent by the client
+    Client(ReasonCode),
+    /// DISCONNECT sent by the server
+    Server(ServerDisconnect),
+    /// CONNACK refusing the connection
+    Refused(ReasonCode),
+    /// Network error, or connection closed without DISCONNECT
+    Lost(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum LifecycleEvent {
+    Connected(ConnAck),
+    Disconnected(DisconnectCause),
+    /// Connection opened to the address by a reconnect or redirect
+    ReconnectAttempt(SocketAddr),
+    /// No PINGRESP within the keep alive after a PINGREQ
+    PingTimeout,
+}
+
+/// Publishes the connection state and lifecycle events of a client to its observers.
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    state: watch::Sender<ConnectionState>,
+    events: broadcast::Sender<LifecycleEvent>,
+}
+
+impl Lifecycle {
+    pub(crate) fn new() -> Self {
+        Lifecycle {
+            state: watch::channel(ConnectionState::Connecting).0,
+            events: broadcast::channel(CAPACITY).0,
+        }
+    }
+
+    pub(crate) fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    pub(crate) fn set_state(&self, state: ConnectionState) {
+        self.state.send_replace(state);
+    }
+
+    pub(crate) fn emit(&self, event: LifecycleEvent) {
+        // nobody observing is not an error
+        let _ = self.events.send(event);
+    }
+
+    pub(crate) fn watch(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Events emitted from now on, the stream ends when the client is dropped.
+    pub(crate) fn events(&self) -> impl Stream<Item = LifecycleEvent> + Send + 'static {
+        stream::unfold(self.events.subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((event, events)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("lifecycle observer lagged, skip {} events", skipped)
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use super::*;
+
+    #[test]
+    fn test_lifecycle() {
+        let lifecycle = Lifecycle::new();
+        let state = lifecycle.watch();
+        let mut events = Box::pin(lifecycle.events());
+        lifecycle.set_state(ConnectionState::Closed);
+        lifecycle.emit(LifecycleEvent::PingTimeout);
+        assert_eq!(ConnectionState::Closed, *state.borrow());
+        assert!(matches!(
+            block_on(events.next()),
+            Some(LifecycleEvent::PingTimeout)
+        ));
+        drop(lifecycle);
+        assert!(block_on(events.next()).is_none());
+    }
+}
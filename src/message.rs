@@ -0,0 +1,159 @@
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use crate::v5::property::PublishProperties;
+use crate::v5::string::MqttString;
+use crate::v5::types::{Publish, QoS};
+
+/// Application message, received on a subscription or built with [`Message::new`] to be
+/// published with its own flags and properties.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Message {
+    pub topic: MqttString,
+    pub payload: Bytes,
+    pub qos: QoS,
+    pub retain: bool,
+    pub properties: PublishProperties,
+    /// Set for QoS 1 and QoS 2 messages received in manual ack mode, a clone of the message
+    /// does not carry it.
+    pub ack: Option<AckToken>,
+}
+
+impl Clone for Message {
+    fn clone(&self) -> Self {
+        Message {
+            topic: self.topic.clone(),
+            payload: self.payload.clone(),
+            qos: self.qos,
+            retain: self.retain,
+            properties: self.properties.clone(),
+            ack: None,
+        }
+    }
+}
+
+/// Pending acknowledgement of a message received in manual ack mode, the PUBACK or PUBREC is
+/// sent when the token is passed to `ack` or `nack`. A token dropped without either, e.g. by a
+/// handler which failed or panicked, nacks the message with `UnspecifiedError` so that it is not
+/// reported as processed and later acknowledgements are not held back by it.
+#[derive(Debug)]
+pub struct AckToken {
+    packet_identifier: u16,
+    dropped: Option<mpsc::UnboundedSender<u16>>,
+}
+
+impl AckToken {
+    pub(crate) fn new(packet_identifier: u16, dropped: mpsc::UnboundedSender<u16>) -> Self {
+        AckToken {
+            packet_identifier,
+            dropped: Some(dropped),
+        }
+    }
+
+    pub fn packet_identifier(&self) -> u16 {
+        self.packet_identifier
+    }
+
+    /// Consumes the token without reporting it as dropped.
+    pub(crate) fn take(mut self) -> u16 {
+        self.dropped = None;
+        self.packet_identifier
+    }
+}
+
+impl PartialEq for AckToken {
+    fn eq(&self, other: &Self) -> bool {
+        self.packet_identifier == other.packet_identifier
+    }
+}
+
+impl Eq for AckToken {}
+
+impl Drop for AckToken {
+    fn drop(&mut self) {
+        if let Some(dropped) = self.dropped.take() {
+            // the client is gone when this fails, and the message with it
+            let _ = dropped.send(self.packet_identifier);
+        }
+    }
+}
+
+impl Message {
+    /// QoS 0 message without properties.
+    pub fn new(topic: impl Into<MqttString>, payload: impl Into<Bytes>) -> Self {
+        Message {
+            topic: topic.into(),
+            payload: payload.into(),
+            qos: QoS::AtMostOnce,
+            retain: false,
+            properties: Default::default(),
+            ack: None,
+        }
+    }
+
+    pub fn qos(mut self, value: QoS) -> Self {
+        self.qos = value;
+        self
+    }
+
+    pub fn retain(mut self, value: bool) -> Self {
+        self.retain = value;
+        self
+    }
+
+    /// Properties of the message, e.g. made with a `PublishPropertiesBuilder`.
+    pub fn properties(mut self, value: PublishProperties) -> Self {
+        self.properties = value;
+        self
+    }
+}
+
+impl From<Message> for Publish {
+    fn from(message: Message) -> Self {
+        Publish {
+            dup: false,
+            qos: message.qos,
+            retain: message.retain,
+            topic_name: message.topic,
+            packet_identifier: None,
+            properties: message.properties,
+            payload: message.payload,
+        }
+    }
+}
+
+impl From<Publish> for Message {
+    fn from(publish: Publish) -> Self {
+        Message {
+            topic: publish.topic_name,
+            payload: publish.payload,
+            qos: publish.qos,
+            retain: publish.retain,
+            properties: publish.properties,
+            ack: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::*;
+
+    use super::*;
+
+    #[test]
+    fn test_ack_token_drop() {
+        let (sender, mut dropped) = mpsc::unbounded_channel();
+        let token = AckToken::new(1, sender.clone());
+        assert_eq!(1, token.take());
+        assert_err!(dropped.try_recv());
+
+        let message = Message {
+            ack: Some(AckToken::new(2, sender)),
+            ..Message::new("topic", Bytes::new())
+        };
+        assert_none!(message.clone().ack);
+        drop(message);
+        assert_eq!(Ok(2), dropped.try_recv());
+    }
+}
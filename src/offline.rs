@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use tracing::{trace, warn};
+
+use crate::v5::types::{ControlPacket, Publish, QoS};
+
+/// What [`OfflineQueue::push`] does when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the oldest queued message to make room for the new one.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+    /// Hand the new message back, the publisher waits for a connection to send it, see
+    /// [`Blocked`].
+    Block,
+}
+
+/// Result of [`OfflineQueue::push`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum Pushed {
+    Queued,
+    Dropped,
+    /// Queue is full and the overflow policy is [`Overflow::Block`].
+    Full(Publish),
+}
+
+/// Error handing back a message published while disconnected with the offline queue full and
+/// [`Overflow::Block`]. A [`ClientHandle`](crate::client::ClientHandle) waits for the
+/// connection instead of failing.
+#[derive(Debug)]
+pub struct Blocked(pub Publish);
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offline queue full, blocked {:?}", self.0.topic_name)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+#[derive(Debug)]
+struct Queued {
+    sequence: u64,
+    enqueued: SystemTime,
+    publish: Publish,
+}
+
+/// Bounded queue of publishes made while the client is disconnected, sent in order on the next
+/// connection. Messages whose message expiry interval elapsed in the queue are dropped and the
+/// remaining interval of the others is reduced by the time they waited.
+#[derive(Debug)]
+pub struct OfflineQueue {
+    capacity: usize,
+    overflow: Overflow,
+    path: Option<PathBuf>,
+    sequence: u64,
+    queue: VecDeque<Queued>,
+}
+
+impl OfflineQueue {
+    /// Queue kept in memory.
+    pub fn new(capacity: usize) -> Self {
+        OfflineQueue {
+            capacity,
+            overflow: Overflow::DropOldest,
+            path: None,
+            sequence: 0,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Queue persisted in directory `path`, one file per message, restoring the messages
+    /// queued there by a previous process.
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+        let mut files = vec![];
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            if let Some(sequence) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u64>().ok())
+            {
+                files.push((sequence, entry.path()));
+            }
+        }
+        files.sort();
+        let mut queue = VecDeque::with_capacity(files.len());
+        for (sequence, file) in files {
+            let mut buf = &fs::read(&file)?[..];
+            if buf.remaining() < 9 {
+                return Err(anyhow!("malformed queued message: {}", file.display()));
+            }
+            let enqueued = UNIX_EPOCH + Duration::from_millis(buf.get_u64());
+            let qos = QoS::try_from(buf.get_u8())?;
+            match ControlPacket::from_bytes(buf)? {
+                ControlPacket::Publish(mut publish) => {
+                    publish.qos = qos;
+                    queue.push_back(Queued {
+                        sequence,
+                        enqueued,
+                        publish,
+                    })
+                }
+                packet => return Err(anyhow!("unexpected queued packet: {}", packet)),
+            }
+        }
+        Ok(OfflineQueue {
+            capacity,
+            overflow: Overflow::DropOldest,
+            path: Some(path),
+            sequence: queue.back().map_or(0, |queued| queued.sequence + 1),
+            queue,
+        })
+    }
+
+    pub fn overflow(mut self, value: Overflow) -> Self {
+        self.overflow = value;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn push(&mut self, publish: Publish) -> Result<Pushed> {
+        if self.queue.len() >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => {
+                    if let Some(queued) = self.queue.pop_front() {
+                        warn!("offline queue full, drop {:?}", queued.publish.topic_name);
+                        self.remove(&queued)?;
+                    }
+                }
+                Overflow::DropNewest => {
+                    warn!("offline queue full, drop {:?}", publish.topic_name);
+                    return Ok(Pushed::Dropped);
+                }
+                Overflow::Block => return Ok(Pushed::Full(publish)),
+            }
+            if self.capacity == 0 {
+                return Ok(Pushed::Dropped);
+            }
+        }
+        let queued = Queued {
+            sequence: self.sequence,
+            enqueued: SystemTime::now(),
+            publish,
+        };
+        self.sequence += 1;
+        if let Some(path) = &self.path {
+            // the packet identifier is assigned on sending, so the QoS is kept beside a QoS 0
+            // packet which needs none
+            let mut publish = queued.publish.clone();
+            let qos = publish.qos;
+            publish.qos = QoS::AtMostOnce;
+            let packet = ControlPacket::Publish(publish);
+            let mut buf = BytesMut::with_capacity(9 + packet.encoded_len());
+            let millis = queued.enqueued.duration_since(UNIX_EPOCH)?.as_millis();
+            buf.put_u64(millis as u64);
+            buf.put_u8(qos.into());
+            buf.extend_from_slice(&packet.to_bytes()?);
+            fs::write(path.join(queued.sequence.to_string()), buf)?;
+        }
+        self.queue.push_back(queued);
+        Ok(Pushed::Queued)
+    }
+
+    /// Next message still within its message expiry interval, with the interval reduced by the
+    /// time it spent in the queue. The message stays queued until [`OfflineQueue::pop`].
+    pub fn front(&mut self) -> Result<Option<Publish>> {
+        while let Some(queued) = self.queue.front() {
+            let mut publish = queued.publish.clone();
+            if let Some(expire) = publish.properties.message_expire_interval {
+                let waited = queued.enqueued.elapsed().unwrap_or_default().as_secs();
+                if waited >= expire as u64 {
+                    trace!("offline message expired {:?}", publish.topic_name);
+                    self.remove_front()?;
+                    continue;
+                }
+                publish.properties.message_expire_interval = Some(expire - waited as u32);
+            }
+            return Ok(Some(publish));
+        }
+        Ok(None)
+    }
+
+    /// Removes and returns the message [`OfflineQueue::front`] returns.
+    pub fn pop(&mut self) -> Result<Option<Publish>> {
+        let publish = self.front()?;
+        if publish.is_some() {
+            self.remove_front()?;
+        }
+        Ok(publish)
+    }
+
+    fn remove_front(&mut self) -> Result<()> {
+        match self.queue.pop_front() {
+            Some(queued) => self.remove(&queued),
+            None => Ok(()),
+        }
+    }
+
+    fn remove(&self, queued: &Queued) -> Result<()> {
+        if let Some(path) = &self.path {
+            fs::remove_file(path.join(queued.sequence.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use claims::*;
+
+    use super::*;
+    use crate::v5::string::MqttString;
+    use crate::v5::types::QoS;
+
+    fn publish(topic: &'static str, message_expire_interval: Option<u32>) -> Publish {
+        let mut publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic_name: MqttString::from(topic),
+            packet_identifier: None,
+            properties: Default::default(),
+            payload: Bytes::from_static(b"payload"),
+        };
+        publish.properties.message_expire_interval = message_expire_interval;
+        publish
+    }
+
+    #[test]
+    fn test_offline_queue_overflow() {
+        let mut queue = OfflineQueue::new(2);
+        assert_eq!(Pushed::Queued, assert_ok!(queue.push(publish("a", None))));
+        assert_eq!(
+            Pushed::Queued,
+            assert_ok!(queue.push(publish("b", Some(0))))
+        );
+        assert_eq!(
+            Pushed::Queued,
+            assert_ok!(queue.push(publish("c", Some(60))))
+        );
+        // "a" dropped as the oldest, "b" expired in the queue
+        assert_eq!(Some(publish("c", Some(60))), assert_ok!(queue.pop()));
+        assert_none!(assert_ok!(queue.pop()));
+
+        let mut queue = OfflineQueue::new(1).overflow(Overflow::DropNewest);
+        assert_ok!(queue.push(publish("a", None)));
+        assert_eq!(Pushed::Dropped, assert_ok!(queue.push(publish("b", None))));
+        let mut queue = queue.overflow(Overflow::Block);
+        assert_eq!(
+            Pushed::Full(publish("b", None)),
+            assert_ok!(queue.push(publish("b", None)))
+        );
+    }
+
+    #[test]
+    fn test_offline_queue_front() {
+        let mut queue = OfflineQueue::new(2);
+        assert_ok!(queue.push(publish("a", None)));
+        assert_eq!(Some(publish("a", None)), assert_ok!(queue.front()));
+        assert_eq!(1, queue.len());
+        assert_eq!(Some(publish("a", None)), assert_ok!(queue.pop()));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_offline_queue_file() {
+        let path = std::env::temp_dir().join(format!("como-mqtt-queue-{}", std::process::id()));
+        let mut queue = assert_ok!(OfflineQueue::open(&path, 10));
+        assert_ok!(queue.push(publish("a", None)));
+        assert_ok!(queue.push(publish("b", Some(60))));
+
+        let mut queue = assert_ok!(OfflineQueue::open(&path, 10));
+        assert_eq!(2, queue.len());
+        assert_eq!(Some(publish("a", None)), assert_ok!(queue.pop()));
+        assert_eq!(Some(publish("b", Some(60))), assert_ok!(queue.pop()));
+        assert!(assert_ok!(OfflineQueue::open(&path, 10)).is_empty());
+        assert_ok!(fs::remove_dir(&path));
+    }
+}
@@ -0,0 +1,78 @@
+use bytes::Bytes;
+
+use crate::message::Message;
+use crate::v5::property::WillProperties;
+use crate::v5::string::MqttString;
+use crate::v5::types::{QoS, Will};
+
+/// Birth and death messages announcing whether a client is online on a presence topic.
+///
+/// The retained death message is the will of the connection, so the server publishes it when
+/// the connection is lost, and the client publishes it itself before a graceful disconnect,
+/// which discards the will. The retained birth message is published after every successful
+/// connect and reconnect, replacing the death message.
+///
+/// With a will delay interval, a reconnect within the interval cancels the will, so short
+/// outages do not announce the client offline. The server publishes the will no later than
+/// the session expires, so connecting fails unless a non-zero session expiry interval is set.
+#[derive(Debug, Clone)]
+pub struct Presence {
+    topic: MqttString,
+    online: Bytes,
+    offline: Bytes,
+    qos: QoS,
+    will_delay_interval: u32,
+}
+
+impl Presence {
+    /// QoS 1 presence on `topic` without will delay.
+    pub fn new(
+        topic: impl Into<MqttString>,
+        online: impl Into<Bytes>,
+        offline: impl Into<Bytes>,
+    ) -> Self {
+        Presence {
+            topic: topic.into(),
+            online: online.into(),
+            offline: offline.into(),
+            qos: QoS::AtLeastOnce,
+            will_delay_interval: 0,
+        }
+    }
+
+    pub fn qos(mut self, value: QoS) -> Self {
+        self.qos = value;
+        self
+    }
+
+    pub fn will_delay_interval(mut self, value: u32) -> Self {
+        self.will_delay_interval = value;
+        self
+    }
+
+    /// Death message as the will of the connection.
+    pub fn will(&self) -> Will {
+        Will {
+            qos: self.qos,
+            retain: true,
+            properties: WillProperties {
+                will_delay_interval: self.will_delay_interval,
+                ..Default::default()
+            },
+            topic: self.topic.clone(),
+            payload: self.offline.clone(),
+        }
+    }
+
+    pub fn birth(&self) -> Message {
+        Message::new(self.topic.clone(), self.online.clone())
+            .qos(self.qos)
+            .retain(true)
+    }
+
+    pub fn death(&self) -> Message {
+        Message::new(self.topic.clone(), self.offline.clone())
+            .qos(self.qos)
+            .retain(true)
+    }
+}
@@ -0,0 +1,1121 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use tracing::{trace, warn};
+
+use crate::identifier::PacketIdentifier;
+use crate::store::{MemoryStore, SessionStore, Stored};
+use crate::v5::error::MqttError;
+use crate::v5::reason::PubAckReason;
+use crate::v5::types::{
+    Auth, ConnAck, Connect, ControlPacket, Disconnect, Publish, PublishResponse, QoS, ReasonCode,
+    SubAck, Subscribe,
+};
+
+/// Outcome of feeding packets and timer ticks into a [`Session`].
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Connected(ConnAck),
+    /// Inbound application message, already acknowledged by the session.
+    Message(Publish),
+    /// Outgoing QoS 1 or QoS 2 publish completed with the PUBACK, PUBREC or PUBCOMP reason code.
+    Published {
+        packet_identifier: u16,
+        reason_code: ReasonCode,
+    },
+    Subscribed(SubAck),
+    Unsubscribed(SubAck),
+    Disconnected(Disconnect),
+    Auth(Auth),
+    /// No acknowledgement for an outgoing publish after all retries.
+    Timeout {
+        packet_identifier: u16,
+    },
+    /// Outgoing publish discarded with its unknown outcome, as the server did not resume the
+    /// session.
+    Discarded {
+        packet_identifier: u16,
+    },
+    /// No PINGRESP within the keep alive interval.
+    KeepAliveTimeout,
+}
+
+/// When unacknowledged PUBLISH and PUBREL packets are sent again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retransmit {
+    /// Only when the session is resumed on a new connection, as MQTT 5 requires.
+    OnReconnect,
+    /// Also on the live connection after each acknowledgement timeout, as MQTT 3 clients did.
+    OnTimeout,
+}
+
+/// What happens to an outgoing publish which is still not acknowledged after all retries.
+/// Defaults to [`Exhausted::Disconnect`] so that with [`Retransmit::OnReconnect`] the publish
+/// is sent again on the next connection instead of being lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exhausted {
+    /// Report [`Event::Timeout`] and forget the publish. Its packet identifier stays reserved
+    /// until the connection closes, as the server may still answer it [MQTT-2.2.1-3].
+    Drop,
+    /// Report [`Event::Timeout`] and treat the connection as broken, the publish stays in the
+    /// session store and is sent again when the session is resumed.
+    Disconnect,
+}
+
+/// Acknowledgement timeout and retransmission of outgoing QoS 1 and QoS 2 publishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    ack_timeout: Duration,
+    retransmit: Retransmit,
+    retries: usize,
+    exhausted: Exhausted,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            ack_timeout: Duration::from_secs(3),
+            retransmit: Retransmit::OnReconnect,
+            retries: 3,
+            exhausted: Exhausted::Disconnect,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn ack_timeout(mut self, value: Duration) -> Self {
+        self.ack_timeout = value;
+        self
+    }
+
+    pub fn retransmit(mut self, value: Retransmit) -> Self {
+        self.retransmit = value;
+        self
+    }
+
+    /// Number of retransmissions on the live connection, used with [`Retransmit::OnTimeout`].
+    pub fn retries(mut self, value: usize) -> Self {
+        self.retries = value;
+        self
+    }
+
+    pub fn exhausted(mut self, value: Exhausted) -> Self {
+        self.exhausted = value;
+        self
+    }
+
+    pub fn get_exhausted(&self) -> Exhausted {
+        self.exhausted
+    }
+
+    fn retries_on_timeout(&self) -> usize {
+        match self.retransmit {
+            Retransmit::OnReconnect => 0,
+            Retransmit::OnTimeout => self.retries,
+        }
+    }
+}
+
+/// Inbound QoS 1 or QoS 2 message delivered in manual ack mode.
+#[derive(Debug)]
+struct Unacked {
+    packet_identifier: u16,
+    qos: QoS,
+    /// Set by [`Session::ack`], sent once all earlier messages of the same QoS are
+    /// acknowledged too.
+    reason_code: Option<ReasonCode>,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum Inflight {
+    /// PUBLISH sent, waiting for PUBACK or PUBREC
+    Publish {
+        publish: Publish,
+        sent: Instant,
+        retries: usize,
+    },
+    /// PUBREL sent, waiting for PUBCOMP
+    Release { sent: Instant, retries: usize },
+}
+
+/// Synchronous, IO-free MQTT client protocol state machine.
+///
+/// The session is fed with received packets through [`Session::handle_packet`] and with the
+/// current time through [`Session::handle_tick`]. Packets to send are taken with
+/// [`Session::poll_transmit`], application events with [`Session::poll_event`] and the next
+/// time the session needs a tick with [`Session::poll_timeout`].
+///
+/// Unacknowledged outgoing publishes and releases are recorded in a [`SessionStore`] and
+/// retransmitted when the server resumes the session with `session_present`.
+#[derive(Debug)]
+pub struct Session {
+    packet_identifier: PacketIdentifier,
+    inflight: BTreeMap<u16, Inflight>,
+    /// Packet identifiers of publishes dropped after all retries, reserved until the next
+    /// connection
+    dropped: BTreeSet<u16>,
+    subscriptions: BTreeSet<u16>,
+    incoming: BTreeSet<u16>,
+    transmit: VecDeque<ControlPacket>,
+    events: VecDeque<Event>,
+    retry_policy: RetryPolicy,
+    validate_payload_format: bool,
+    manual_ack: bool,
+    unacked: VecDeque<Unacked>,
+    /// Receive Maximum of the server, QoS 1 and QoS 2 publishes it accepts unacknowledged
+    receive_maximum: u16,
+    keep_alive: Option<Duration>,
+    last_sent: Option<Instant>,
+    ping_sent: Option<Instant>,
+    store: Arc<dyn SessionStore>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            packet_identifier: Default::default(),
+            inflight: BTreeMap::new(),
+            dropped: BTreeSet::new(),
+            subscriptions: BTreeSet::new(),
+            incoming: BTreeSet::new(),
+            transmit: VecDeque::new(),
+            events: VecDeque::new(),
+            retry_policy: RetryPolicy::default(),
+            validate_payload_format: false,
+            manual_ack: false,
+            unacked: VecDeque::new(),
+            receive_maximum: u16::MAX,
+            keep_alive: None,
+            last_sent: None,
+            ping_sent: None,
+            store: Arc::new(MemoryStore::default()),
+        }
+    }
+}
+
+impl Session {
+    pub fn retry_policy(mut self, value: RetryPolicy) -> Self {
+        self.retry_policy = value;
+        self
+    }
+
+    pub fn validate_payload_format(mut self, value: bool) -> Self {
+        self.validate_payload_format = value;
+        self
+    }
+
+    pub fn store(mut self, store: Arc<dyn SessionStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Acknowledge inbound QoS 1 and QoS 2 messages only when [`Session::ack`] is called.
+    pub fn manual_ack(mut self, value: bool) -> Self {
+        self.manual_ack = value;
+        self
+    }
+
+    pub fn connect(&mut self, connect: Connect) {
+        self.keep_alive =
+            (connect.keep_alive > 0).then(|| Duration::from_secs(connect.keep_alive as u64));
+        // anything left from a previous connection is resent from the inflight state on resume
+        self.transmit.clear();
+        for packet_identifier in std::mem::take(&mut self.dropped) {
+            self.packet_identifier.release(packet_identifier);
+        }
+        self.ping_sent = None;
+        self.transmit.push_back(ControlPacket::Connect(connect));
+    }
+
+    /// Queues a PUBLISH, allocating its packet identifier for QoS 1 and QoS 2.
+    pub fn publish(&mut self, now: Instant, mut publish: Publish) -> Result<Option<u16>> {
+        if publish.qos == QoS::AtMostOnce {
+            publish.packet_identifier = None;
+        } else {
+            let packet_identifier = self
+                .packet_identifier
+                .next()
+                .ok_or_else(|| anyhow!("no free packet identifier"))?;
+            publish.packet_identifier = Some(packet_identifier);
+            if let Err(err) = self
+                .store
+                .store(packet_identifier, Stored::Publish(publish.clone()))
+            {
+                self.packet_identifier.release(packet_identifier);
+                return Err(err);
+            }
+            self.inflight.insert(
+                packet_identifier,
+                Inflight::Publish {
+                    publish: publish.clone(),
+                    sent: now,
+                    retries: self.retry_policy.retries_on_timeout(),
+                },
+            );
+        }
+        let packet_identifier = publish.packet_identifier;
+        self.transmit.push_back(ControlPacket::Publish(publish));
+        Ok(packet_identifier)
+    }
+
+    /// Queues a SUBSCRIBE, allocating its packet identifier.
+    pub fn subscribe(&mut self, mut subscribe: Subscribe) -> Result<u16> {
+        let packet_identifier = self
+            .packet_identifier
+            .next()
+            .ok_or_else(|| anyhow!("no free packet identifier"))?;
+        subscribe.packet_identifier = packet_identifier;
+        self.subscriptions.insert(packet_identifier);
+        self.transmit.push_back(ControlPacket::Subscribe(subscribe));
+        Ok(packet_identifier)
+    }
+
+    /// QoS 1 and QoS 2 publishes which can still be sent before the server's Receive Maximum
+    /// is reached.
+    pub fn send_quota(&self) -> usize {
+        (self.receive_maximum as usize).saturating_sub(self.inflight.len())
+    }
+
+    pub fn disconnect(&mut self, disconnect: Disconnect) {
+        self.transmit
+            .push_back(ControlPacket::Disconnect(disconnect));
+    }
+
+    pub fn handle_packet(&mut self, now: Instant, packet: ControlPacket) -> Result<()> {
+        trace!("recv {}", packet);
+        match packet {
+            ControlPacket::ConnAck(ack) => {
+                if let Some(server_keep_alive) = ack.properties.server_keep_alive {
+                    self.keep_alive = (server_keep_alive > 0)
+                        .then(|| Duration::from_secs(server_keep_alive as u64));
+                }
+                self.receive_maximum = ack.properties.receive_maximum.unwrap_or(u16::MAX);
+                // a rejected connection, possibly redirected, keeps the session for the next
+                // the outgoing publishes discarded on resume are reported after the CONNACK
+                let queued = self.events.len();
+                if ack.reason_code == ReasonCode::Success {
+                    self.resume(now, ack.session_present)?;
+                }
+                self.events.insert(queued, Event::Connected(ack));
+            }
+            ControlPacket::Publish(publish) => return self.handle_publish(publish),
+            ControlPacket::PubAck(response) => {
+                if let Some(Inflight::Publish { .. }) =
+                    self.inflight.get(&response.packet_identifier)
+                {
+                    self.complete(response);
+                } else {
+                    warn!("unexpected: PUBACK {}", response.packet_identifier);
+                }
+            }
+            ControlPacket::PubRec(response) => {
+                let packet_identifier = response.packet_identifier;
+                match self.inflight.get(&packet_identifier) {
+                    Some(Inflight::Publish { .. })
+                        if u8::from(response.reason_code)
+                            < u8::from(ReasonCode::UnspecifiedError) =>
+                    {
+                        self.store.store(packet_identifier, Stored::Release)?;
+                        self.inflight.insert(
+                            packet_identifier,
+                            Inflight::Release {
+                                sent: now,
+                                retries: self.retry_policy.retries_on_timeout(),
+                            },
+                        );
+                        self.transmit
+                            .push_back(ControlPacket::PubRel(response_to(packet_identifier)));
+                    }
+                    Some(Inflight::Publish { .. }) => self.complete(response),
+                    _ => {
+                        warn!("unexpected: PUBREC {}", packet_identifier);
+                        let mut response = response_to(packet_identifier);
+                        response.reason_code = ReasonCode::PacketIdentifierNotFound;
+                        self.transmit.push_back(ControlPacket::PubRel(response));
+                    }
+                }
+            }
+            ControlPacket::PubRel(response) => {
+                let mut comp = response_to(response.packet_identifier);
+                if !self.incoming.remove(&response.packet_identifier) {
+                    comp.reason_code = ReasonCode::PacketIdentifierNotFound;
+                }
+                self.transmit.push_back(ControlPacket::PubComp(comp));
+            }
+            ControlPacket::PubComp(response) => {
+                if let Some(Inflight::Release { .. }) =
+                    self.inflight.get(&response.packet_identifier)
+                {
+                    self.complete(response);
+                } else {
+                    warn!("unexpected: PUBCOMP {}", response.packet_identifier);
+                }
+            }
+            ControlPacket::SubAck(ack) => {
+                self.release_subscription(ack.packet_identifier);
+                self.events.push_back(Event::Subscribed(ack));
+            }
+            ControlPacket::UnSubAck(ack) => {
+                self.release_subscription(ack.packet_identifier);
+                self.events.push_back(Event::Unsubscribed(ack));
+            }
+            ControlPacket::PingResp => self.ping_sent = None,
+            ControlPacket::Disconnect(disconnect) => {
+                self.events.push_back(Event::Disconnected(disconnect))
+            }
+            ControlPacket::Auth(auth) => self.events.push_back(Event::Auth(auth)),
+            unexpected => return Err(anyhow!("unexpected: {}", unexpected)),
+        }
+        Ok(())
+    }
+
+    /// Acknowledges an inbound message delivered in manual ack mode with PUBACK or PUBREC, a
+    /// failure `reason_code` rejects it. Acknowledgements are sent in the order the messages
+    /// of the same QoS were received [MQTT-4.6.0-2] [MQTT-4.6.0-3].
+    pub fn ack(&mut self, packet_identifier: u16, reason_code: ReasonCode) -> Result<()> {
+        PubAckReason::try_from(reason_code)?;
+        let unacked = self
+            .unacked
+            .iter_mut()
+            .find(|unacked| {
+                unacked.packet_identifier == packet_identifier && unacked.reason_code.is_none()
+            })
+            .ok_or_else(|| anyhow!("unexpected ack: {}", packet_identifier))?;
+        unacked.reason_code = Some(reason_code);
+        self.release_acks();
+        Ok(())
+    }
+
+    /// Sends the acknowledgements no earlier unacknowledged message of the same QoS holds back.
+    fn release_acks(&mut self) {
+        let mut blocked = [false; 2];
+        let mut i = 0;
+        while i < self.unacked.len() {
+            let unacked = &self.unacked[i];
+            let lane = usize::from(unacked.qos == QoS::ExactlyOnce);
+            let (Some(reason_code), false) = (unacked.reason_code, blocked[lane]) else {
+                blocked[lane] = true;
+                i += 1;
+                continue;
+            };
+            let Some(unacked) = self.unacked.remove(i) else {
+                break;
+            };
+            let packet_identifier = unacked.packet_identifier;
+            let mut response = response_to(packet_identifier);
+            response.reason_code = reason_code;
+            if unacked.qos == QoS::AtLeastOnce {
+                self.transmit.push_back(ControlPacket::PubAck(response));
+            } else {
+                if reason_code == ReasonCode::Success {
+                    self.incoming.insert(packet_identifier);
+                }
+                self.transmit.push_back(ControlPacket::PubRec(response));
+            }
+        }
+    }
+
+    /// Retransmits or expires unacknowledged publishes and keeps the connection alive.
+    pub fn handle_tick(&mut self, now: Instant) {
+        let ack_timeout = self.retry_policy.ack_timeout;
+        let mut expired = vec![];
+        for (packet_identifier, inflight) in self.inflight.iter_mut() {
+            match inflight {
+                Inflight::Publish {
+                    publish,
+                    sent,
+                    retries,
+                } if now >= *sent + ack_timeout => {
+                    if *retries > 0 {
+                        *retries -= 1;
+                        *sent = now;
+                        let mut publish = publish.clone();
+                        publish.dup = true;
+                        self.transmit.push_back(ControlPacket::Publish(publish));
+                    } else {
+                        expired.push(*packet_identifier);
+                    }
+                }
+                Inflight::Release { sent, retries } if now >= *sent + ack_timeout => {
+                    if *retries > 0 {
+                        *retries -= 1;
+                        *sent = now;
+                        self.transmit
+                            .push_back(ControlPacket::PubRel(response_to(*packet_identifier)));
+                    } else {
+                        expired.push(*packet_identifier);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for packet_identifier in expired {
+            match self.retry_policy.exhausted {
+                Exhausted::Drop => {
+                    self.inflight.remove(&packet_identifier);
+                    self.dropped.insert(packet_identifier);
+                    if let Err(err) = self.store.remove(packet_identifier) {
+                        warn!("session store: {}", err);
+                    }
+                }
+                // stop the timer only, the stored state is resent on resume
+                Exhausted::Disconnect => {
+                    self.inflight.remove(&packet_identifier);
+                }
+            }
+            self.events.push_back(Event::Timeout { packet_identifier });
+        }
+
+        if let Some(keep_alive) = self.keep_alive {
+            if let Some(ping_sent) = self.ping_sent {
+                if now >= ping_sent + keep_alive {
+                    self.ping_sent = None;
+                    self.events.push_back(Event::KeepAliveTimeout);
+                }
+            } else if self.last_sent.is_none_or(|sent| now >= sent + keep_alive) {
+                self.ping_sent = Some(now);
+                self.transmit.push_back(ControlPacket::PingReq);
+            }
+        }
+    }
+
+    pub fn poll_transmit(&mut self, now: Instant) -> Option<ControlPacket> {
+        let packet = self.transmit.pop_front();
+        if packet.is_some() {
+            self.last_sent = Some(now);
+        }
+        packet
+    }
+
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Next time [`Session::handle_tick`] has work to do.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        let inflight = self
+            .inflight
+            .values()
+            .map(|inflight| match inflight {
+                Inflight::Publish { sent, .. } | Inflight::Release { sent, .. } => {
+                    *sent + self.retry_policy.ack_timeout
+                }
+            })
+            .min();
+        let keep_alive = self.keep_alive.and_then(|keep_alive| {
+            self.ping_sent
+                .or(self.last_sent)
+                .map(|sent| sent + keep_alive)
+        });
+        match (inflight, keep_alive) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn handle_publish(&mut self, publish: Publish) -> Result<()> {
+        let reason_code = if self.validate_payload_format {
+            publish
+                .payload_str()
+                .map_or_else(ReasonCode::from, |_| ReasonCode::Success)
+        } else {
+            ReasonCode::Success
+        };
+        let deliver = match (publish.qos, publish.packet_identifier) {
+            (QoS::AtMostOnce, _) => true,
+            (qos, Some(packet_identifier)) if self.manual_ack => {
+                if self
+                    .unacked
+                    .iter()
+                    .any(|unacked| unacked.packet_identifier == packet_identifier)
+                {
+                    // retransmission of a message the application did not ack yet
+                    false
+                } else if qos == QoS::ExactlyOnce && self.incoming.contains(&packet_identifier) {
+                    self.transmit
+                        .push_back(ControlPacket::PubRec(response_to(packet_identifier)));
+                    false
+                } else {
+                    // a rejected message is not delivered but keeps its place in the ack order
+                    let rejected = reason_code != ReasonCode::Success;
+                    self.unacked.push_back(Unacked {
+                        packet_identifier,
+                        qos,
+                        reason_code: rejected.then_some(reason_code),
+                    });
+                    self.release_acks();
+                    !rejected
+                }
+            }
+            (QoS::AtLeastOnce, Some(packet_identifier)) => {
+                let mut response = response_to(packet_identifier);
+                response.reason_code = reason_code;
+                self.transmit.push_back(ControlPacket::PubAck(response));
+                true
+            }
+            (QoS::ExactlyOnce, Some(packet_identifier)) => {
+                let mut response = response_to(packet_identifier);
+                response.reason_code = reason_code;
+                self.transmit.push_back(ControlPacket::PubRec(response));
+                // a retransmitted PUBLISH is acknowledged again but delivered only once
+                reason_code == ReasonCode::Success && self.incoming.insert(packet_identifier)
+            }
+            (qos, None) => return Err(MqttError::UndefinedPacketIdentifier(qos).into()),
+        };
+        if reason_code != ReasonCode::Success {
+            return Err(MqttError::PayloadFormatInvalid.into());
+        }
+        if deliver {
+            self.events.push_back(Event::Message(publish));
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, response: PublishResponse) {
+        self.forget(response.packet_identifier);
+        self.events.push_back(Event::Published {
+            packet_identifier: response.packet_identifier,
+            reason_code: response.reason_code,
+        });
+    }
+
+    /// Drops the inflight state of a finished or expired outgoing publish.
+    fn forget(&mut self, packet_identifier: u16) {
+        self.inflight.remove(&packet_identifier);
+        self.packet_identifier.release(packet_identifier);
+        if let Err(err) = self.store.remove(packet_identifier) {
+            warn!("session store: {}", err);
+        }
+    }
+
+    /// Retransmits the stored publishes and releases when the server resumed the session,
+    /// otherwise discards them, reporting each with [`Event::Discarded`], and the inbound state
+    /// as the server no longer knows their packet identifiers [MQTT-3.2.2-5].
+    fn resume(&mut self, now: Instant, session_present: bool) -> Result<()> {
+        if !session_present {
+            let mut discarded: BTreeSet<u16> =
+                std::mem::take(&mut self.inflight).into_keys().collect();
+            self.incoming.clear();
+            self.unacked.clear();
+            for packet_identifier in std::mem::take(&mut self.subscriptions) {
+                self.packet_identifier.release(packet_identifier);
+            }
+            discarded.extend(self.store.load()?.into_iter().map(|(id, _)| id));
+            for packet_identifier in discarded {
+                warn!("discard inflight {}", packet_identifier);
+                self.packet_identifier.release(packet_identifier);
+                self.events
+                    .push_back(Event::Discarded { packet_identifier });
+            }
+            return self.store.clear();
+        }
+        for (packet_identifier, stored) in self.store.load()? {
+            self.packet_identifier.acquire(packet_identifier);
+            let inflight = match stored {
+                Stored::Publish(mut publish) => {
+                    publish.dup = true;
+                    self.transmit
+                        .push_back(ControlPacket::Publish(publish.clone()));
+                    Inflight::Publish {
+                        publish,
+                        sent: now,
+                        retries: self.retry_policy.retries_on_timeout(),
+                    }
+                }
+                Stored::Release => {
+                    self.transmit
+                        .push_back(ControlPacket::PubRel(response_to(packet_identifier)));
+                    Inflight::Release {
+                        sent: now,
+                        retries: self.retry_policy.retries_on_timeout(),
+                    }
+                }
+            };
+            self.inflight.insert(packet_identifier, inflight);
+        }
+        Ok(())
+    }
+
+    fn release_subscription(&mut self, packet_identifier: u16) {
+        if self.subscriptions.remove(&packet_identifier) {
+            self.packet_identifier.release(packet_identifier);
+        } else {
+            warn!("unexpected: SUBACK {}", packet_identifier);
+        }
+    }
+}
+
+fn response_to(packet_identifier: u16) -> PublishResponse {
+    PublishResponse {
+        packet_identifier,
+        reason_code: ReasonCode::Success,
+        properties: Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use claims::*;
+
+    use super::*;
+    use crate::v5::string::MqttString;
+
+    fn publish(qos: QoS, packet_identifier: Option<u16>) -> Publish {
+        Publish {
+            dup: false,
+            qos,
+            retain: false,
+            topic_name: MqttString::from("topic"),
+            packet_identifier,
+            properties: Default::default(),
+            payload: Bytes::from_static(b"payload"),
+        }
+    }
+
+    fn response(packet_identifier: u16, reason_code: ReasonCode) -> PublishResponse {
+        PublishResponse {
+            packet_identifier,
+            reason_code,
+            properties: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_at_least_once_publish() {
+        let now = Instant::now();
+        let mut session = Session::default();
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        assert_eq!(
+            Some(ControlPacket::Publish(publish(
+                QoS::AtLeastOnce,
+                Some(packet_identifier)
+            ))),
+            session.poll_transmit(now)
+        );
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::PubAck(response(packet_identifier, ReasonCode::Success))
+        ));
+        assert_eq!(
+            Some(Event::Published {
+                packet_identifier,
+                reason_code: ReasonCode::Success
+            }),
+            session.poll_event()
+        );
+        assert_none!(session.poll_timeout());
+    }
+
+    #[test]
+    fn test_exactly_once_publish() {
+        let now = Instant::now();
+        let mut session = Session::default();
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::ExactlyOnce, None))
+        ));
+        assert_some!(session.poll_transmit(now));
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::PubRec(response(packet_identifier, ReasonCode::Success))
+        ));
+        assert_eq!(
+            Some(ControlPacket::PubRel(response(
+                packet_identifier,
+                ReasonCode::Success
+            ))),
+            session.poll_transmit(now)
+        );
+        assert_none!(session.poll_event());
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::PubComp(response(packet_identifier, ReasonCode::Success))
+        ));
+        assert_eq!(
+            Some(Event::Published {
+                packet_identifier,
+                reason_code: ReasonCode::Success
+            }),
+            session.poll_event()
+        );
+    }
+
+    #[test]
+    fn test_publish_retransmit_and_timeout() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(1);
+        let mut session = Session::default().retry_policy(
+            RetryPolicy::default()
+                .ack_timeout(timeout)
+                .retransmit(Retransmit::OnTimeout)
+                .retries(1)
+                .exhausted(Exhausted::Drop),
+        );
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        assert_some!(session.poll_transmit(now));
+        assert_eq!(Some(now + timeout), session.poll_timeout());
+
+        session.handle_tick(now + timeout);
+        match assert_some!(session.poll_transmit(now + timeout)) {
+            ControlPacket::Publish(publish) => {
+                assert!(publish.dup);
+                assert_eq!(Some(packet_identifier), publish.packet_identifier);
+            }
+            packet => panic!("unexpected: {}", packet),
+        }
+
+        session.handle_tick(now + timeout * 2);
+        assert_eq!(
+            Some(Event::Timeout { packet_identifier }),
+            session.poll_event()
+        );
+        assert_none!(session.poll_transmit(now + timeout * 2));
+        assert!(assert_ok!(session.store.load()).is_empty());
+
+        // the dropped packet identifier is reserved until the next connection
+        assert!(session.dropped.contains(&packet_identifier));
+        session.connect(Connect {
+            reserved: false,
+            clean_start_flag: false,
+            keep_alive: 0,
+            properties: Default::default(),
+            client_identifier: None,
+            username: None,
+            password: None,
+            will: None,
+        });
+        assert!(session.dropped.is_empty());
+    }
+
+    #[test]
+    fn test_resume_from_store() {
+        let now = Instant::now();
+        let store: Arc<dyn SessionStore> = Arc::new(MemoryStore::default());
+        let mut session = Session::default().store(store.clone());
+        let released = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::ExactlyOnce, None))
+        ));
+        let published = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::PubRec(response(released, ReasonCode::Success))
+        ));
+
+        let mut session = Session::default().store(store);
+        let ack = ConnAck {
+            session_present: true,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        };
+        assert_ok!(session.handle_packet(now, ControlPacket::ConnAck(ack)));
+        assert_eq!(
+            Some(ControlPacket::PubRel(response(
+                released,
+                ReasonCode::Success
+            ))),
+            session.poll_transmit(now)
+        );
+        match assert_some!(session.poll_transmit(now)) {
+            ControlPacket::Publish(publish) => {
+                assert!(publish.dup);
+                assert_eq!(Some(published), publish.packet_identifier);
+            }
+            packet => panic!("unexpected: {}", packet),
+        }
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::PubAck(response(published, ReasonCode::Success))
+        ));
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::PubComp(response(released, ReasonCode::Success))
+        ));
+        assert_none!(session.poll_timeout());
+        assert!(assert_ok!(session.store.load()).is_empty());
+    }
+
+    #[test]
+    fn test_publish_after_resume() {
+        let now = Instant::now();
+        let store: Arc<dyn SessionStore> = Arc::new(MemoryStore::default());
+        let mut session = Session::default().store(store.clone());
+        let restored = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+
+        let mut session = Session::default().store(store);
+        let ack = ConnAck {
+            session_present: true,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        };
+        assert_ok!(session.handle_packet(now, ControlPacket::ConnAck(ack)));
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        assert_ne!(restored, packet_identifier);
+    }
+
+    #[test]
+    fn test_discard_on_new_session() {
+        let now = Instant::now();
+        let mut session = Session::default();
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        let ack = ConnAck {
+            session_present: false,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        };
+        assert_ok!(session.handle_packet(now, ControlPacket::ConnAck(ack.clone())));
+        assert_eq!(Some(Event::Connected(ack)), session.poll_event());
+        assert_eq!(
+            Some(Event::Discarded { packet_identifier }),
+            session.poll_event()
+        );
+        assert_none!(session.poll_event());
+        assert_none!(session.poll_timeout());
+        assert!(assert_ok!(session.store.load()).is_empty());
+    }
+
+    #[test]
+    fn test_retransmit_on_reconnect() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(1);
+        let mut session = Session::default().retry_policy(
+            RetryPolicy::default()
+                .ack_timeout(timeout)
+                .retransmit(Retransmit::OnReconnect)
+                .exhausted(Exhausted::Disconnect),
+        );
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        assert_some!(session.poll_transmit(now));
+
+        session.handle_tick(now + timeout);
+        assert_none!(session.poll_transmit(now + timeout));
+        assert_eq!(
+            Some(Event::Timeout { packet_identifier }),
+            session.poll_event()
+        );
+        assert_none!(session.poll_timeout());
+
+        let ack = ConnAck {
+            session_present: true,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        };
+        assert_ok!(session.handle_packet(now + timeout, ControlPacket::ConnAck(ack)));
+        match assert_some!(session.poll_transmit(now + timeout)) {
+            ControlPacket::Publish(publish) => {
+                assert!(publish.dup);
+                assert_eq!(Some(packet_identifier), publish.packet_identifier);
+            }
+            packet => panic!("unexpected: {}", packet),
+        }
+    }
+
+    #[test]
+    fn test_default_retry_policy_resends_on_resume() {
+        let now = Instant::now();
+        let timeout = RetryPolicy::default().ack_timeout;
+        let mut session = Session::default();
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        assert_some!(session.poll_transmit(now));
+
+        session.handle_tick(now + timeout);
+        assert_eq!(
+            Some(Event::Timeout { packet_identifier }),
+            session.poll_event()
+        );
+
+        let ack = ConnAck {
+            session_present: true,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        };
+        assert_ok!(session.handle_packet(now + timeout, ControlPacket::ConnAck(ack)));
+        match assert_some!(session.poll_transmit(now + timeout)) {
+            ControlPacket::Publish(publish) => {
+                assert!(publish.dup);
+                assert_eq!(Some(packet_identifier), publish.packet_identifier);
+            }
+            packet => panic!("unexpected: {}", packet),
+        }
+    }
+
+    #[test]
+    fn test_manual_ack_order() {
+        let now = Instant::now();
+        let mut session = Session::default().manual_ack(true);
+        for packet_identifier in [1, 2] {
+            assert_ok!(session.handle_packet(
+                now,
+                ControlPacket::Publish(publish(QoS::AtLeastOnce, Some(packet_identifier)))
+            ));
+        }
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::Publish(publish(QoS::ExactlyOnce, Some(3)))
+        ));
+        for _ in 0..3 {
+            assert_some!(session.poll_event());
+        }
+        assert_none!(session.poll_transmit(now));
+
+        // blocked by the earlier QoS 1 message
+        assert_ok!(session.ack(2, ReasonCode::Success));
+        assert_none!(session.poll_transmit(now));
+        // a QoS 2 message is not ordered after QoS 1 messages
+        assert_ok!(session.ack(3, ReasonCode::Success));
+        assert_eq!(
+            Some(ControlPacket::PubRec(response(3, ReasonCode::Success))),
+            session.poll_transmit(now)
+        );
+        assert_none!(session.poll_transmit(now));
+        assert_ok!(session.ack(1, ReasonCode::ImplementationSpecificError));
+        assert_eq!(
+            Some(ControlPacket::PubAck(response(
+                1,
+                ReasonCode::ImplementationSpecificError
+            ))),
+            session.poll_transmit(now)
+        );
+        assert_eq!(
+            Some(ControlPacket::PubAck(response(2, ReasonCode::Success))),
+            session.poll_transmit(now)
+        );
+        assert_err!(session.ack(1, ReasonCode::Success));
+        assert_err!(session.ack(4, ReasonCode::ServerMoved));
+    }
+
+    #[test]
+    fn test_manual_ack_payload_format_invalid() {
+        let now = Instant::now();
+        let mut session = Session::default()
+            .manual_ack(true)
+            .validate_payload_format(true);
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::Publish(publish(QoS::AtLeastOnce, Some(1)))
+        ));
+        let mut invalid = publish(QoS::AtLeastOnce, Some(2));
+        invalid.properties.payload_format_indicator = Some(true);
+        invalid.payload = Bytes::from_static(b"\xff");
+        assert_err!(session.handle_packet(now, ControlPacket::Publish(invalid)));
+        assert_some!(session.poll_event());
+        assert_none!(session.poll_event());
+        // the rejection waits for the earlier message
+        assert_none!(session.poll_transmit(now));
+        assert_ok!(session.ack(1, ReasonCode::Success));
+        assert_eq!(
+            Some(ControlPacket::PubAck(response(1, ReasonCode::Success))),
+            session.poll_transmit(now)
+        );
+        assert_eq!(
+            Some(ControlPacket::PubAck(response(
+                2,
+                ReasonCode::PayloadFormatInvalid
+            ))),
+            session.poll_transmit(now)
+        );
+        assert_err!(session.ack(2, ReasonCode::Success));
+    }
+
+    #[test]
+    fn test_exactly_once_receive_duplicate() {
+        let now = Instant::now();
+        let mut session = Session::default();
+        for _ in 0..2 {
+            assert_ok!(session.handle_packet(
+                now,
+                ControlPacket::Publish(publish(QoS::ExactlyOnce, Some(7)))
+            ));
+            assert_eq!(
+                Some(ControlPacket::PubRec(response(7, ReasonCode::Success))),
+                session.poll_transmit(now)
+            );
+        }
+        assert_eq!(
+            Some(Event::Message(publish(QoS::ExactlyOnce, Some(7)))),
+            session.poll_event()
+        );
+        assert_none!(session.poll_event());
+        assert_ok!(
+            session.handle_packet(now, ControlPacket::PubRel(response(7, ReasonCode::Success)))
+        );
+        assert_eq!(
+            Some(ControlPacket::PubComp(response(7, ReasonCode::Success))),
+            session.poll_transmit(now)
+        );
+    }
+
+    #[test]
+    fn test_exactly_once_receive_after_new_session() {
+        let now = Instant::now();
+        let mut session = Session::default();
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::Publish(publish(QoS::ExactlyOnce, Some(7)))
+        ));
+        assert_some!(session.poll_event());
+        assert_some!(session.poll_transmit(now));
+
+        // the server lost the session before its PUBREL, packet identifier 7 is new again
+        let ack = ConnAck {
+            session_present: false,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        };
+        assert_ok!(session.handle_packet(now, ControlPacket::ConnAck(ack)));
+        assert_some!(session.poll_event());
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::Publish(publish(QoS::ExactlyOnce, Some(7)))
+        ));
+        assert_eq!(
+            Some(Event::Message(publish(QoS::ExactlyOnce, Some(7)))),
+            session.poll_event()
+        );
+        assert_eq!(
+            Some(ControlPacket::PubRec(response(7, ReasonCode::Success))),
+            session.poll_transmit(now)
+        );
+    }
+
+    #[test]
+    fn test_send_quota() {
+        let now = Instant::now();
+        let mut session = Session::default();
+        let mut ack = ConnAck {
+            session_present: false,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        };
+        ack.properties.receive_maximum = Some(1);
+        assert_ok!(session.handle_packet(now, ControlPacket::ConnAck(ack)));
+        assert_eq!(1, session.send_quota());
+        let packet_identifier = assert_some!(assert_ok!(
+            session.publish(now, publish(QoS::AtLeastOnce, None))
+        ));
+        assert_eq!(0, session.send_quota());
+        assert_ok!(session.handle_packet(
+            now,
+            ControlPacket::PubAck(response(packet_identifier, ReasonCode::Success))
+        ));
+        assert_eq!(1, session.send_quota());
+    }
+}
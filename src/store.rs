@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::{anyhow, Result};
+
+use crate::v5::types::{ControlPacket, Publish, PublishResponse, ReasonCode};
+
+/// Outgoing QoS 1 or QoS 2 state kept for a packet identifier.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stored {
+    /// PUBLISH sent, not yet acknowledged with PUBACK or PUBREC
+    Publish(Publish),
+    /// PUBREL sent, not yet acknowledged with PUBCOMP
+    Release,
+}
+
+/// Persistence of the client session state which has to survive a reconnect: the outgoing
+/// publishes and releases which are not acknowledged yet, keyed by packet identifier.
+pub trait SessionStore: fmt::Debug + Send + Sync {
+    /// Records `stored` for `packet_identifier`, replacing the previous state.
+    fn store(&self, packet_identifier: u16, stored: Stored) -> Result<()>;
+    fn remove(&self, packet_identifier: u16) -> Result<()>;
+    /// All stored states, ordered by packet identifier.
+    fn load(&self) -> Result<Vec<(u16, Stored)>>;
+    fn clear(&self) -> Result<()>;
+}
+
+/// Store shared with the application, e.g. to inspect what is left after disconnecting.
+impl<S: SessionStore + ?Sized> SessionStore for Arc<S> {
+    fn store(&self, packet_identifier: u16, stored: Stored) -> Result<()> {
+        (**self).store(packet_identifier, stored)
+    }
+
+    fn remove(&self, packet_identifier: u16) -> Result<()> {
+        (**self).remove(packet_identifier)
+    }
+
+    fn load(&self) -> Result<Vec<(u16, Stored)>> {
+        (**self).load()
+    }
+
+    fn clear(&self) -> Result<()> {
+        (**self).clear()
+    }
+}
+
+/// Session store kept in memory, it survives reconnects but not the process.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    stored: Mutex<BTreeMap<u16, Stored>>,
+}
+
+impl SessionStore for MemoryStore {
+    fn store(&self, packet_identifier: u16, stored: Stored) -> Result<()> {
+        self.lock()?.insert(packet_identifier, stored);
+        Ok(())
+    }
+
+    fn remove(&self, packet_identifier: u16) -> Result<()> {
+        self.lock()?.remove(&packet_identifier);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<(u16, Stored)>> {
+        Ok(self
+            .lock()?
+            .iter()
+            .map(|(packet_identifier, stored)| (*packet_identifier, stored.clone()))
+            .collect())
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.lock()?.clear();
+        Ok(())
+    }
+}
+
+impl MemoryStore {
+    fn lock(&self) -> Result<MutexGuard<'_, BTreeMap<u16, Stored>>> {
+        self.stored
+            .lock()
+            .map_err(|_| anyhow!("session store lock poisoned"))
+    }
+}
+
+/// Session store backed by a directory, one file per packet identifier holding the encoded
+/// PUBLISH or PUBREL packet.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    /// Opens the store in `path`, creating the directory if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+        Ok(FileStore { path })
+    }
+
+    fn file(&self, packet_identifier: u16) -> PathBuf {
+        self.path.join(packet_identifier.to_string())
+    }
+}
+
+impl SessionStore for FileStore {
+    fn store(&self, packet_identifier: u16, stored: Stored) -> Result<()> {
+        let packet = match stored {
+            Stored::Publish(publish) => ControlPacket::Publish(publish),
+            Stored::Release => ControlPacket::PubRel(PublishResponse {
+                packet_identifier,
+                reason_code: ReasonCode::Success,
+                properties: Default::default(),
+            }),
+        };
+        // write and rename, so a crash never leaves a partially written packet
+        let tmp = self.path.join(format!("{}.tmp", packet_identifier));
+        fs::write(&tmp, packet.to_bytes()?)?;
+        fs::rename(&tmp, self.file(packet_identifier))?;
+        Ok(())
+    }
+
+    fn remove(&self, packet_identifier: u16) -> Result<()> {
+        match fs::remove_file(self.file(packet_identifier)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn load(&self) -> Result<Vec<(u16, Stored)>> {
+        let mut stored = BTreeMap::new();
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let Some(packet_identifier) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u16>().ok())
+            else {
+                continue;
+            };
+            let state = match ControlPacket::from_bytes(&fs::read(entry.path())?)? {
+                ControlPacket::Publish(publish) => Stored::Publish(publish),
+                ControlPacket::PubRel(_) => Stored::Release,
+                packet => return Err(anyhow!("unexpected stored packet: {}", packet)),
+            };
+            stored.insert(packet_identifier, state);
+        }
+        Ok(stored.into_iter().collect())
+    }
+
+    fn clear(&self) -> Result<()> {
+        for entry in fs::read_dir(&self.path)? {
+            fs::remove_file(entry?.path())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use claims::*;
+
+    use super::*;
+    use crate::v5::string::MqttString;
+    use crate::v5::types::QoS;
+
+    #[test]
+    fn test_file_store() {
+        let path = std::env::temp_dir().join(format!("como-mqtt-store-{}", std::process::id()));
+        let store = assert_ok!(FileStore::open(&path));
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic_name: MqttString::from("topic"),
+            packet_identifier: Some(1),
+            properties: Default::default(),
+            payload: Bytes::from_static(b"payload"),
+        };
+        assert_ok!(store.store(1, Stored::Publish(publish.clone())));
+        assert_ok!(store.store(2, Stored::Publish(publish.clone())));
+        assert_ok!(store.store(2, Stored::Release));
+        assert_ok!(store.store(3, Stored::Release));
+        assert_ok!(store.remove(3));
+        assert_ok!(store.remove(4));
+
+        let store = assert_ok!(FileStore::open(&path));
+        assert_eq!(
+            vec![(1, Stored::Publish(publish)), (2, Stored::Release)],
+            assert_ok!(store.load())
+        );
+        assert_ok!(store.clear());
+        assert!(assert_ok!(store.load()).is_empty());
+        assert_ok!(fs::remove_dir(&path));
+    }
+}
@@ -1,23 +1,30 @@
-use std::convert::{TryFrom, TryInto};
+use core::convert::{TryFrom, TryInto};
 
-use bytes::{Buf, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
-use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
+use crate::v5::codec::Encoder;
+use crate::v5::decoder::{decode_binary_data, decode_utf8_string, decode_variable_integer};
+use crate::v5::encoder::{encode_utf8_string, RemainingLength};
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::EndOfStream;
 use crate::v5::error::MqttError::UnacceptableProperty;
-use crate::v5::property::{AuthProperties, PropertiesBuilder, Property};
-use crate::v5::types::{Auth, MqttCodec};
+use crate::v5::property::{AuthProperties, AuthPropertiesBuilder, PropertiesSize, Property};
+use crate::v5::types::{Auth, MqttCodec, ReasonCode};
 
 impl TryFrom<Bytes> for Auth {
     type Error = MqttError;
 
     fn try_from(mut reader: Bytes) -> Result<Self, Self::Error> {
-        end_of_stream!(reader.remaining() < 1, "auth reason code");
+        if !reader.has_remaining() {
+            return Ok(Auth {
+                reason_code: ReasonCode::Success,
+                properties: Default::default(),
+            });
+        }
         let reason_code = reader.get_u8().try_into()?;
         let properties_length = decode_variable_integer(&mut reader)? as usize;
-        let properties = AuthProperties::try_from(reader.slice(..properties_length))?;
+        end_of_stream!(reader.remaining() < properties_length, "auth properties");
+        let properties = AuthProperties::try_from(reader.split_to(properties_length))?;
         Ok(Auth {
             reason_code,
             properties,
@@ -29,40 +36,80 @@ impl TryFrom<Bytes> for AuthProperties {
     type Error = MqttError;
 
     fn try_from(mut reader: Bytes) -> Result<Self, Self::Error> {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = AuthPropertiesBuilder::default();
         while reader.has_remaining() {
             let id = decode_variable_integer(&mut reader)?;
             let property = id.try_into()?;
             match property {
                 Property::AuthenticationMethod => {
-                    if let Some(authentication_method) = decode_utf8_string(&mut reader)? {
-                        builder = builder.authentication_method(authentication_method)?
-                    }
+                    builder = builder.authentication_method(decode_utf8_string(&mut reader)?)?;
+                }
+                Property::AuthenticationData => {
+                    builder = builder.authentication_data(decode_binary_data(&mut reader)?)?;
                 }
-                Property::AuthenticationData => unimplemented!(),
                 Property::ReasonString => {
-                    builder = builder.response_topic(decode_utf8_string(&mut reader)?)?;
+                    builder = builder.reason_string(decode_utf8_string(&mut reader)?)?;
                 }
                 Property::UserProperty => {
-                    let user_property = (
+                    builder = builder.user_property((
                         decode_utf8_string(&mut reader)?,
                         decode_utf8_string(&mut reader)?,
-                    );
-                    if let (Some(key), Some(value)) = user_property {
-                        builder = builder.user_property((key, value));
-                    }
+                    ));
                 }
                 _ => return Err(UnacceptableProperty(property)),
             }
         }
-        Ok(builder.auth())
+        Ok(builder.build())
+    }
+}
+
+impl Encoder<&Auth> for MqttCodec {
+    type Error = MqttError;
+
+    fn encode(&mut self, msg: &Auth, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        writer.put_u8(msg.reason_code.into());
+        self.encode(&msg.properties, writer)
+    }
+}
+
+impl RemainingLength for Auth {
+    fn remaining_length(&self) -> usize {
+        let properties_length = self.properties.size();
+        1 + properties_length.size() + properties_length
+    }
+}
+
+impl PropertiesSize for AuthProperties {
+    fn size(&self) -> usize {
+        let mut len = check_size_of_string!(self, authentication_method);
+        len += check_size_of_bytes!(self, authentication_data);
+        len += check_size_of_string!(self, reason_string);
+        len += self
+            .user_properties
+            .iter()
+            .map(|(x, y)| 5 + x.len() + y.len())
+            .sum::<usize>();
+        len
     }
 }
 
-impl Encoder<Auth> for MqttCodec {
+impl Encoder<&AuthProperties> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, _msg: Auth, _writer: &mut BytesMut) -> Result<(), Self::Error> {
-        unimplemented!()
+    fn encode(
+        &mut self,
+        properties: &AuthProperties,
+        writer: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        self.encode(properties.size(), writer)?;
+        encode_property_string!(
+            writer,
+            AuthenticationMethod,
+            properties.authentication_method
+        );
+        encode_property_bytes!(writer, AuthenticationData, properties.authentication_data);
+        encode_property_string!(writer, ReasonString, properties.reason_string);
+        encode_property_user_properties!(writer, UserProperty, properties.user_properties);
+        Ok(())
     }
 }
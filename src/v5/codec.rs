@@ -0,0 +1,25 @@
+//! Codec traits implemented by [`MqttCodec`](crate::v5::types::MqttCodec).
+//!
+//! With the `std` feature these are the `tokio_util::codec` traits, so the codec can be used
+//! with `Framed`. Without it, equivalent traits with only the required methods are provided.
+
+#[cfg(feature = "std")]
+pub use tokio_util::codec::{Decoder, Encoder};
+
+#[cfg(not(feature = "std"))]
+use bytes::BytesMut;
+
+#[cfg(not(feature = "std"))]
+pub trait Encoder<Item> {
+    type Error;
+
+    fn encode(&mut self, item: Item, dst: &mut BytesMut) -> Result<(), Self::Error>;
+}
+
+#[cfg(not(feature = "std"))]
+pub trait Decoder {
+    type Item;
+    type Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error>;
+}
@@ -0,0 +1,778 @@
+//! Byte level fixtures for every packet type, checked against `MqttCodec` encode and decode.
+//! Each fixture names the normative statement of the MQTT v5.0 specification it verifies,
+//! with its section.
+
+use bytes::{Bytes, BytesMut};
+use claims::*;
+use test_case::test_case;
+
+use crate::v5::codec::{Decoder, Encoder};
+use crate::v5::decoder::decode_variable_integer;
+use crate::v5::encoder::encode_variable_integer;
+use crate::v5::error::MqttError;
+use crate::v5::property::{
+    AuthProperties, ConnAckProperties, ConnectProperties, DisconnectProperties, PublishProperties,
+    ResponseProperties, SubscribeProperties, WillProperties,
+};
+use crate::v5::string::MqttString;
+use crate::v5::types::{
+    Auth, ConnAck, Connect, ControlPacket, Disconnect, MqttCodec, Publish, PublishResponse, QoS,
+    ReasonCode, Retain, SubAck, Subscribe, SubscriptionOptions, UnSubscribe, Will,
+};
+
+type Fixture = (Vec<u8>, ControlPacket);
+
+fn user_property() -> (MqttString, MqttString) {
+    (MqttString::from("k"), MqttString::from("v"))
+}
+
+fn response(packet_identifier: u16, reason_code: ReasonCode) -> PublishResponse {
+    PublishResponse {
+        packet_identifier,
+        reason_code,
+        properties: Default::default(),
+    }
+}
+
+// 3.1.2 protocol name [MQTT-3.1.2-1], protocol version [MQTT-3.1.2-2], clean start
+// [MQTT-3.1.2-4], client identifier first in the payload [MQTT-3.1.3-3]
+fn connect_minimal() -> Fixture {
+    (
+        vec![
+            0x10, 0x0E, // fixed header
+            0x00, 0x04, b'M', b'Q', b'T', b'T', // protocol name
+            0x05, // protocol version
+            0x02, // clean start
+            0x00, 0x3C, // keep alive
+            0x00, // properties length
+            0x00, 0x01, b'c', // client identifier
+        ],
+        ControlPacket::Connect(Connect {
+            reserved: false,
+            clean_start_flag: true,
+            keep_alive: 60,
+            properties: Default::default(),
+            client_identifier: Some(MqttString::from("c")),
+            username: None,
+            password: None,
+            will: None,
+        }),
+    )
+}
+
+// 3.1.2 will flag [MQTT-3.1.2-9], will QoS [MQTT-3.1.2-12], will retain [MQTT-3.1.2-15], user
+// name flag [MQTT-3.1.2-17], password flag [MQTT-3.1.2-19], 3.1.3 payload order [MQTT-3.1.3-1]
+fn connect_full() -> Fixture {
+    (
+        vec![
+            0x10, 0x2B, // fixed header
+            0x00, 0x04, b'M', b'Q', b'T', b'T', // protocol name
+            0x05, // protocol version
+            0xEE, // username, password, will retain, will QoS 1, will, clean start
+            0x00, 0x0A, // keep alive
+            0x08, // properties length
+            0x11, 0x00, 0x00, 0x00, 0x0A, // session expire interval
+            0x21, 0x00, 0x14, // receive maximum
+            0x00, 0x01, b'c', // client identifier
+            0x05, // will properties length
+            0x18, 0x00, 0x00, 0x00, 0x00, // will delay interval
+            0x00, 0x01, b'w', // will topic
+            0x00, 0x03, b'b', b'y', b'e', // will payload
+            0x00, 0x01, b'u', // username
+            0x00, 0x02, 0x01, 0x02, // password
+        ],
+        ControlPacket::Connect(Connect {
+            reserved: false,
+            clean_start_flag: true,
+            keep_alive: 10,
+            properties: ConnectProperties {
+                session_expire_interval: Some(10),
+                receive_maximum: Some(20),
+                ..Default::default()
+            },
+            client_identifier: Some(MqttString::from("c")),
+            username: Some(MqttString::from("u")),
+            password: Some(Bytes::from_static(&[0x01, 0x02])),
+            will: Some(Will {
+                qos: QoS::AtLeastOnce,
+                retain: true,
+                properties: WillProperties::default(),
+                topic: MqttString::from("w"),
+                payload: Bytes::from_static(b"bye"),
+            }),
+        }),
+    )
+}
+
+// 3.1.2.11 maximum packet size [MQTT-3.1.2-24], topic alias maximum [MQTT-3.1.2-26], request
+// response information [MQTT-3.1.2-28], request problem information [MQTT-3.1.2-29],
+// authentication method [MQTT-3.1.2-30]
+fn connect_properties() -> Fixture {
+    (
+        vec![
+            0x10, 0x27, // fixed header
+            0x00, 0x04, b'M', b'Q', b'T', b'T', // protocol name
+            0x05, // protocol version
+            0x02, // clean start
+            0x00, 0x00, // keep alive
+            0x19, // properties length
+            0x27, 0x00, 0x00, 0x10, 0x00, // maximum packet size
+            0x22, 0x00, 0x0A, // topic alias maximum
+            0x19, 0x01, // request response information
+            0x17, 0x00, // request problem information
+            0x15, 0x00, 0x05, b'S', b'C', b'R', b'A', b'M', // authentication method
+            0x16, 0x00, 0x02, 0x01, 0x02, // authentication data
+            0x00, 0x01, b'c', // client identifier
+        ],
+        ControlPacket::Connect(Connect {
+            reserved: false,
+            clean_start_flag: true,
+            keep_alive: 0,
+            properties: ConnectProperties {
+                maximum_packet_size: Some(4096),
+                topic_alias_maximum: Some(10),
+                request_response_information: Some(true),
+                request_problem_information: Some(false),
+                authentication_method: Some(MqttString::from("SCRAM")),
+                authentication_data: Some(Bytes::from_static(&[0x01, 0x02])),
+                ..Default::default()
+            },
+            client_identifier: Some(MqttString::from("c")),
+            username: None,
+            password: None,
+            will: None,
+        }),
+    )
+}
+
+// 3.1.3.2 will delay interval [MQTT-3.1.3-9], 3.1.3.3 will topic [MQTT-3.1.3-11]
+fn connect_will_properties() -> Fixture {
+    (
+        vec![
+            0x10, 0x2B, // fixed header
+            0x00, 0x04, b'M', b'Q', b'T', b'T', // protocol name
+            0x05, // protocol version
+            0x06, // will QoS 0, will, clean start
+            0x00, 0x00, // keep alive
+            0x00, // properties length
+            0x00, 0x01, b'c', // client identifier
+            0x17, // will properties length
+            0x18, 0x00, 0x00, 0x00, 0x05, // will delay interval
+            0x02, 0x00, 0x00, 0x00, 0x3C, // message expire interval
+            0x03, 0x00, 0x01, b'c', // content type
+            0x08, 0x00, 0x01, b'r', // response topic
+            0x09, 0x00, 0x02, 0xCA, 0xFE, // correlation data
+            0x00, 0x01, b'w', // will topic
+            0x00, 0x00, // will payload
+        ],
+        ControlPacket::Connect(Connect {
+            reserved: false,
+            clean_start_flag: true,
+            keep_alive: 0,
+            properties: Default::default(),
+            client_identifier: Some(MqttString::from("c")),
+            username: None,
+            password: None,
+            will: Some(Will {
+                qos: QoS::AtMostOnce,
+                retain: false,
+                properties: WillProperties {
+                    will_delay_interval: 5,
+                    message_expire_interval: Some(60),
+                    content_type: Some(MqttString::from("c")),
+                    response_topic: Some(MqttString::from("r")),
+                    correlation_data: Some(Bytes::from_static(&[0xCA, 0xFE])),
+                    ..Default::default()
+                },
+                topic: MqttString::from("w"),
+                payload: Bytes::new(),
+            }),
+        }),
+    )
+}
+
+// 3.2.2.1.1 session present of a resumed session [MQTT-3.2.2-3]
+fn connack_session_present() -> Fixture {
+    (
+        vec![0x20, 0x03, 0x01, 0x00, 0x00],
+        ControlPacket::ConnAck(ConnAck {
+            session_present: true,
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        }),
+    )
+}
+
+// 3.2.2 session present 0 with a failure reason code [MQTT-3.2.2-6], CONNACK reason code
+// [MQTT-3.2.2-8], maximum QoS [MQTT-3.2.2-9]
+fn connack_properties() -> Fixture {
+    (
+        vec![
+            0x20, 0x18, // fixed header
+            0x00, // connect acknowledge flags
+            0x87, // not authorized
+            0x15, // properties length
+            0x24, 0x01, // maximum QoS
+            0x25, 0x00, // retain available
+            0x12, 0x00, 0x02, b'i', b'd', // assigned client identifier
+            0x1F, 0x00, 0x02, b'n', b'o', // reason string
+            0x26, 0x00, 0x01, b'k', 0x00, 0x01, b'v', // user property
+        ],
+        ControlPacket::ConnAck(ConnAck {
+            session_present: false,
+            reason_code: ReasonCode::NotAuthorized,
+            properties: ConnAckProperties {
+                maximum_qos: Some(QoS::AtLeastOnce),
+                retain_available: Some(false),
+                assigned_client_identifier: Some(MqttString::from("id")),
+                reason_string: Some(MqttString::from("no")),
+                user_properties: vec![user_property()],
+                ..Default::default()
+            },
+        }),
+    )
+}
+
+// 3.2.2.3 maximum packet size [MQTT-3.2.2-15], topic alias maximum [MQTT-3.2.2-17], server
+// keep alive [MQTT-3.2.2-21]
+fn connack_server_properties() -> Fixture {
+    (
+        vec![
+            0x20, 0x31, // fixed header
+            0x00, // connect acknowledge flags
+            0x00, // success
+            0x2E, // properties length
+            0x11, 0x00, 0x00, 0x00, 0x78, // session expire interval
+            0x21, 0x00, 0x0A, // receive maximum
+            0x27, 0x00, 0x00, 0x04, 0x00, // maximum packet size
+            0x22, 0x00, 0x05, // topic alias maximum
+            0x28, 0x00, // wildcard subscription available
+            0x29, 0x01, // subscription identifier available
+            0x2A, 0x00, // shared subscription available
+            0x13, 0x00, 0x1E, // server keep alive
+            0x1A, 0x00, 0x01, b'i', // response information
+            0x1C, 0x00, 0x01, b's', // server reference
+            0x15, 0x00, 0x05, b'S', b'C', b'R', b'A', b'M', // authentication method
+            0x16, 0x00, 0x02, 0x01, 0x02, // authentication data
+        ],
+        ControlPacket::ConnAck(ConnAck {
+            session_present: false,
+            reason_code: ReasonCode::Success,
+            properties: ConnAckProperties {
+                session_expire_interval: Some(120),
+                receive_maximum: Some(10),
+                maximum_packet_size: Some(1024),
+                topic_alias_maximum: Some(5),
+                wildcard_subscription_available: Some(false),
+                subscription_identifier_available: Some(true),
+                shared_subscription_available: Some(false),
+                server_keep_alive: Some(30),
+                response_information: Some(MqttString::from("i")),
+                server_reference: Some(MqttString::from("s")),
+                authentication_method: Some(MqttString::from("SCRAM")),
+                authentication_data: Some(Bytes::from_static(&[0x01, 0x02])),
+                ..Default::default()
+            },
+        }),
+    )
+}
+
+// 3.3.1 QoS 0 without packet identifier [MQTT-2.2.1-2] and with DUP 0 [MQTT-3.3.1-2]
+fn publish_qos0() -> Fixture {
+    (
+        vec![
+            0x30, 0x08, // fixed header
+            0x00, 0x03, b'a', b'/', b'b', // topic name
+            0x00, // properties length
+            b'h', b'i', // payload
+        ],
+        ControlPacket::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic_name: MqttString::from("a/b"),
+            packet_identifier: None,
+            properties: Default::default(),
+            payload: Bytes::from_static(b"hi"),
+        }),
+    )
+}
+
+// 3.3.1.1 DUP of a redelivery [MQTT-3.3.1-1], 3.3.2.3 payload format indicator [MQTT-3.3.2-4],
+// response topic [MQTT-3.3.2-13], correlation data [MQTT-3.3.2-16], content type
+// [MQTT-3.3.2-19], 1.5.5 largest subscription identifier [MQTT-1.5.5-1]
+fn publish_properties() -> Fixture {
+    (
+        vec![
+            0x3B, 0x2B, // fixed header: DUP, QoS 1, RETAIN
+            0x00, 0x01, b't', // topic name
+            0x00, 0x0A, // packet identifier
+            0x23, // properties length
+            0x01, 0x01, // payload format indicator
+            0x02, 0x00, 0x00, 0x00, 0x1E, // message expire interval
+            0x23, 0x00, 0x05, // topic alias
+            0x08, 0x00, 0x01, b'r', // response topic
+            0x09, 0x00, 0x02, 0xCA, 0xFE, // correlation data
+            0x26, 0x00, 0x01, b'k', 0x00, 0x01, b'v', // user property
+            0x0B, 0xFF, 0xFF, 0xFF, 0x7F, // subscription identifier
+            0x03, 0x00, 0x01, b'c', // content type
+            b'o', b'k', // payload
+        ],
+        ControlPacket::Publish(Publish {
+            dup: true,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            topic_name: MqttString::from("t"),
+            packet_identifier: Some(10),
+            properties: PublishProperties {
+                payload_format_indicator: Some(true),
+                message_expire_interval: Some(30),
+                topic_alias: Some(5),
+                response_topic: Some(MqttString::from("r")),
+                correlation_data: Some(Bytes::from_static(&[0xCA, 0xFE])),
+                user_properties: vec![user_property()],
+                subscription_identifiers: vec![268_435_455],
+                content_type: Some(MqttString::from("c")),
+            },
+            payload: Bytes::from_static(b"ok"),
+        }),
+    )
+}
+
+// 3.3.4 one subscription identifier for each matching subscription [MQTT-3.3.4-4]
+fn publish_subscription_identifiers() -> Fixture {
+    (
+        vec![
+            0x30, 0x08, // fixed header
+            0x00, 0x01, b't', // topic name
+            0x04, // properties length
+            0x0B, 0x01, // subscription identifier
+            0x0B, 0x02, // subscription identifier
+        ],
+        ControlPacket::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic_name: MqttString::from("t"),
+            packet_identifier: None,
+            properties: PublishProperties {
+                subscription_identifiers: vec![1, 2],
+                ..Default::default()
+            },
+            payload: Bytes::new(),
+        }),
+    )
+}
+
+// 2.1.4 remaining length in the minimum number of bytes, two [MQTT-1.5.5-1]
+fn publish_two_byte_remaining_length() -> Fixture {
+    let mut bytes = vec![
+        0x30, 0xCC, 0x01, // fixed header, remaining length 204
+        0x00, 0x01, b't', // topic name
+        0x00, // properties length
+    ];
+    bytes.extend_from_slice(&[0xAB; 200]);
+    (
+        bytes,
+        ControlPacket::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic_name: MqttString::from("t"),
+            packet_identifier: None,
+            properties: Default::default(),
+            payload: Bytes::from_static(&[0xAB; 200]),
+        }),
+    )
+}
+
+// 3.4.2.1 PUBACK reason code [MQTT-3.4.2-1]
+fn puback() -> Fixture {
+    (
+        vec![0x40, 0x04, 0x00, 0x0A, 0x10, 0x00],
+        ControlPacket::PubAck(response(10, ReasonCode::NoMatchingSubscribers)),
+    )
+}
+
+// 3.5.2 PUBREC reason code [MQTT-3.5.2-1] and reason string [MQTT-3.5.2-2]
+fn pubrec_reason_string() -> Fixture {
+    (
+        vec![
+            0x50, 0x09, // fixed header
+            0x00, 0x01, // packet identifier
+            0x10, // no matching subscribers
+            0x05, // properties length
+            0x1F, 0x00, 0x02, b'n', b'o', // reason string
+        ],
+        ControlPacket::PubRec(PublishResponse {
+            packet_identifier: 1,
+            reason_code: ReasonCode::NoMatchingSubscribers,
+            properties: ResponseProperties {
+                reason_string: Some(MqttString::from("no")),
+                ..Default::default()
+            },
+        }),
+    )
+}
+
+// 1.5.4 zero length UTF-8 encoded string [MQTT-1.5.4-1] as reason string and in a user
+// property [MQTT-1.5.7-1]
+fn puback_empty_strings() -> Fixture {
+    (
+        vec![
+            0x40, 0x0D, // fixed header
+            0x00, 0x0A, // packet identifier
+            0x00, // success
+            0x09, // properties length
+            0x1F, 0x00, 0x00, // reason string
+            0x26, 0x00, 0x01, b'k', 0x00, 0x00, // user property
+        ],
+        ControlPacket::PubAck(PublishResponse {
+            packet_identifier: 10,
+            reason_code: ReasonCode::Success,
+            properties: ResponseProperties {
+                reason_string: Some(MqttString::from("")),
+                user_properties: vec![(MqttString::from("k"), MqttString::from(""))],
+            },
+        }),
+    )
+}
+
+// 3.6.1 PUBREL fixed header flags 0b0010 [MQTT-3.6.1-1]
+fn pubrel() -> Fixture {
+    (
+        vec![0x62, 0x04, 0x00, 0x01, 0x92, 0x00],
+        ControlPacket::PubRel(response(1, ReasonCode::PacketIdentifierNotFound)),
+    )
+}
+
+// 3.7.2.1 PUBCOMP reason code [MQTT-3.7.2-1]
+fn pubcomp() -> Fixture {
+    (
+        vec![0x70, 0x04, 0x00, 0x01, 0x00, 0x00],
+        ControlPacket::PubComp(response(1, ReasonCode::Success)),
+    )
+}
+
+// 3.8.1 SUBSCRIBE fixed header flags 0b0010 [MQTT-3.8.1-1], 3.8.3.1 subscription options with
+// reserved bits 0 [MQTT-3.8.3-5]
+fn subscribe() -> Fixture {
+    (
+        vec![
+            0x82, 0x0F, // fixed header
+            0x00, 0x01, // packet identifier
+            0x02, // properties length
+            0x0B, 0x01, // subscription identifier
+            0x00, 0x03, b'a', b'/', b'+', // topic filter
+            0x2D, // QoS 1, no local, retain as published, do not send retained
+            0x00, 0x01, b'#', // topic filter
+            0x02, // QoS 2
+        ],
+        ControlPacket::Subscribe(Subscribe {
+            packet_identifier: 1,
+            properties: SubscribeProperties {
+                subscription_identifier: Some(1),
+                ..Default::default()
+            },
+            topic_filters: vec![
+                (
+                    MqttString::from("a/+"),
+                    SubscriptionOptions {
+                        qos: QoS::AtLeastOnce,
+                        nl: true,
+                        rap: true,
+                        retain: Retain::DoNotSend,
+                    },
+                ),
+                (
+                    MqttString::from("#"),
+                    SubscriptionOptions {
+                        qos: QoS::ExactlyOnce,
+                        nl: false,
+                        rap: false,
+                        retain: Retain::SendAtTime,
+                    },
+                ),
+            ],
+        }),
+    )
+}
+
+// 3.9.3 one SUBACK reason code per topic filter, in order [MQTT-3.9.3-1]
+fn suback() -> Fixture {
+    (
+        vec![0x90, 0x07, 0x00, 0x01, 0x00, 0x00, 0x01, 0x02, 0x80],
+        ControlPacket::SubAck(SubAck {
+            packet_identifier: 1,
+            properties: Default::default(),
+            reason_codes: vec![
+                ReasonCode::Success,
+                ReasonCode::GrantedQoS1,
+                ReasonCode::GrantedQoS2,
+                ReasonCode::UnspecifiedError,
+            ],
+        }),
+    )
+}
+
+// 3.9.2.1 SUBACK reason string [MQTT-3.9.2-1]
+fn suback_properties() -> Fixture {
+    (
+        vec![
+            0x90, 0x10, // fixed header
+            0x00, 0x01, // packet identifier
+            0x0C, // properties length
+            0x1F, 0x00, 0x02, b'n', b'o', // reason string
+            0x26, 0x00, 0x01, b'k', 0x00, 0x01, b'v', // user property
+            0x01, // granted QoS 1
+        ],
+        ControlPacket::SubAck(SubAck {
+            packet_identifier: 1,
+            properties: ResponseProperties {
+                reason_string: Some(MqttString::from("no")),
+                user_properties: vec![user_property()],
+            },
+            reason_codes: vec![ReasonCode::GrantedQoS1],
+        }),
+    )
+}
+
+// 3.10.1 UNSUBSCRIBE fixed header flags 0b0010 [MQTT-3.10.1-1], 3.10.3 at least one topic
+// filter [MQTT-3.10.3-2]
+fn unsubscribe() -> Fixture {
+    (
+        vec![
+            0xA2, 0x08, // fixed header
+            0x00, 0x02, // packet identifier
+            0x00, // properties length
+            0x00, 0x03, b'a', b'/', b'b', // topic filter
+        ],
+        ControlPacket::UnSubscribe(UnSubscribe {
+            packet_identifier: 2,
+            properties: Default::default(),
+            topic_filters: vec![MqttString::from("a/b")],
+        }),
+    )
+}
+
+// 3.11.3 one UNSUBACK reason code per topic filter, in order [MQTT-3.11.3-1]
+fn unsuback() -> Fixture {
+    (
+        vec![0xB0, 0x05, 0x00, 0x02, 0x00, 0x00, 0x11],
+        ControlPacket::UnSubAck(SubAck {
+            packet_identifier: 2,
+            properties: Default::default(),
+            reason_codes: vec![ReasonCode::Success, ReasonCode::NoSubscriptionExisted],
+        }),
+    )
+}
+
+// 3.11.2.1 UNSUBACK reason string [MQTT-3.11.2-1]
+fn unsuback_properties() -> Fixture {
+    (
+        vec![
+            0xB0, 0x10, // fixed header
+            0x00, 0x02, // packet identifier
+            0x0C, // properties length
+            0x1F, 0x00, 0x02, b'n', b'o', // reason string
+            0x26, 0x00, 0x01, b'k', 0x00, 0x01, b'v', // user property
+            0x11, // no subscription existed
+        ],
+        ControlPacket::UnSubAck(SubAck {
+            packet_identifier: 2,
+            properties: ResponseProperties {
+                reason_string: Some(MqttString::from("no")),
+                user_properties: vec![user_property()],
+            },
+            reason_codes: vec![ReasonCode::NoSubscriptionExisted],
+        }),
+    )
+}
+
+// 3.12 PINGREQ sent to keep the connection alive [MQTT-3.1.2-20]
+fn pingreq() -> Fixture {
+    (vec![0xC0, 0x00], ControlPacket::PingReq)
+}
+
+// 3.13 PINGRESP answering a PINGREQ [MQTT-3.12.4-1]
+fn pingresp() -> Fixture {
+    (vec![0xD0, 0x00], ControlPacket::PingResp)
+}
+
+// 3.14.2 DISCONNECT reason code [MQTT-3.14.2-1] and properties
+fn disconnect_properties() -> Fixture {
+    (
+        vec![
+            0xE0, 0x0B, // fixed header
+            0x8B, // server shutting down
+            0x09, // properties length
+            0x11, 0x00, 0x00, 0x00, 0x00, // session expire interval
+            0x1C, 0x00, 0x01, b's', // server reference
+        ],
+        ControlPacket::Disconnect(Disconnect {
+            reason_code: ReasonCode::ServerShuttingDown,
+            properties: DisconnectProperties {
+                session_expire_interval: Some(0),
+                server_reference: Some(MqttString::from("s")),
+                ..Default::default()
+            },
+        }),
+    )
+}
+
+// 3.15.2 AUTH reason code [MQTT-3.15.2-1] and properties
+fn auth() -> Fixture {
+    (
+        vec![
+            0xF0, 0x0F, // fixed header
+            0x18, // continue authentication
+            0x0D, // properties length
+            0x15, 0x00, 0x05, b'S', b'C', b'R', b'A', b'M', // authentication method
+            0x16, 0x00, 0x02, 0x01, 0x02, // authentication data
+        ],
+        ControlPacket::Auth(Auth {
+            reason_code: ReasonCode::ContinueAuthentication,
+            properties: AuthProperties {
+                authentication_method: Some(MqttString::from("SCRAM")),
+                authentication_data: Some(Bytes::from_static(&[0x01, 0x02])),
+                ..Default::default()
+            },
+        }),
+    )
+}
+
+// Short forms which are valid on the wire but never produced by the encoder.
+
+// 3.4.2.1 reason code and property length omitted for Success [MQTT-3.4.2-1]
+fn puback_no_reason_code() -> Fixture {
+    (
+        vec![0x40, 0x02, 0x00, 0x0A],
+        ControlPacket::PubAck(response(10, ReasonCode::Success)),
+    )
+}
+
+// 3.4.2.2.1 property length omitted, remaining length 3 [MQTT-3.4.2-1]
+fn puback_no_properties_length() -> Fixture {
+    (
+        vec![0x40, 0x03, 0x00, 0x0A, 0x10],
+        ControlPacket::PubAck(response(10, ReasonCode::NoMatchingSubscribers)),
+    )
+}
+
+// 3.14.2.1 DISCONNECT without reason code is a normal disconnection [MQTT-3.14.2-1]
+fn disconnect_no_reason_code() -> Fixture {
+    (
+        vec![0xE0, 0x00],
+        ControlPacket::Disconnect(Disconnect {
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        }),
+    )
+}
+
+// 3.14.2.2.1 property length omitted, remaining length 1 [MQTT-3.14.2-1]
+fn disconnect_with_will() -> Fixture {
+    (
+        vec![0xE0, 0x01, 0x04],
+        ControlPacket::Disconnect(Disconnect {
+            reason_code: ReasonCode::DisconnectWithWill,
+            properties: Default::default(),
+        }),
+    )
+}
+
+// 3.15.2.1 AUTH without reason code is a success [MQTT-3.15.2-1]
+fn auth_no_reason_code() -> Fixture {
+    (
+        vec![0xF0, 0x00],
+        ControlPacket::Auth(Auth {
+            reason_code: ReasonCode::Success,
+            properties: Default::default(),
+        }),
+    )
+}
+
+fn decode(bytes: &[u8]) -> Result<Option<ControlPacket>, MqttError> {
+    let mut reader = BytesMut::from(bytes);
+    let packet = MqttCodec::new(None).decode(&mut reader);
+    assert!(reader.is_empty(), "{} bytes left", reader.len());
+    packet
+}
+
+#[test_case(connect_minimal() ; "3.1 CONNECT minimal")]
+#[test_case(connect_full() ; "3.1 CONNECT will username password")]
+#[test_case(connect_properties() ; "3.1 CONNECT properties")]
+#[test_case(connect_will_properties() ; "3.1 CONNECT will properties")]
+#[test_case(connack_session_present() ; "3.2 CONNACK session present")]
+#[test_case(connack_properties() ; "3.2 CONNACK properties")]
+#[test_case(connack_server_properties() ; "3.2 CONNACK server properties")]
+#[test_case(publish_qos0() ; "3.3 PUBLISH QoS 0")]
+#[test_case(publish_properties() ; "3.3 PUBLISH properties")]
+#[test_case(publish_subscription_identifiers() ; "3.3 PUBLISH subscription identifiers")]
+#[test_case(publish_two_byte_remaining_length() ; "2.1.4 remaining length")]
+#[test_case(puback() ; "3.4 PUBACK")]
+#[test_case(pubrec_reason_string() ; "3.5 PUBREC reason string")]
+#[test_case(puback_empty_strings() ; "1.5.4 zero length strings")]
+#[test_case(pubrel() ; "3.6 PUBREL")]
+#[test_case(pubcomp() ; "3.7 PUBCOMP")]
+#[test_case(subscribe() ; "3.8 SUBSCRIBE")]
+#[test_case(suback() ; "3.9 SUBACK")]
+#[test_case(suback_properties() ; "3.9 SUBACK properties")]
+#[test_case(unsubscribe() ; "3.10 UNSUBSCRIBE")]
+#[test_case(unsuback() ; "3.11 UNSUBACK")]
+#[test_case(unsuback_properties() ; "3.11 UNSUBACK properties")]
+#[test_case(pingreq() ; "3.12 PINGREQ")]
+#[test_case(pingresp() ; "3.13 PINGRESP")]
+#[test_case(disconnect_properties() ; "3.14 DISCONNECT properties")]
+#[test_case(auth() ; "3.15 AUTH")]
+fn test_encode_decode((bytes, packet): Fixture) {
+    let mut writer = BytesMut::new();
+    assert_ok!(MqttCodec::new(None).encode(packet.clone(), &mut writer));
+    assert_eq!(bytes, writer.to_vec());
+    assert_eq!(packet, assert_some!(assert_ok!(decode(&bytes))));
+}
+
+#[test_case(puback_no_reason_code() ; "3.4.2.1 PUBACK without reason code")]
+#[test_case(puback_no_properties_length() ; "3.4.2.2.1 PUBACK without properties")]
+#[test_case(disconnect_no_reason_code() ; "3.14.2.1 DISCONNECT without reason code")]
+#[test_case(disconnect_with_will() ; "3.14.2.2.1 DISCONNECT without properties")]
+#[test_case(auth_no_reason_code() ; "3.15.2.1 AUTH without reason code")]
+fn test_decode((bytes, packet): Fixture) {
+    assert_eq!(packet, assert_some!(assert_ok!(decode(&bytes))));
+}
+
+#[test_case(&[0x10, 0x0A, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x03, 0x00, 0x00, 0x00, 0x00] ;
+    "MQTT-3.1.2-3 CONNECT reserved flag")]
+#[test_case(&[0x10, 0x0A, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x00, 0x00, 0x00] ;
+    "MQTT-3.1.2-2 CONNECT protocol version 4")]
+#[test_case(&[0x36, 0x04, 0x00, 0x01, b't', 0x00] ; "MQTT-3.3.1-4 PUBLISH QoS 3")]
+#[test_case(&[0x60, 0x02, 0x00, 0x01] ; "MQTT-3.6.1-1 PUBREL reserved flags")]
+#[test_case(&[0x80, 0x02, 0x00, 0x01] ; "MQTT-3.8.1-1 SUBSCRIBE reserved flags")]
+#[test_case(&[0x00, 0x00] ; "MQTT-4.13.1-1 reserved packet type")]
+#[test_case(&[0x30, 0x08, 0x00, 0x01, b't', 0x04, 0x01, 0x01, 0x01, 0x00] ;
+    "MQTT-4.13.1-1 PUBLISH payload format indicator twice")]
+#[test_case(&[0x30, 0x06, 0x00, 0x01, b't', 0x02, 0x11, 0x00] ;
+    "MQTT-4.13.1-1 PUBLISH session expire interval")]
+#[test_case(&[0x82, 0x06, 0x00, 0x01, 0x00, 0x00, 0x01, b'#'] ;
+    "MQTT-3.8.3-2 SUBSCRIBE without subscription options")]
+#[test_case(&[0xE0, 0x02, 0x02, 0x00] ; "MQTT-3.14.2-1 DISCONNECT granted QoS 2")]
+#[test_case(&[0xC0, 0xFF, 0xFF, 0xFF, 0xFF, 0x01] ; "MQTT-4.13.1-1 variable byte integer of five bytes")]
+fn test_decode_malformed(bytes: &[u8]) {
+    let mut reader = BytesMut::from(bytes);
+    assert_err!(MqttCodec::new(None).decode(&mut reader));
+}
+
+#[test_case(0, &[0x00] ; "zero")]
+#[test_case(127, &[0x7F] ; "one byte maximum")]
+#[test_case(128, &[0x80, 0x01] ; "two bytes minimum")]
+#[test_case(16_383, &[0xFF, 0x7F] ; "two bytes maximum")]
+#[test_case(16_384, &[0x80, 0x80, 0x01] ; "three bytes minimum")]
+#[test_case(2_097_151, &[0xFF, 0xFF, 0x7F] ; "three bytes maximum")]
+#[test_case(2_097_152, &[0x80, 0x80, 0x80, 0x01] ; "four bytes minimum")]
+#[test_case(268_435_455, &[0xFF, 0xFF, 0xFF, 0x7F] ; "four bytes maximum")]
+fn test_variable_byte_integer(value: u32, bytes: &[u8]) {
+    let mut writer = BytesMut::with_capacity(4);
+    assert_ok!(encode_variable_integer(&mut writer, value as usize));
+    assert_eq!(bytes, &writer[..]);
+    assert_eq!(value, assert_ok!(decode_variable_integer(&mut writer)));
+}
@@ -1,10 +1,12 @@
-use std::convert::TryInto;
-use std::mem::size_of_val;
+use core::convert::TryInto;
+use core::mem::size_of_val;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
-use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
+use crate::v5::codec::Encoder;
+use crate::v5::decoder::{
+    decode_binary_data, decode_bool, decode_utf8_string, decode_variable_integer,
+};
 use crate::v5::encoder::encode_utf8_string;
 use crate::v5::encoder::RemainingLength;
 use crate::v5::error::MqttError;
@@ -27,7 +29,7 @@ pub fn decode_connack(mut reader: Bytes) -> Result<Option<ControlPacket>, MqttEr
 }
 
 pub fn decode_connack_properties(mut reader: Bytes) -> Result<ConnAckProperties, MqttError> {
-    let mut builder = PropertiesBuilder::default();
+    let mut builder = ConnAckPropertiesBuilder::default();
     while reader.has_remaining() {
         let id = decode_variable_integer(&mut reader)?;
         let property = id.try_into()?;
@@ -45,8 +47,8 @@ pub fn decode_connack_properties(mut reader: Bytes) -> Result<ConnAckProperties,
                 builder = builder.maximum_qos(reader.get_u8())?;
             }
             Property::RetainAvailable => {
-                end_of_stream!(reader.remaining() < 1, "retain available");
-                builder = builder.retain_available(reader.get_u8())?;
+                builder =
+                    builder.retain_available(decode_bool(&mut reader, "retain available")?)?;
             }
             Property::MaximumPacketSize => {
                 end_of_stream!(reader.remaining() < 4, "maximum packet size");
@@ -60,47 +62,60 @@ pub fn decode_connack_properties(mut reader: Bytes) -> Result<ConnAckProperties,
                 builder = builder.topic_alias_maximum(reader.get_u16())?;
             }
             Property::ReasonString => {
-                builder = builder.response_topic(decode_utf8_string(&mut reader)?)?;
+                builder = builder.reason_string(decode_utf8_string(&mut reader)?)?;
             }
             Property::UserProperty => {
-                let user_property = (
+                builder = builder.user_property((
                     decode_utf8_string(&mut reader)?,
                     decode_utf8_string(&mut reader)?,
-                );
-                if let (Some(key), Some(value)) = user_property {
-                    builder = builder.user_property((key, value));
-                }
+                ));
             }
             Property::WildcardSubscriptionAvailable => {
-                end_of_stream!(reader.remaining() < 1, "wildcard subscription available");
-                builder = builder.wildcard_subscription_available(reader.get_u8())?;
+                builder = builder.wildcard_subscription_available(decode_bool(
+                    &mut reader,
+                    "wildcard subscription available",
+                )?)?;
             }
             Property::SubscriptionIdentifierAvailable => {
-                end_of_stream!(reader.remaining() < 1, "subscription identifier available");
-                builder = builder.subscription_identifier_available(reader.get_u8())?;
+                builder = builder.subscription_identifier_available(decode_bool(
+                    &mut reader,
+                    "subscription identifier available",
+                )?)?;
             }
             Property::SharedSubscriptionAvailable => {
-                end_of_stream!(reader.remaining() < 1, "shared subscription available");
-                builder = builder.shared_subscription_available(reader.get_u8())?;
+                builder = builder.shared_subscription_available(decode_bool(
+                    &mut reader,
+                    "shared subscription available",
+                )?)?;
             }
             Property::ServerKeepAlive => {
                 end_of_stream!(reader.remaining() < 2, "server keep alive");
                 builder = builder.server_keep_alive(reader.get_u16())?;
             }
-            Property::AuthenticationMethod => unimplemented!(),
-            Property::AuthenticationData => unimplemented!(),
+            Property::ResponseInformation => {
+                builder = builder.response_information(decode_utf8_string(&mut reader)?)?;
+            }
+            Property::ServerReference => {
+                builder = builder.server_reference(decode_utf8_string(&mut reader)?)?;
+            }
+            Property::AuthenticationMethod => {
+                builder = builder.authentication_method(decode_utf8_string(&mut reader)?)?;
+            }
+            Property::AuthenticationData => {
+                builder = builder.authentication_data(decode_binary_data(&mut reader)?)?;
+            }
             _ => return Err(UnacceptableProperty(property)),
         }
     }
-    Ok(builder.connack())
+    Ok(builder.build())
 }
 
-impl Encoder<ConnAckProperties> for MqttCodec {
+impl Encoder<&ConnAckProperties> for MqttCodec {
     type Error = MqttError;
 
     fn encode(
         &mut self,
-        properties: ConnAckProperties,
+        properties: &ConnAckProperties,
         writer: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.encode(properties.size(), writer)?; // properties length
@@ -126,17 +141,43 @@ impl Encoder<ConnAckProperties> for MqttCodec {
         encode_property_u16!(writer, TopicAliasMaximum, properties.topic_alias_maximum);
         encode_property_string!(writer, ReasonString, properties.reason_string);
         encode_property_user_properties!(writer, UserProperty, properties.user_properties);
+        encode_property_u8!(
+            writer,
+            WildcardSubscriptionAvailable,
+            properties.wildcard_subscription_available.map(|b| b as u8)
+        );
+        encode_property_u8!(
+            writer,
+            SubscriptionIdentifierAvailable,
+            properties
+                .subscription_identifier_available
+                .map(|b| b as u8)
+        );
+        encode_property_u8!(
+            writer,
+            SharedSubscriptionAvailable,
+            properties.shared_subscription_available.map(|b| b as u8)
+        );
+        encode_property_u16!(writer, ServerKeepAlive, properties.server_keep_alive);
+        encode_property_string!(writer, ResponseInformation, properties.response_information);
+        encode_property_string!(writer, ServerReference, properties.server_reference);
+        encode_property_string!(
+            writer,
+            AuthenticationMethod,
+            properties.authentication_method
+        );
+        encode_property_bytes!(writer, AuthenticationData, properties.authentication_data);
         Ok(())
     }
 }
 
-impl Encoder<ConnAck> for MqttCodec {
+impl Encoder<&ConnAck> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: ConnAck, writer: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, msg: &ConnAck, writer: &mut BytesMut) -> Result<(), Self::Error> {
         writer.put_u8(msg.session_present as u8); // connack flags
         writer.put_u8(msg.reason_code.into());
-        self.encode(msg.properties, writer)
+        self.encode(&msg.properties, writer)
     }
 }
 
@@ -162,6 +203,14 @@ impl PropertiesSize for ConnAckProperties {
             .iter()
             .map(|(x, y)| 5 + x.len() + y.len())
             .sum::<usize>();
+        len += check_size_of!(self, wildcard_subscription_available);
+        len += check_size_of!(self, subscription_identifier_available);
+        len += check_size_of!(self, shared_subscription_available);
+        len += check_size_of!(self, server_keep_alive);
+        len += check_size_of_string!(self, response_information);
+        len += check_size_of_string!(self, server_reference);
+        len += check_size_of_string!(self, authentication_method);
+        len += check_size_of_bytes!(self, authentication_data);
         len
     }
 }
@@ -1,16 +1,20 @@
-use std::convert::{TryFrom, TryInto};
-use std::mem::size_of_val;
+use alloc::borrow::ToOwned;
+use alloc::string::{String, ToString};
+use core::convert::{TryFrom, TryInto};
+use core::mem::size_of_val;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
-use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
+use crate::v5::codec::Encoder;
+use crate::v5::decoder::{
+    decode_binary_data, decode_bool, decode_utf8_string, decode_variable_integer,
+};
 use crate::v5::encoder::{encode_utf8_string, RemainingLength};
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::{EndOfStream, MalformedPacket, UnacceptableProperty};
 use crate::v5::error::MqttError::{UnspecifiedError, UnsupportedProtocolVersion};
 use crate::v5::property::{
-    ConnectProperties, PropertiesBuilder, PropertiesSize, Property, WillProperties,
+    ConnectProperties, ConnectPropertiesBuilder, PropertiesSize, Property, WillProperties,
 };
 use crate::v5::string::MqttString;
 use crate::v5::types::{Connect, MqttCodec, Will, MQTT, VERSION};
@@ -19,13 +23,9 @@ impl TryFrom<Bytes> for Connect {
     type Error = MqttError;
 
     fn try_from(mut reader: Bytes) -> Result<Self, Self::Error> {
-        if let Some(protocol) = decode_utf8_string(&mut reader)? {
-            let protocol = protocol.try_into()?;
-            if MQTT != protocol {
-                return Err(UnsupportedProtocolVersion(protocol));
-            }
-        } else {
-            return Err(UnsupportedProtocolVersion(String::new()));
+        let protocol: String = decode_utf8_string(&mut reader)?.try_into()?;
+        if MQTT != protocol {
+            return Err(UnsupportedProtocolVersion(protocol));
         }
         end_of_stream!(reader.remaining() < 4, "connect version");
         let version = reader.get_u8();
@@ -52,13 +52,16 @@ impl TryFrom<Bytes> for Connect {
         let properties_length = decode_variable_integer(&mut reader)? as usize;
         let properties = ConnectProperties::try_from(reader.split_to(properties_length))?;
 
-        let client_identifier = decode_utf8_string(&mut reader)?;
+        // a zero length client identifier asks the server to assign one
+        let client_identifier = Some(decode_utf8_string(&mut reader)?).filter(|id| !id.is_empty());
 
         let will = if will_flag {
             let will_properties_length = decode_variable_integer(&mut reader)? as usize;
             let properties = WillProperties::try_from(reader.split_to(will_properties_length))?;
-            let topic = decode_utf8_string(&mut reader)?
-                .ok_or_else(|| UnspecifiedError("will topic is missing".to_owned()))?;
+            let topic = decode_utf8_string(&mut reader)?;
+            if topic.is_empty() {
+                return Err(UnspecifiedError("will topic is missing".to_owned()));
+            }
             let payload_len = reader.get_u16() as usize;
             let payload = reader.split_to(payload_len);
 
@@ -74,7 +77,7 @@ impl TryFrom<Bytes> for Connect {
         };
 
         let username = if username_flag {
-            decode_utf8_string(&mut reader)?
+            Some(decode_utf8_string(&mut reader)?)
         } else {
             None
         };
@@ -103,7 +106,7 @@ impl TryFrom<Bytes> for ConnectProperties {
     type Error = MqttError;
 
     fn try_from(mut reader: Bytes) -> Result<Self, Self::Error> {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = ConnectPropertiesBuilder::default();
         while reader.has_remaining() {
             let id = decode_variable_integer(&mut reader)?;
             let property = id.try_into()?;
@@ -125,40 +128,42 @@ impl TryFrom<Bytes> for ConnectProperties {
                     builder = builder.topic_alias_maximum(reader.get_u16())?;
                 }
                 Property::RequestResponseInformation => {
-                    end_of_stream!(reader.remaining() < 1, "request response information");
-                    builder = builder.request_response_information(reader.get_u8())?;
+                    builder = builder.request_response_information(decode_bool(
+                        &mut reader,
+                        "request response information",
+                    )?)?;
                 }
                 Property::RequestProblemInformation => {
-                    end_of_stream!(reader.remaining() < 1, "request problem information");
-                    builder = builder.request_problem_information(reader.get_u8())?;
+                    builder = builder.request_problem_information(decode_bool(
+                        &mut reader,
+                        "request problem information",
+                    )?)?;
                 }
                 Property::UserProperty => {
-                    let user_property = (
+                    builder = builder.user_property((
                         decode_utf8_string(&mut reader)?,
                         decode_utf8_string(&mut reader)?,
-                    );
-                    if let (Some(key), Some(value)) = user_property {
-                        builder = builder.user_property((key, value));
-                    }
+                    ));
                 }
                 Property::AuthenticationMethod => {
-                    if let Some(authentication_method) = decode_utf8_string(&mut reader)? {
-                        builder = builder.authentication_method(authentication_method)?
-                    }
+                    builder = builder.authentication_method(decode_utf8_string(&mut reader)?)?;
+                }
+                Property::AuthenticationData => {
+                    builder = builder.authentication_data(decode_binary_data(&mut reader)?)?;
                 }
                 _ => return Err(UnacceptableProperty(property)),
             }
         }
-        Ok(builder.connect())
+        Ok(builder.build())
     }
 }
 
-impl Encoder<ConnectProperties> for MqttCodec {
+impl Encoder<&ConnectProperties> for MqttCodec {
     type Error = MqttError;
 
     fn encode(
         &mut self,
-        properties: ConnectProperties,
+        properties: &ConnectProperties,
         writer: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.encode(properties.size(), writer)?;
@@ -186,27 +191,31 @@ impl Encoder<ConnectProperties> for MqttCodec {
             AuthenticationMethod,
             properties.authentication_method
         );
+        encode_property_bytes!(writer, AuthenticationData, properties.authentication_data);
         Ok(())
     }
 }
 
-impl Encoder<Connect> for MqttCodec {
+impl Encoder<&Connect> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: Connect, writer: &mut BytesMut) -> Result<(), Self::Error> {
-        self.encode(MqttString::from(MQTT), writer)?;
+    fn encode(&mut self, msg: &Connect, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&MqttString::from(MQTT), writer)?;
         writer.put_u8(VERSION);
         writer.put_u8(msg.get_flags());
         writer.put_u16(msg.keep_alive);
-        self.encode(msg.properties, writer)?;
-        self.encode(msg.client_identifier.unwrap_or_default(), writer)?;
-        if let Some(will) = msg.will {
+        self.encode(&msg.properties, writer)?;
+        match &msg.client_identifier {
+            Some(client_identifier) => self.encode(client_identifier, writer)?,
+            None => self.encode(&MqttString::default(), writer)?,
+        }
+        if let Some(will) = &msg.will {
             self.encode(will, writer)?;
         }
-        if let Some(username) = msg.username {
+        if let Some(username) = &msg.username {
             self.encode(username, writer)?;
         }
-        if let Some(password) = msg.password {
+        if let Some(password) = &msg.password {
             writer.put_u16(password.len() as u16);
             self.encode(password, writer)?;
         }
@@ -245,6 +254,7 @@ impl PropertiesSize for ConnectProperties {
             .map(|(x, y)| 5 + x.len() + y.len())
             .sum::<usize>();
         len += check_size_of_string!(self, authentication_method);
+        len += check_size_of_bytes!(self, authentication_data);
         len
     }
 }
@@ -1,15 +1,17 @@
-use std::convert::TryFrom;
+use alloc::format;
+use core::convert::TryFrom;
 
 use bytes::{Buf, Bytes, BytesMut};
-use tokio_util::codec::Decoder;
 use tracing::{instrument, trace};
 
+use crate::v5::codec::Decoder;
 use crate::v5::connack::decode_connack;
 use crate::v5::disconnect::decode_disconnect;
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::MalformedVariableInteger;
-use crate::v5::error::MqttError::{EndOfStream, PacketTooLarge};
+use crate::v5::error::MqttError::{EndOfStream, PacketTooLarge, ProtocolError};
 use crate::v5::publish::decode_publish;
+use crate::v5::reason::validate_reason_codes;
 use crate::v5::string::MqttString;
 use crate::v5::subscribe::decode_subscribe;
 use crate::v5::types::{
@@ -17,7 +19,7 @@ use crate::v5::types::{
 };
 use crate::v5::unsubscribe::decode_unsubscribe;
 
-const MIN_FIXED_HEADER_LEN: usize = 2;
+pub(crate) const MIN_FIXED_HEADER_LEN: usize = 2;
 
 impl Decoder for MqttCodec {
     type Item = ControlPacket;
@@ -58,7 +60,7 @@ impl Decoder for MqttCodec {
                 }
                 self.part = PacketPart::FixedHeader;
                 let packet = reader.split_to(remaining).freeze();
-                match packet_type {
+                let packet = match packet_type {
                     PacketType::Connect => Connect::try_from(packet)
                         .map(|connect| Some(ControlPacket::Connect(connect))),
                     PacketType::ConnAck => decode_connack(packet),
@@ -78,19 +80,68 @@ impl Decoder for MqttCodec {
                         SubAck::try_from(packet).map(|s| Some(ControlPacket::SubAck(s)))
                     }
                     PacketType::UnSubscribe => decode_unsubscribe(packet),
-                    PacketType::UnSubAck => unimplemented!(),
+                    PacketType::UnSubAck => {
+                        SubAck::try_from(packet).map(|s| Some(ControlPacket::UnSubAck(s)))
+                    }
                     PacketType::PingReq => Ok(Some(ControlPacket::PingReq)),
                     PacketType::PingResp => Ok(Some(ControlPacket::PingResp)),
                     PacketType::Disconnect => decode_disconnect(packet),
                     PacketType::Auth => {
                         Auth::try_from(packet).map(|a| Some(ControlPacket::Auth(a)))
                     }
+                }?;
+                if let Some(packet) = &packet {
+                    validate_reason_codes(packet)?;
+                }
+                if self.validate_payload_format {
+                    match &packet {
+                        Some(ControlPacket::Publish(publish)) => {
+                            publish.payload_str()?;
+                        }
+                        Some(ControlPacket::Connect(Connect {
+                            will: Some(will), ..
+                        })) => {
+                            will.payload_str()?;
+                        }
+                        _ => {}
+                    }
                 }
+                Ok(packet)
             }
         }
     }
 }
 
+/// Decodes one packet if it is entirely in `reader`, leaving `reader` untouched otherwise.
+pub fn decode(reader: &mut BytesMut) -> Result<Option<ControlPacket>, MqttError> {
+    match peek_fixed_header(reader)? {
+        Some((_, remaining, header_length)) if header_length + remaining <= reader.len() => {
+            MqttCodec::new(None).decode(reader)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Packet type, remaining length and fixed header length, without consuming the buffer.
+pub(crate) fn peek_fixed_header(
+    reader: &BytesMut,
+) -> Result<Option<(PacketType, usize, usize)>, MqttError> {
+    if reader.len() < MIN_FIXED_HEADER_LEN {
+        return Ok(None);
+    }
+    let packet_type = PacketType::try_from(reader[0])?;
+    let mut peek = &reader[1..];
+    match decode_variable_integer(&mut peek) {
+        Ok(remaining) => Ok(Some((
+            packet_type,
+            remaining as usize,
+            reader.len() - peek.len(),
+        ))),
+        Err(EndOfStream(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
 pub fn decode_variable_integer<T>(reader: &mut T) -> Result<u32, MqttError>
 where
     T: Buf,
@@ -116,15 +167,12 @@ where
     Ok(value)
 }
 
-pub fn decode_utf8_string(reader: &mut Bytes) -> Result<Option<MqttString>, MqttError> {
+/// UTF-8 encoded string, which may be empty.
+pub fn decode_utf8_string(reader: &mut Bytes) -> Result<MqttString, MqttError> {
     if reader.remaining() >= 2 {
         let len = reader.get_u16() as usize;
         if reader.remaining() >= len {
-            if len > 0 {
-                Ok(Some(MqttString::from(reader.split_to(len))))
-            } else {
-                Ok(None)
-            }
+            Ok(MqttString::from(reader.split_to(len)))
         } else {
             Err(EndOfStream("decode_utf8_string"))
         }
@@ -132,3 +180,58 @@ pub fn decode_utf8_string(reader: &mut Bytes) -> Result<Option<MqttString>, Mqtt
         Err(EndOfStream("decode_utf8_string"))
     }
 }
+
+/// Byte property holding a boolean, any value other than 0 or 1 is a Protocol Error.
+pub fn decode_bool(reader: &mut Bytes, name: &'static str) -> Result<bool, MqttError> {
+    end_of_stream!(reader.remaining() < 1, name);
+    match reader.get_u8() {
+        0 => Ok(false),
+        1 => Ok(true),
+        value => Err(ProtocolError(format!("{} value {}", name, value))),
+    }
+}
+
+pub fn decode_binary_data(reader: &mut Bytes) -> Result<Bytes, MqttError> {
+    end_of_stream!(reader.remaining() < 2, "decode_binary_data len");
+    let len = reader.get_u16() as usize;
+    end_of_stream!(reader.remaining() < len, "decode_binary_data");
+    Ok(reader.split_to(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::*;
+
+    use super::*;
+    use crate::v5::encoder::encode;
+
+    #[test]
+    fn test_decode_partial() {
+        let mut buf = BytesMut::new();
+        encode(&ControlPacket::PingReq, &mut buf).unwrap();
+        let mut partial = buf.split_to(1);
+        assert_none!(assert_ok!(decode(&mut partial)));
+        assert_eq!(1, partial.len());
+        partial.unsplit(buf);
+        assert_eq!(
+            ControlPacket::PingReq,
+            assert_some!(assert_ok!(decode(&mut partial)))
+        );
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn test_decode_bool() {
+        let mut reader = Bytes::from_static(&[0, 1, 2]);
+        assert!(!assert_ok!(decode_bool(&mut reader, "retain available")));
+        assert!(assert_ok!(decode_bool(&mut reader, "retain available")));
+        assert_matches!(
+            decode_bool(&mut reader, "retain available"),
+            Err(ProtocolError(_))
+        );
+        assert_matches!(
+            decode_bool(&mut reader, "retain available"),
+            Err(EndOfStream(_))
+        );
+    }
+}
@@ -1,15 +1,17 @@
-use std::convert::TryInto;
-use std::mem::size_of_val;
+use core::convert::TryInto;
+use core::mem::size_of_val;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
+use crate::v5::codec::Encoder;
 use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
 use crate::v5::encoder::{encode_utf8_string, RemainingLength};
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::EndOfStream;
 use crate::v5::error::MqttError::UnacceptableProperty;
-use crate::v5::property::{DisconnectProperties, PropertiesBuilder, PropertiesSize, Property};
+use crate::v5::property::{
+    DisconnectProperties, DisconnectPropertiesBuilder, PropertiesSize, Property,
+};
 use crate::v5::types::{ControlPacket, Disconnect, MqttCodec, ReasonCode};
 
 pub fn decode_disconnect(mut reader: Bytes) -> Result<Option<ControlPacket>, MqttError> {
@@ -31,7 +33,7 @@ pub fn decode_disconnect(mut reader: Bytes) -> Result<Option<ControlPacket>, Mqt
 }
 
 fn decode_disconnect_properties(mut reader: Bytes) -> Result<DisconnectProperties, MqttError> {
-    let mut builder = PropertiesBuilder::default();
+    let mut builder = DisconnectPropertiesBuilder::default();
     while reader.has_remaining() {
         let id = decode_variable_integer(&mut reader)?;
         let property = id.try_into()?;
@@ -41,16 +43,13 @@ fn decode_disconnect_properties(mut reader: Bytes) -> Result<DisconnectPropertie
                 builder = builder.session_expire_interval(reader.get_u32())?;
             }
             Property::UserProperty => {
-                let user_property = (
+                builder = builder.user_property((
                     decode_utf8_string(&mut reader)?,
                     decode_utf8_string(&mut reader)?,
-                );
-                if let (Some(key), Some(value)) = user_property {
-                    builder = builder.user_property((key, value));
-                }
+                ));
             }
             Property::ReasonString => {
-                builder = builder.response_topic(decode_utf8_string(&mut reader)?)?;
+                builder = builder.reason_string(decode_utf8_string(&mut reader)?)?;
             }
             Property::ServerReference => {
                 builder = builder.server_reference(decode_utf8_string(&mut reader)?)?;
@@ -58,7 +57,7 @@ fn decode_disconnect_properties(mut reader: Bytes) -> Result<DisconnectPropertie
             _ => return Err(UnacceptableProperty(property)),
         }
     }
-    Ok(builder.disconnect())
+    Ok(builder.build())
 }
 
 impl RemainingLength for Disconnect {
@@ -68,12 +67,12 @@ impl RemainingLength for Disconnect {
     }
 }
 
-impl Encoder<Disconnect> for MqttCodec {
+impl Encoder<&Disconnect> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: Disconnect, writer: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, msg: &Disconnect, writer: &mut BytesMut) -> Result<(), Self::Error> {
         writer.put_u8(msg.reason_code.into());
-        self.encode(msg.properties, writer)
+        self.encode(&msg.properties, writer)
     }
 }
 
@@ -91,12 +90,12 @@ impl PropertiesSize for DisconnectProperties {
     }
 }
 
-impl Encoder<DisconnectProperties> for MqttCodec {
+impl Encoder<&DisconnectProperties> for MqttCodec {
     type Error = MqttError;
 
     fn encode(
         &mut self,
-        properties: DisconnectProperties,
+        properties: &DisconnectProperties,
         writer: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.encode(properties.size(), writer)?;
@@ -112,3 +111,50 @@ impl Encoder<DisconnectProperties> for MqttCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use claims::*;
+
+    use super::*;
+    use crate::v5::string::MqttString;
+    use crate::v5::types::ReasonCode;
+
+    #[test]
+    fn test_disconnect_reason_string() {
+        let properties = DisconnectPropertiesBuilder::default()
+            .reason_string(MqttString::from("shutting down"))
+            .unwrap()
+            .build();
+        let disconnect = ControlPacket::Disconnect(Disconnect {
+            reason_code: ReasonCode::ServerShuttingDown,
+            properties,
+        });
+        let bytes = assert_ok!(disconnect.to_bytes());
+        match assert_ok!(ControlPacket::from_bytes(&bytes)) {
+            ControlPacket::Disconnect(Disconnect { properties, .. }) => {
+                assert_eq!(
+                    Some(MqttString::from("shutting down")),
+                    properties.reason_string
+                );
+                assert_none!(properties.server_reference);
+            }
+            packet => panic!("unexpected: {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn test_disconnect_empty_strings() {
+        let properties = DisconnectPropertiesBuilder::default()
+            .reason_string(MqttString::from(""))
+            .unwrap()
+            .user_property((MqttString::from("key"), MqttString::from("")))
+            .build();
+        let disconnect = ControlPacket::Disconnect(Disconnect {
+            reason_code: ReasonCode::Success,
+            properties,
+        });
+        let bytes = assert_ok!(disconnect.to_bytes());
+        assert_eq!(disconnect, assert_ok!(ControlPacket::from_bytes(&bytes)));
+    }
+}
@@ -1,9 +1,10 @@
 use bytes::{BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
+use crate::v5::codec::Encoder;
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::EndOfStream;
 use crate::v5::property::PropertiesSize;
+use crate::v5::reason::validate_reason_codes;
 use crate::v5::string::MqttString;
 use crate::v5::types::{ControlPacket, MqttCodec, PacketType};
 
@@ -15,6 +16,15 @@ impl Encoder<ControlPacket> for MqttCodec {
     type Error = MqttError;
 
     fn encode(&mut self, packet: ControlPacket, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&packet, writer)
+    }
+}
+
+impl Encoder<&ControlPacket> for MqttCodec {
+    type Error = MqttError;
+
+    fn encode(&mut self, packet: &ControlPacket, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        validate_reason_codes(packet)?;
         match packet {
             ControlPacket::Connect(connect) => {
                 encode_fixed_header(writer, PacketType::Connect, connect.remaining_length())?;
@@ -90,12 +100,20 @@ impl Encoder<ControlPacket> for MqttCodec {
                 )?;
                 self.encode(disconnect, writer)?
             }
-            ControlPacket::Auth(_auth) => unimplemented!(),
+            ControlPacket::Auth(auth) => {
+                encode_fixed_header(writer, PacketType::Auth, auth.remaining_length())?;
+                self.encode(auth, writer)?
+            }
         };
         Ok(())
     }
 }
 
+/// Encodes `packet` at the end of `writer`.
+pub fn encode(packet: &ControlPacket, writer: &mut BytesMut) -> Result<(), MqttError> {
+    MqttCodec::new(None).encode(packet, writer)
+}
+
 impl Encoder<usize> for MqttCodec {
     type Error = MqttError;
 
@@ -118,12 +136,12 @@ impl Encoder<usize> for MqttCodec {
     }
 }
 
-impl Encoder<Bytes> for MqttCodec {
+impl Encoder<&Bytes> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, b: Bytes, writer: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, b: &Bytes, writer: &mut BytesMut) -> Result<(), Self::Error> {
         end_of_stream!(writer.capacity() < b.len(), "encode bytes");
-        writer.put(b);
+        writer.put_slice(b);
         Ok(())
     }
 }
@@ -149,11 +167,11 @@ impl PropertiesSize for usize {
     }
 }
 
-pub fn encode_utf8_string(writer: &mut BytesMut, s: MqttString) -> Result<(), MqttError> {
+pub fn encode_utf8_string(writer: &mut BytesMut, s: &MqttString) -> Result<(), MqttError> {
     end_of_stream!(writer.capacity() < 2, "encode utf2 string len");
     writer.put_u16(s.len() as u16);
     end_of_stream!(writer.capacity() < s.len(), "encode utf2 string");
-    writer.put(s);
+    writer.put_slice(s);
     Ok(())
 }
 
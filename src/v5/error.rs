@@ -1,5 +1,6 @@
+use alloc::string::{FromUtf8Error, String};
+#[cfg(feature = "std")]
 use std::io;
-use std::string::FromUtf8Error;
 
 use thiserror::Error;
 
@@ -8,11 +9,13 @@ use crate::v5::types::{QoS, ReasonCode};
 
 #[derive(Error, Debug)]
 pub enum MqttError {
+    #[cfg(feature = "std")]
     #[error(transparent)]
     Io {
         #[from]
         source: io::Error,
     },
+    #[cfg(feature = "std")]
     #[error(transparent)]
     BincodeErrorKind {
         #[from]
@@ -77,6 +80,12 @@ pub enum MqttError {
     #[error("Property {0} has empty value")]
     EmptyPropertyValue(&'static str),
 
+    #[error("Payload format invalid")]
+    PayloadFormatInvalid,
+
+    #[error("Protocol error: {0}")]
+    ProtocolError(String),
+
     #[error("Not Authorized")]
     NotAuthorized,
     #[error("Bad username or password")]
@@ -94,6 +103,7 @@ impl From<MqttError> for ReasonCode {
             MqttError::ServerBusy => ReasonCode::ServerBusy,
             MqttError::TopicFilterInvalid(_) => ReasonCode::TopicFilterInvalid,
             MqttError::TopicNameInvalid(_) => ReasonCode::TopicNameInvalid,
+            #[cfg(feature = "std")]
             MqttError::Io { .. } => ReasonCode::ImplementationSpecificError,
             MqttError::EndOfStream(_) => ReasonCode::MalformedPacket,
             MqttError::MalformedVariableInteger(_) => ReasonCode::MalformedPacket,
@@ -106,13 +116,15 @@ impl From<MqttError> for ReasonCode {
             MqttError::UndefinedPacketIdentifier(_) => ReasonCode::ProtocolError,
             MqttError::MoreThanOnceProperty => ReasonCode::ProtocolError,
             MqttError::EmptyPropertyValue(_) => ReasonCode::ProtocolError,
+            #[cfg(feature = "std")]
             MqttError::BincodeErrorKind { .. } => ReasonCode::ImplementationSpecificError,
             MqttError::FromUtf8Error { .. } => ReasonCode::MalformedPacket,
             MqttError::PacketTooLarge => ReasonCode::PacketTooLarge,
             MqttError::NotAuthorized => ReasonCode::NotAuthorized,
             MqttError::BadUserNameOrPassword => ReasonCode::BadUserNameOrPassword,
             MqttError::MalformedPacket => ReasonCode::MalformedPacket,
+            MqttError::PayloadFormatInvalid => ReasonCode::PayloadFormatInvalid,
+            MqttError::ProtocolError(_) => ReasonCode::ProtocolError,
         }
     }
 }
-
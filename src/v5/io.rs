@@ -0,0 +1,152 @@
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "std")]
+use bytes::BufMut;
+use bytes::{Bytes, BytesMut};
+
+use crate::v5::decoder::decode;
+#[cfg(feature = "std")]
+use crate::v5::decoder::peek_fixed_header;
+use crate::v5::encoder::{encode, RemainingLength};
+use crate::v5::error::MqttError;
+#[cfg(feature = "std")]
+use crate::v5::error::MqttError::PacketTooLarge;
+use crate::v5::error::MqttError::{EndOfStream, MalformedPacket};
+use crate::v5::property::PropertiesSize;
+use crate::v5::types::ControlPacket;
+
+impl RemainingLength for ControlPacket {
+    fn remaining_length(&self) -> usize {
+        match self {
+            ControlPacket::Connect(connect) => connect.remaining_length(),
+            ControlPacket::ConnAck(connack) => connack.remaining_length(),
+            ControlPacket::Publish(publish) => publish.remaining_length(),
+            ControlPacket::PubAck(response)
+            | ControlPacket::PubRec(response)
+            | ControlPacket::PubRel(response)
+            | ControlPacket::PubComp(response) => response.remaining_length(),
+            ControlPacket::Subscribe(subscribe) => subscribe.remaining_length(),
+            ControlPacket::SubAck(response) | ControlPacket::UnSubAck(response) => {
+                response.remaining_length()
+            }
+            ControlPacket::UnSubscribe(unsubscribe) => unsubscribe.remaining_length(),
+            ControlPacket::PingReq | ControlPacket::PingResp => 0,
+            ControlPacket::Disconnect(disconnect) => disconnect.remaining_length(),
+            ControlPacket::Auth(auth) => auth.remaining_length(),
+        }
+    }
+}
+
+impl ControlPacket {
+    /// Size of the encoded packet, fixed header included.
+    pub fn encoded_len(&self) -> usize {
+        let remaining_length = self.remaining_length();
+        1 + remaining_length.size() + remaining_length
+    }
+
+    pub fn to_bytes(&self) -> Result<Bytes, MqttError> {
+        let mut writer = BytesMut::with_capacity(self.encoded_len());
+        encode(self, &mut writer)?;
+        Ok(writer.freeze())
+    }
+
+    /// Decodes a packet from `bytes`, which must hold exactly one complete packet.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ControlPacket, MqttError> {
+        let mut reader = BytesMut::from(bytes);
+        let packet = decode(&mut reader)?.ok_or(EndOfStream("from_bytes"))?;
+        ensure!(reader.is_empty(), MalformedPacket);
+        Ok(packet)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), MqttError> {
+        writer.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Reads exactly one packet from `reader`, blocking until it is complete. A packet larger
+    /// than `maximum_packet_size` is rejected with `PacketTooLarge` before its buffer is
+    /// allocated, as the remaining length comes from the peer.
+    #[cfg(feature = "std")]
+    pub fn read_from<R: Read>(
+        reader: &mut R,
+        maximum_packet_size: Option<u32>,
+    ) -> Result<ControlPacket, MqttError> {
+        let mut buf = BytesMut::with_capacity(5);
+        let mut byte = [0u8; 1];
+        // packet type and up to four bytes of remaining length
+        loop {
+            reader.read_exact(&mut byte)?;
+            buf.put_u8(byte[0]);
+            if buf.len() > 1 && (byte[0] & 0x80 == 0 || buf.len() == 5) {
+                break;
+            }
+        }
+        let (_, remaining, header_length) = peek_fixed_header(&buf)?.ok_or(MalformedPacket)?;
+        if let Some(maximum_packet_size) = maximum_packet_size {
+            ensure!(
+                header_length + remaining <= maximum_packet_size as usize,
+                PacketTooLarge
+            );
+        }
+        buf.resize(header_length + remaining, 0);
+        reader.read_exact(&mut buf[header_length..])?;
+        decode(&mut buf)?.ok_or(MalformedPacket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use claims::*;
+
+    use super::*;
+    use crate::v5::string::MqttString;
+    use crate::v5::types::{Publish, QoS};
+
+    #[test]
+    fn test_write_read() {
+        let publish = ControlPacket::Publish(Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic_name: MqttString::from("topic"),
+            packet_identifier: Some(1),
+            properties: Default::default(),
+            payload: Bytes::from(vec![0u8; 200]),
+        });
+        let mut writer = vec![];
+        assert_ok!(publish.write_to(&mut writer));
+        assert_ok!(ControlPacket::PingReq.write_to(&mut writer));
+        assert_eq!(publish.encoded_len() + 2, writer.len());
+
+        let mut reader = Cursor::new(writer.clone());
+        assert_eq!(
+            publish,
+            assert_ok!(ControlPacket::read_from(&mut reader, None))
+        );
+        assert_eq!(
+            ControlPacket::PingReq,
+            assert_ok!(ControlPacket::read_from(&mut reader, Some(2)))
+        );
+        assert_err!(ControlPacket::read_from(&mut reader, None));
+
+        let mut reader = Cursor::new(writer);
+        assert_matches!(
+            ControlPacket::read_from(&mut reader, Some(100)),
+            Err(PacketTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let bytes = assert_ok!(ControlPacket::PingResp.to_bytes());
+        assert_eq!(
+            ControlPacket::PingResp,
+            assert_ok!(ControlPacket::from_bytes(&bytes))
+        );
+        assert_err!(ControlPacket::from_bytes(&bytes[..1]));
+    }
+}
@@ -3,15 +3,28 @@ pub mod types;
 #[macro_use]
 pub mod property;
 mod auth;
+pub mod codec;
+#[cfg(test)]
+mod conformance;
 mod connack;
 mod connect;
 mod decoder;
 mod disconnect;
 pub mod encoder;
 pub mod error;
+mod io;
 mod publish;
 mod pubres;
+pub mod reason;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy;
+pub mod stream;
 pub mod string;
 mod subscribe;
+pub mod topic;
 mod unsubscribe;
 mod will;
+
+pub use decoder::decode;
+pub use encoder::encode;
+pub use will::WillBuilder;
@@ -1,23 +1,43 @@
-use std::convert::{TryFrom, TryInto};
-use std::fmt;
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+use core::fmt;
 
 use bytes::Bytes;
 
 use crate::v5::error::MqttError;
+use crate::v5::error::MqttError::MalformedPropertyType;
 use crate::v5::error::MqttError::MoreThanOnceProperty;
-use crate::v5::error::MqttError::{EmptyPropertyValue, MalformedPropertyType};
 use crate::v5::string::MqttString;
 use crate::v5::types::QoS;
 
 macro_rules! check_and_set {
     ($self:ident, $property:ident, $value: expr) => {
-        match $self.$property.replace($value) {
+        match $self.properties.$property.replace($value) {
             None => Ok($self),
             Some(_) => Err(MoreThanOnceProperty),
         }
     };
 }
 
+macro_rules! property_setter {
+    (user_property) => {
+        pub fn user_property(mut self, value: (MqttString, MqttString)) -> Self {
+            self.properties.user_properties.push(value);
+            self
+        }
+    };
+    ($property:ident: QoS) => {
+        pub fn $property(mut self, value: u8) -> Result<Self, MqttError> {
+            check_and_set!(self, $property, value.try_into()?)
+        }
+    };
+    ($property:ident: $type:ty) => {
+        pub fn $property(mut self, value: $type) -> Result<Self, MqttError> {
+            check_and_set!(self, $property, value)
+        }
+    };
+}
+
 macro_rules! check_size_of {
     ($self:ident, $property:ident) => {
         match &$self.$property {
@@ -80,20 +100,20 @@ macro_rules! encode_property_u32 {
 
 macro_rules! encode_property_bytes {
     ($writer:ident, $property:ident, $value:expr) => {
-        if let Some(value) = $value {
+        if let Some(value) = &$value {
             end_of_stream!($writer.capacity() < 1, "$value id");
             $writer.put_u8(Property::$property as u8);
             end_of_stream!($writer.capacity() < 2, "$value");
             $writer.put_u16(value.len() as u16);
             end_of_stream!($writer.capacity() < value.len(), "$value");
-            $writer.put(value);
+            $writer.put_slice(value);
         }
     };
 }
 
 macro_rules! encode_property_string {
     ($writer:ident, $property:ident, $value:expr) => {
-        if let Some(value) = $value {
+        if let Some(value) = &$value {
             end_of_stream!($writer.capacity() < 1, "$value id");
             $writer.put_u8(Property::$property as u8);
             end_of_stream!($writer.capacity() < value.len(), "$value");
@@ -114,7 +134,7 @@ macro_rules! encode_property_variable_integer {
 
 macro_rules! encode_property_user_properties {
     ($writer:ident, $property:ident, $value:expr) => {
-        for (first, second) in $value {
+        for (first, second) in &$value {
             end_of_stream!($writer.capacity() < 1, "$value");
             $writer.put_u8(Property::$property as u8);
             encode_utf8_string($writer, first)?;
@@ -195,7 +215,8 @@ impl TryFrom<u32> for Property {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WillProperties {
     pub will_delay_interval: u32,
     pub payload_format_indicator: Option<bool>,
@@ -206,7 +227,8 @@ pub struct WillProperties {
     pub user_properties: Vec<(MqttString, MqttString)>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnectProperties {
     pub session_expire_interval: Option<u32>,
     pub receive_maximum: Option<u16>,
@@ -219,7 +241,8 @@ pub struct ConnectProperties {
     pub authentication_data: Option<Bytes>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnAckProperties {
     pub session_expire_interval: Option<u32>,
     pub receive_maximum: Option<u16>,
@@ -240,7 +263,8 @@ pub struct ConnAckProperties {
     pub authentication_data: Option<Bytes>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublishProperties {
     pub payload_format_indicator: Option<bool>,
     pub message_expire_interval: Option<u32>,
@@ -248,17 +272,20 @@ pub struct PublishProperties {
     pub response_topic: Option<MqttString>,
     pub correlation_data: Option<Bytes>,
     pub user_properties: Vec<(MqttString, MqttString)>,
-    pub subscription_identifier: Option<u32>,
+    /// One for each subscription of the client matching a PUBLISH sent by the server
+    pub subscription_identifiers: Vec<u32>,
     pub content_type: Option<MqttString>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ResponseProperties {
     pub reason_string: Option<MqttString>,
     pub user_properties: Vec<(MqttString, MqttString)>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DisconnectProperties {
     pub session_expire_interval: Option<u32>,
     pub reason_string: Option<MqttString>,
@@ -266,18 +293,21 @@ pub struct DisconnectProperties {
     pub server_reference: Option<MqttString>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubscribeProperties {
     pub subscription_identifier: Option<u32>,
     pub user_properties: Vec<(MqttString, MqttString)>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnSubscribeProperties {
     pub user_properties: Vec<(MqttString, MqttString)>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AuthProperties {
     pub authentication_method: Option<MqttString>,
     pub authentication_data: Option<Bytes>,
@@ -285,348 +315,179 @@ pub struct AuthProperties {
     pub user_properties: Vec<(MqttString, MqttString)>,
 }
 
-impl Default for ConnAckProperties {
-    fn default() -> Self {
-        ConnAckProperties {
-            session_expire_interval: None,
-            receive_maximum: None,
-            maximum_qos: None,
-            retain_available: None,
-            maximum_packet_size: None,
-            assigned_client_identifier: None,
-            topic_alias_maximum: None,
-            reason_string: None,
-            user_properties: vec![],
-            wildcard_subscription_available: None,
-            subscription_identifier_available: None,
-            shared_subscription_available: None,
-            server_keep_alive: None,
-            response_information: None,
-            server_reference: None,
-            authentication_method: None,
-            authentication_data: None,
-        }
-    }
+#[derive(Clone, Debug, Default)]
+pub struct WillPropertiesBuilder {
+    will_delay_interval: Option<u32>,
+    properties: WillProperties,
 }
 
-impl Default for PublishProperties {
-    fn default() -> Self {
-        PublishProperties {
-            payload_format_indicator: None,
-            message_expire_interval: None,
-            topic_alias: None,
-            response_topic: None,
-            correlation_data: None,
-            user_properties: vec![],
-            subscription_identifier: None,
-            content_type: None,
+impl WillPropertiesBuilder {
+    pub fn will_delay_interval(mut self, value: u32) -> Result<Self, MqttError> {
+        match self.will_delay_interval.replace(value) {
+            None => Ok(self),
+            Some(_) => Err(MoreThanOnceProperty),
         }
     }
-}
+    property_setter!(payload_format_indicator: bool);
+    property_setter!(message_expire_interval: u32);
+    property_setter!(content_type: MqttString);
+    property_setter!(response_topic: MqttString);
+    property_setter!(correlation_data: Bytes);
+    property_setter!(user_property);
 
-impl Default for ResponseProperties {
-    fn default() -> Self {
-        ResponseProperties {
-            reason_string: None,
-            user_properties: vec![],
+    pub fn build(self) -> WillProperties {
+        WillProperties {
+            will_delay_interval: self.will_delay_interval.unwrap_or(0),
+            ..self.properties
         }
     }
 }
 
-impl Default for DisconnectProperties {
-    fn default() -> Self {
-        DisconnectProperties {
-            session_expire_interval: None,
-            reason_string: None,
-            user_properties: vec![],
-            server_reference: None,
-        }
-    }
+#[derive(Clone, Debug, Default)]
+pub struct ConnectPropertiesBuilder {
+    properties: ConnectProperties,
 }
 
-impl Default for SubscribeProperties {
-    fn default() -> Self {
-        SubscribeProperties {
-            subscription_identifier: None,
-            user_properties: vec![],
-        }
+impl ConnectPropertiesBuilder {
+    property_setter!(session_expire_interval: u32);
+    property_setter!(receive_maximum: u16);
+    property_setter!(maximum_packet_size: u32);
+    property_setter!(topic_alias_maximum: u16);
+    property_setter!(request_response_information: bool);
+    property_setter!(request_problem_information: bool);
+    property_setter!(user_property);
+    property_setter!(authentication_method: MqttString);
+    property_setter!(authentication_data: Bytes);
+
+    pub fn build(self) -> ConnectProperties {
+        self.properties
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct PropertiesBuilder {
-    payload_format_indicator: Option<bool>,
-    message_expire_interval: Option<u32>,
-    content_type: Option<MqttString>,
-    response_topic: Option<MqttString>,
-    correlation_data: Option<Bytes>,
-    subscription_identifier: Option<u32>,
-    session_expire_interval: Option<u32>,
-    assigned_client_identifier: Option<MqttString>,
-    server_keep_alive: Option<u16>,
-    authentication_method: Option<MqttString>,
-    authentication_data: Option<Bytes>,
-    request_problem_information: Option<bool>,
-    will_delay_interval: Option<u32>,
-    request_response_information: Option<bool>,
-    response_information: Option<MqttString>,
-    server_reference: Option<MqttString>,
-    reason_string: Option<MqttString>,
-    receive_maximum: Option<u16>,
-    topic_alias_maximum: Option<u16>,
-    topic_alias: Option<u16>,
-    maximum_qos: Option<QoS>,
-    retain_available: Option<bool>,
-    user_properties: Vec<(MqttString, MqttString)>,
-    maximum_packet_size: Option<u32>,
-    wildcard_subscription_available: Option<bool>,
-    subscription_identifier_available: Option<bool>,
-    shared_subscription_available: Option<bool>,
-}
-
-impl Default for PropertiesBuilder {
-    fn default() -> Self {
-        PropertiesBuilder {
-            session_expire_interval: None,
-            assigned_client_identifier: None,
-            receive_maximum: None,
-            maximum_packet_size: None,
-            wildcard_subscription_available: None,
-            subscription_identifier_available: None,
-            topic_alias_maximum: None,
-            topic_alias: None,
-            maximum_qos: None,
-            request_response_information: None,
-            response_information: None,
-            server_reference: None,
-            request_problem_information: None,
-            user_properties: vec![],
-            authentication_method: None,
-            will_delay_interval: None,
-            payload_format_indicator: None,
-            message_expire_interval: None,
-            content_type: None,
-            response_topic: None,
-            correlation_data: None,
-            subscription_identifier: None,
-            server_keep_alive: None,
-            authentication_data: None,
-            reason_string: None,
-            retain_available: None,
-            shared_subscription_available: None,
-        }
-    }
+#[derive(Clone, Debug, Default)]
+pub struct ConnAckPropertiesBuilder {
+    properties: ConnAckProperties,
 }
 
-impl Default for WillProperties {
-    fn default() -> Self {
-        WillProperties {
-            will_delay_interval: 0,
-            payload_format_indicator: None,
-            message_expire_interval: None,
-            content_type: None,
-            response_topic: None,
-            correlation_data: None,
-            user_properties: vec![],
-        }
+impl ConnAckPropertiesBuilder {
+    property_setter!(session_expire_interval: u32);
+    property_setter!(receive_maximum: u16);
+    property_setter!(maximum_qos: QoS);
+    property_setter!(retain_available: bool);
+    property_setter!(maximum_packet_size: u32);
+    property_setter!(assigned_client_identifier: MqttString);
+    property_setter!(topic_alias_maximum: u16);
+    property_setter!(reason_string: MqttString);
+    property_setter!(user_property);
+    property_setter!(wildcard_subscription_available: bool);
+    property_setter!(subscription_identifier_available: bool);
+    property_setter!(shared_subscription_available: bool);
+    property_setter!(server_keep_alive: u16);
+    property_setter!(response_information: MqttString);
+    property_setter!(server_reference: MqttString);
+    property_setter!(authentication_method: MqttString);
+    property_setter!(authentication_data: Bytes);
+
+    pub fn build(self) -> ConnAckProperties {
+        self.properties
     }
 }
 
-impl PropertiesBuilder {
-    pub fn session_expire_interval(mut self, value: u32) -> Result<Self, MqttError> {
-        check_and_set!(self, session_expire_interval, value)
-    }
-    pub fn receive_maximum(mut self, value: u16) -> Result<Self, MqttError> {
-        check_and_set!(self, receive_maximum, value)
-    }
-    pub fn maximum_packet_size(mut self, value: u32) -> Result<Self, MqttError> {
-        check_and_set!(self, maximum_packet_size, value)
-    }
-    pub fn topic_alias_maximum(mut self, value: u16) -> Result<Self, MqttError> {
-        check_and_set!(self, topic_alias_maximum, value)
-    }
-    pub fn request_response_information(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, request_response_information, value != 0)
-    }
-    pub fn request_problem_information(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, request_problem_information, value != 0)
-    }
-    pub fn user_property(mut self, value: (MqttString, MqttString)) -> Self {
-        self.user_properties.push(value);
+#[derive(Clone, Debug, Default)]
+pub struct PublishPropertiesBuilder {
+    properties: PublishProperties,
+}
+
+impl PublishPropertiesBuilder {
+    property_setter!(payload_format_indicator: bool);
+    property_setter!(message_expire_interval: u32);
+    property_setter!(topic_alias: u16);
+    property_setter!(response_topic: MqttString);
+    property_setter!(correlation_data: Bytes);
+    property_setter!(user_property);
+    property_setter!(content_type: MqttString);
+
+    pub fn subscription_identifier(mut self, value: u32) -> Self {
+        self.properties.subscription_identifiers.push(value);
         self
     }
-    pub fn authentication_method(mut self, value: MqttString) -> Result<Self, MqttError> {
-        check_and_set!(self, authentication_method, value)
-    }
-    pub fn will_delay_interval(mut self, value: u32) -> Result<Self, MqttError> {
-        check_and_set!(self, will_delay_interval, value)
-    }
-    pub fn payload_format_indicator(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, payload_format_indicator, value != 0)
-    }
-    pub fn message_expire_interval(mut self, value: u32) -> Result<Self, MqttError> {
-        check_and_set!(self, message_expire_interval, value)
-    }
-    pub fn content_type(mut self, value: Option<MqttString>) -> Result<Self, MqttError> {
-        if let Some(v) = value {
-            check_and_set!(self, content_type, v)
-        } else {
-            Err(EmptyPropertyValue("ContentType"))
-        }
-    }
-    pub fn response_topic(mut self, value: Option<MqttString>) -> Result<Self, MqttError> {
-        if let Some(v) = value {
-            check_and_set!(self, response_topic, v)
-        } else {
-            Err(EmptyPropertyValue("ResponseTopic"))
-        }
-    }
-    pub fn server_reference(mut self, value: Option<MqttString>) -> Result<Self, MqttError> {
-        if let Some(v) = value {
-            check_and_set!(self, server_reference, v)
-        } else {
-            Err(EmptyPropertyValue("ServerReference"))
-        }
-    }
-    pub fn correlation_data(mut self, value: Option<Bytes>) -> Result<Self, MqttError> {
-        if let Some(v) = value {
-            check_and_set!(self, correlation_data, v)
-        } else {
-            Err(EmptyPropertyValue("CorrelationDate"))
-        }
-    }
-    pub fn maximum_qos(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, maximum_qos, value.try_into()?)
-    }
-    pub fn retain_available(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, retain_available, value != 0)
-    }
-    pub fn wildcard_subscription_available(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, wildcard_subscription_available, value != 0)
-    }
-    pub fn subscription_identifier_available(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, subscription_identifier_available, value != 0)
-    }
-    pub fn shared_subscription_available(mut self, value: u8) -> Result<Self, MqttError> {
-        check_and_set!(self, shared_subscription_available, value != 0)
-    }
-    pub fn server_keep_alive(mut self, value: u16) -> Result<Self, MqttError> {
-        check_and_set!(self, server_keep_alive, value)
-    }
-    pub fn topic_alias(mut self, value: u16) -> Result<Self, MqttError> {
-        check_and_set!(self, topic_alias, value)
-    }
-    pub fn subscription_identifier(mut self, value: u32) -> Result<Self, MqttError> {
-        check_and_set!(self, subscription_identifier, value)
-    }
-    pub fn assigned_client_identifier(
-        mut self,
-        value: Option<MqttString>,
-    ) -> Result<Self, MqttError> {
-        if let Some(v) = value {
-            check_and_set!(self, assigned_client_identifier, v)
-        } else {
-            Err(EmptyPropertyValue("AssignedClientIdentifier"))
-        }
-    }
 
-    pub fn will(self) -> WillProperties {
-        WillProperties {
-            will_delay_interval: self.will_delay_interval.unwrap_or(0),
-            payload_format_indicator: self.payload_format_indicator,
-            message_expire_interval: self.message_expire_interval,
-            content_type: self.content_type,
-            response_topic: self.response_topic,
-            correlation_data: self.correlation_data,
-            user_properties: self.user_properties,
-        }
+    pub fn build(self) -> PublishProperties {
+        self.properties
     }
+}
 
-    pub fn connect(self) -> ConnectProperties {
-        ConnectProperties {
-            session_expire_interval: self.session_expire_interval,
-            receive_maximum: self.receive_maximum,
-            maximum_packet_size: self.maximum_packet_size,
-            topic_alias_maximum: self.topic_alias_maximum,
-            request_response_information: self.request_response_information,
-            request_problem_information: self.request_problem_information,
-            user_properties: self.user_properties,
-            authentication_method: self.authentication_method,
-            authentication_data: self.authentication_data,
-        }
-    }
+/// Properties of PUBACK, PUBREC, PUBREL, PUBCOMP, SUBACK and UNSUBACK.
+#[derive(Clone, Debug, Default)]
+pub struct ResponsePropertiesBuilder {
+    properties: ResponseProperties,
+}
 
-    pub fn connack(self) -> ConnAckProperties {
-        ConnAckProperties {
-            session_expire_interval: self.session_expire_interval,
-            receive_maximum: self.receive_maximum,
-            maximum_qos: self.maximum_qos,
-            retain_available: self.retain_available,
-            maximum_packet_size: self.maximum_packet_size,
-            assigned_client_identifier: self.assigned_client_identifier,
-            topic_alias_maximum: self.topic_alias_maximum,
-            reason_string: self.reason_string,
-            user_properties: self.user_properties,
-            wildcard_subscription_available: self.wildcard_subscription_available,
-            subscription_identifier_available: self.subscription_identifier_available,
-            shared_subscription_available: self.shared_subscription_available,
-            server_keep_alive: self.server_keep_alive,
-            response_information: self.response_information,
-            server_reference: self.server_reference,
-            authentication_method: self.authentication_method,
-            authentication_data: self.authentication_data,
-        }
-    }
+impl ResponsePropertiesBuilder {
+    property_setter!(reason_string: MqttString);
+    property_setter!(user_property);
 
-    pub fn publish(self) -> PublishProperties {
-        PublishProperties {
-            payload_format_indicator: self.payload_format_indicator,
-            message_expire_interval: self.message_expire_interval,
-            topic_alias: self.topic_alias,
-            response_topic: self.response_topic,
-            correlation_data: self.correlation_data,
-            user_properties: self.user_properties,
-            subscription_identifier: self.subscription_identifier,
-            content_type: self.content_type,
-        }
+    pub fn build(self) -> ResponseProperties {
+        self.properties
     }
+}
 
-    pub fn pubres(self) -> ResponseProperties {
-        ResponseProperties {
-            reason_string: self.reason_string,
-            user_properties: self.user_properties,
-        }
-    }
+#[derive(Clone, Debug, Default)]
+pub struct DisconnectPropertiesBuilder {
+    properties: DisconnectProperties,
+}
 
-    pub fn disconnect(self) -> DisconnectProperties {
-        DisconnectProperties {
-            session_expire_interval: self.session_expire_interval,
-            reason_string: self.reason_string,
-            user_properties: self.user_properties,
-            server_reference: self.server_reference,
-        }
+impl DisconnectPropertiesBuilder {
+    property_setter!(session_expire_interval: u32);
+    property_setter!(reason_string: MqttString);
+    property_setter!(user_property);
+    property_setter!(server_reference: MqttString);
+
+    pub fn build(self) -> DisconnectProperties {
+        self.properties
     }
+}
 
-    pub fn subscribe(self) -> SubscribeProperties {
-        SubscribeProperties {
-            subscription_identifier: self.subscription_identifier,
-            user_properties: self.user_properties,
-        }
+#[derive(Clone, Debug, Default)]
+pub struct SubscribePropertiesBuilder {
+    properties: SubscribeProperties,
+}
+
+impl SubscribePropertiesBuilder {
+    property_setter!(subscription_identifier: u32);
+    property_setter!(user_property);
+
+    pub fn build(self) -> SubscribeProperties {
+        self.properties
     }
+}
 
-    pub fn unsubscribe(self) -> UnSubscribeProperties {
-        UnSubscribeProperties {
-            user_properties: self.user_properties,
-        }
+#[derive(Clone, Debug, Default)]
+pub struct UnSubscribePropertiesBuilder {
+    properties: UnSubscribeProperties,
+}
+
+impl UnSubscribePropertiesBuilder {
+    property_setter!(user_property);
+
+    pub fn build(self) -> UnSubscribeProperties {
+        self.properties
     }
+}
 
-    pub fn auth(self) -> AuthProperties {
-        AuthProperties {
-            authentication_method: self.authentication_method,
-            authentication_data: self.authentication_data,
-            reason_string: self.reason_string,
-            user_properties: self.user_properties,
-        }
+#[derive(Clone, Debug, Default)]
+pub struct AuthPropertiesBuilder {
+    properties: AuthProperties,
+}
+
+impl AuthPropertiesBuilder {
+    property_setter!(authentication_method: MqttString);
+    property_setter!(authentication_data: Bytes);
+    property_setter!(reason_string: MqttString);
+    property_setter!(user_property);
+
+    pub fn build(self) -> AuthProperties {
+        self.properties
     }
 }
 
@@ -638,14 +499,14 @@ impl fmt::Display for Property {
 
 #[cfg(test)]
 mod tests {
-    use claim::*;
+    use claims::*;
 
     use super::*;
 
     #[test]
     fn test_connection_properties_default() {
         assert_eq!(
-            PropertiesBuilder::default().connect(),
+            ConnectPropertiesBuilder::default().build(),
             ConnectProperties {
                 session_expire_interval: None,
                 receive_maximum: None,
@@ -661,17 +522,17 @@ mod tests {
     }
     #[test]
     fn test_connection_properties_fill() {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = ConnectPropertiesBuilder::default();
         builder = builder.session_expire_interval(20).unwrap();
         builder = builder.receive_maximum(1000).unwrap();
         builder = builder.maximum_packet_size(1024).unwrap();
         builder = builder.topic_alias_maximum(1024).unwrap();
-        builder = builder.request_response_information(1).unwrap();
-        builder = builder.request_problem_information(1).unwrap();
+        builder = builder.request_response_information(true).unwrap();
+        builder = builder.request_problem_information(true).unwrap();
         builder = builder.user_property((MqttString::from("username"), MqttString::from("admin")));
         builder = builder.user_property((MqttString::from("password"), MqttString::from("12345")));
         assert_eq!(
-            builder.connect(),
+            builder.build(),
             ConnectProperties {
                 session_expire_interval: Some(20),
                 receive_maximum: Some(1000),
@@ -691,13 +552,13 @@ mod tests {
 
     #[test]
     fn test_properties_sei_dup() {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = ConnectPropertiesBuilder::default();
         builder = builder.session_expire_interval(20).unwrap();
         assert_err!(builder.session_expire_interval(60));
     }
     #[test]
     fn test_properties_rm_dup() {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = ConnectPropertiesBuilder::default();
         builder = builder.receive_maximum(20).unwrap();
         assert_err!(builder.receive_maximum(60));
     }
@@ -705,7 +566,7 @@ mod tests {
     #[test]
     fn test_will_properties_default() {
         assert_eq!(
-            PropertiesBuilder::default().will(),
+            WillPropertiesBuilder::default().build(),
             WillProperties {
                 will_delay_interval: 0,
                 payload_format_indicator: None,
@@ -719,10 +580,10 @@ mod tests {
     }
     #[test]
     fn test_connack_properties_default_len() {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = ConnAckPropertiesBuilder::default();
         builder = builder.session_expire_interval(20).unwrap();
         builder = builder.user_property((MqttString::from("username"), MqttString::from("admin")));
         builder = builder.user_property((MqttString::from("password"), MqttString::from("123456")));
-        assert_eq!(42, builder.connack().size());
+        assert_eq!(42, builder.build().size());
     }
 }
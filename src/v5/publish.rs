@@ -1,18 +1,22 @@
-use std::convert::TryInto;
-use std::mem::size_of_val;
+use alloc::string::ToString;
+use core::convert::TryInto;
+use core::mem::size_of_val;
 
+use bytes::buf::Chain;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
-use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
+use crate::v5::codec::Encoder;
+use crate::v5::decoder::{
+    decode_binary_data, decode_bool, decode_utf8_string, decode_variable_integer,
+};
 use crate::v5::encoder::encode_utf8_string;
 use crate::v5::encoder::encode_variable_integer;
 use crate::v5::encoder::RemainingLength;
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::TopicFilterInvalid;
 use crate::v5::error::MqttError::{EndOfStream, UnacceptableProperty, UndefinedPacketIdentifier};
-use crate::v5::property::{PropertiesBuilder, PropertiesSize, Property, PublishProperties};
-use crate::v5::types::{ControlPacket, MqttCodec, Publish, QoS};
+use crate::v5::property::{PropertiesSize, Property, PublishProperties, PublishPropertiesBuilder};
+use crate::v5::types::{payload_str, ControlPacket, MqttCodec, PacketType, Publish, QoS};
 
 pub fn decode_publish(
     dup: bool,
@@ -20,8 +24,23 @@ pub fn decode_publish(
     retain: bool,
     mut reader: Bytes,
 ) -> Result<Option<ControlPacket>, MqttError> {
+    let mut publish = decode_publish_header(dup, qos, retain, &mut reader)?;
+    publish.payload = reader;
+    Ok(Some(ControlPacket::Publish(publish)))
+}
+
+/// Decodes topic name, packet identifier and properties, leaving the payload empty.
+pub fn decode_publish_header(
+    dup: bool,
+    qos: QoS,
+    retain: bool,
+    reader: &mut Bytes,
+) -> Result<Publish, MqttError> {
     end_of_stream!(reader.remaining() < 3, "publish topic name");
-    let topic_name = decode_utf8_string(&mut reader)?.ok_or(TopicFilterInvalid("".to_string()))?;
+    let topic_name = decode_utf8_string(reader)?;
+    if topic_name.is_empty() {
+        return Err(TopicFilterInvalid("".to_string()));
+    }
 
     let packet_identifier = if qos == QoS::AtMostOnce {
         None
@@ -29,28 +48,31 @@ pub fn decode_publish(
         end_of_stream!(reader.remaining() < 2, "publish packet identifier");
         Some(reader.get_u16())
     };
-    let properties_length = decode_variable_integer(&mut reader)? as usize;
+    let properties_length = decode_variable_integer(reader)? as usize;
+    end_of_stream!(reader.remaining() < properties_length, "publish properties");
     let properties = decode_publish_properties(reader.split_to(properties_length))?;
-    Ok(Some(ControlPacket::Publish(Publish {
+    Ok(Publish {
         dup,
         qos,
         retain,
         topic_name,
         packet_identifier,
         properties,
-        payload: reader,
-    })))
+        payload: Bytes::new(),
+    })
 }
 
 pub fn decode_publish_properties(mut reader: Bytes) -> Result<PublishProperties, MqttError> {
-    let mut builder = PropertiesBuilder::default();
+    let mut builder = PublishPropertiesBuilder::default();
     while reader.has_remaining() {
         let id = decode_variable_integer(&mut reader)?;
         let property = id.try_into()?;
         match property {
             Property::PayloadFormatIndicator => {
-                end_of_stream!(reader.remaining() < 1, "payload format indicator");
-                builder = builder.payload_format_indicator(reader.get_u8())?;
+                builder = builder.payload_format_indicator(decode_bool(
+                    &mut reader,
+                    "payload format indicator",
+                )?)?;
             }
             Property::MessageExpireInterval => {
                 end_of_stream!(reader.remaining() < 4, "message expire interval");
@@ -62,35 +84,65 @@ pub fn decode_publish_properties(mut reader: Bytes) -> Result<PublishProperties,
             Property::ResponseTopic => {
                 builder = builder.response_topic(decode_utf8_string(&mut reader)?)?;
             }
-            Property::CorrelationData => unimplemented!(),
+            Property::CorrelationData => {
+                builder = builder.correlation_data(decode_binary_data(&mut reader)?)?;
+            }
             Property::UserProperty => {
-                let user_property = (
+                builder = builder.user_property((
                     decode_utf8_string(&mut reader)?,
                     decode_utf8_string(&mut reader)?,
-                );
-                if let (Some(key), Some(value)) = user_property {
-                    builder = builder.user_property((key, value));
-                }
+                ));
             }
             Property::TopicAlias => {
                 end_of_stream!(reader.remaining() < 2, "topic alias");
                 builder = builder.topic_alias(reader.get_u16())?;
             }
             Property::SubscriptionIdentifier => {
-                end_of_stream!(reader.remaining() < 4, "subscription identifier");
-                builder = builder.subscription_identifier(decode_variable_integer(&mut reader)?)?;
+                builder = builder.subscription_identifier(decode_variable_integer(&mut reader)?);
             }
             _ => return Err(UnacceptableProperty(property)),
         }
     }
-    Ok(builder.publish())
+    Ok(builder.build())
 }
 
-impl Encoder<Publish> for MqttCodec {
+impl Encoder<&Publish> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: Publish, writer: &mut BytesMut) -> Result<(), Self::Error> {
-        self.encode(msg.topic_name, writer)?;
+    fn encode(&mut self, msg: &Publish, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_publish_header(msg, writer)?;
+        writer.put_slice(&msg.payload);
+        Ok(())
+    }
+}
+
+impl MqttCodec {
+    /// Encodes a whole PUBLISH packet without copying the payload: the fixed header, variable
+    /// header and properties go into a small buffer which is chained with the payload, so it
+    /// can be written to the socket with a single vectored write.
+    pub fn encode_publish(&mut self, msg: Publish) -> Result<Chain<Bytes, Bytes>, MqttError> {
+        let remaining_length = msg.remaining_length();
+        let header_length = 1 + remaining_length.size() + remaining_length - msg.payload.len();
+        let mut writer = BytesMut::with_capacity(header_length);
+        writer.put_u8(
+            PacketType::Publish {
+                dup: msg.dup,
+                qos: msg.qos,
+                retain: msg.retain,
+            }
+            .into(),
+        );
+        encode_variable_integer(&mut writer, remaining_length)?;
+        self.encode_publish_header(&msg, &mut writer)?;
+        Ok(writer.freeze().chain(msg.payload))
+    }
+
+    fn encode_publish_header(
+        &mut self,
+        msg: &Publish,
+        writer: &mut BytesMut,
+    ) -> Result<(), MqttError> {
+        self.encode(&msg.topic_name, writer)?;
         if QoS::AtMostOnce != msg.qos {
             if let Some(packet_identifier) = msg.packet_identifier {
                 writer.put_u16(packet_identifier); // packet identifier
@@ -98,8 +150,7 @@ impl Encoder<Publish> for MqttCodec {
                 return Err(UndefinedPacketIdentifier(msg.qos));
             }
         }
-        self.encode(msg.properties, writer)?;
-        writer.put(msg.payload);
+        self.encode(&msg.properties, writer)?;
         Ok(())
     }
 }
@@ -109,6 +160,12 @@ impl Publish {
         let len = self.remaining_length();
         1 + len.size() + len
     }
+
+    /// Payload as text when the Payload Format Indicator marks it as UTF-8, `None` for
+    /// unspecified bytes, `PayloadFormatInvalid` if the marked payload is not valid UTF-8.
+    pub fn payload_str(&self) -> Result<Option<&str>, MqttError> {
+        payload_str(self.properties.payload_format_indicator, &self.payload)
+    }
 }
 
 impl RemainingLength for Publish {
@@ -135,26 +192,28 @@ impl PropertiesSize for PublishProperties {
         len += check_size_of!(self, message_expire_interval);
         len += check_size_of!(self, topic_alias);
         len += check_size_of_string!(self, response_topic);
-        len += check_size_of!(self, correlation_data);
+        len += check_size_of_bytes!(self, correlation_data);
         len += self
             .user_properties
             .iter()
             .map(|(x, y)| 5 + x.len() + y.len())
             .sum::<usize>();
-        if let Some(id) = self.subscription_identifier {
-            len += (id as usize).size();
-        };
+        len += self
+            .subscription_identifiers
+            .iter()
+            .map(|id| 1 + (*id as usize).size())
+            .sum::<usize>();
         len += check_size_of_string!(self, content_type);
         len
     }
 }
 
-impl Encoder<PublishProperties> for MqttCodec {
+impl Encoder<&PublishProperties> for MqttCodec {
     type Error = MqttError;
 
     fn encode(
         &mut self,
-        properties: PublishProperties,
+        properties: &PublishProperties,
         writer: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.encode(properties.size(), writer)?;
@@ -171,16 +230,82 @@ impl Encoder<PublishProperties> for MqttCodec {
         );
         encode_property_u16!(writer, TopicAlias, properties.topic_alias);
         encode_property_string!(writer, ResponseTopic, properties.response_topic);
-        if properties.correlation_data.is_some() {
-            unimplemented!()
-        }
+        encode_property_bytes!(writer, CorrelationData, properties.correlation_data);
         encode_property_user_properties!(writer, UserProperty, properties.user_properties);
-        encode_property_variable_integer!(
-            writer,
-            SubscriptionIdentifier,
-            properties.subscription_identifier
-        );
+        for subscription_identifier in &properties.subscription_identifiers {
+            encode_property_variable_integer!(
+                writer,
+                SubscriptionIdentifier,
+                Some(*subscription_identifier)
+            );
+        }
         encode_property_string!(writer, ContentType, properties.content_type);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::v5::codec::Decoder;
+    use claims::*;
+
+    use crate::v5::string::MqttString;
+
+    use super::*;
+
+    fn publish(payload: &'static [u8]) -> ControlPacket {
+        ControlPacket::Publish(Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic_name: MqttString::from("topic"),
+            packet_identifier: None,
+            properties: PublishProperties {
+                payload_format_indicator: Some(true),
+                ..Default::default()
+            },
+            payload: Bytes::from_static(payload),
+        })
+    }
+
+    #[test]
+    fn test_payload_format_valid() {
+        let mut codec = MqttCodec::new(None).validate_payload_format(true);
+        let mut buf = BytesMut::new();
+        codec.encode(publish(b"hello"), &mut buf).unwrap();
+        match assert_some!(assert_ok!(codec.decode(&mut buf))) {
+            ControlPacket::Publish(publish) => {
+                assert_eq!(Some("hello"), assert_ok!(publish.payload_str()))
+            }
+            packet => panic!("unexpected: {}", packet),
+        }
+    }
+
+    #[test]
+    fn test_payload_format_invalid() {
+        let mut buf = BytesMut::new();
+        MqttCodec::new(None)
+            .encode(publish(b"\xC3\x28"), &mut buf)
+            .unwrap();
+        let mut codec = MqttCodec::new(None).validate_payload_format(true);
+        assert!(matches!(
+            codec.decode(&mut buf.clone()),
+            Err(MqttError::PayloadFormatInvalid)
+        ));
+        assert_ok!(MqttCodec::new(None).decode(&mut buf));
+    }
+
+    #[test]
+    fn test_encode_publish_vectored() {
+        let mut buf = BytesMut::new();
+        MqttCodec::new(None)
+            .encode(publish(b"payload"), &mut buf)
+            .unwrap();
+        let msg = match publish(b"payload") {
+            ControlPacket::Publish(msg) => msg,
+            _ => unreachable!(),
+        };
+        let mut chain = assert_ok!(MqttCodec::new(None).encode_publish(msg));
+        assert_eq!(buf.freeze(), chain.copy_to_bytes(chain.remaining()));
+    }
+}
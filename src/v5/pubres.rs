@@ -1,13 +1,15 @@
-use std::convert::{TryFrom, TryInto};
+use core::convert::{TryFrom, TryInto};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
+use crate::v5::codec::Encoder;
 use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
 use crate::v5::encoder::{encode_utf8_string, RemainingLength};
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::{EndOfStream, UnacceptableProperty};
-use crate::v5::property::{PropertiesBuilder, PropertiesSize, Property, ResponseProperties};
+use crate::v5::property::{
+    PropertiesSize, Property, ResponseProperties, ResponsePropertiesBuilder,
+};
 use crate::v5::types::{MqttCodec, PublishResponse, ReasonCode};
 
 impl TryFrom<Bytes> for PublishResponse {
@@ -40,46 +42,43 @@ impl TryFrom<Bytes> for ResponseProperties {
     type Error = MqttError;
 
     fn try_from(mut reader: Bytes) -> Result<Self, Self::Error> {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = ResponsePropertiesBuilder::default();
         while reader.has_remaining() {
             let id = decode_variable_integer(&mut reader)?;
             let property = id.try_into()?;
             match property {
                 Property::ReasonString => {
-                    builder = builder.response_topic(decode_utf8_string(&mut reader)?)?;
+                    builder = builder.reason_string(decode_utf8_string(&mut reader)?)?;
                 }
                 Property::UserProperty => {
-                    let user_property = (
+                    builder = builder.user_property((
                         decode_utf8_string(&mut reader)?,
                         decode_utf8_string(&mut reader)?,
-                    );
-                    if let (Some(key), Some(value)) = user_property {
-                        builder = builder.user_property((key, value));
-                    }
+                    ));
                 }
                 _ => return Err(UnacceptableProperty(property)),
             }
         }
-        Ok(builder.pubres())
+        Ok(builder.build())
     }
 }
 
-impl Encoder<PublishResponse> for MqttCodec {
+impl Encoder<&PublishResponse> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: PublishResponse, writer: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, msg: &PublishResponse, writer: &mut BytesMut) -> Result<(), Self::Error> {
         writer.put_u16(msg.packet_identifier); // packet identifier
         writer.put_u8(msg.reason_code.into());
-        self.encode(msg.properties, writer)
+        self.encode(&msg.properties, writer)
     }
 }
 
-impl Encoder<ResponseProperties> for MqttCodec {
+impl Encoder<&ResponseProperties> for MqttCodec {
     type Error = MqttError;
 
     fn encode(
         &mut self,
-        properties: ResponseProperties,
+        properties: &ResponseProperties,
         writer: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.encode(properties.size(), writer)?;
@@ -0,0 +1,251 @@
+use alloc::format;
+use core::convert::TryFrom;
+
+use crate::v5::error::MqttError;
+use crate::v5::error::MqttError::ProtocolError;
+use crate::v5::types::{ControlPacket, QoS, ReasonCode};
+
+macro_rules! reason_codes {
+    ($(#[$meta:meta])* $name:ident { $($reason:ident),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $name {
+            $($reason),*
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$reason),*];
+        }
+
+        impl From<$name> for ReasonCode {
+            fn from(reason: $name) -> Self {
+                match reason {
+                    $($name::$reason => ReasonCode::$reason),*
+                }
+            }
+        }
+
+        impl TryFrom<ReasonCode> for $name {
+            type Error = MqttError;
+
+            fn try_from(reason_code: ReasonCode) -> Result<Self, Self::Error> {
+                match reason_code {
+                    $(ReasonCode::$reason => Ok($name::$reason),)*
+                    _ => Err(ProtocolError(format!(
+                        "reason code {:?} is not a {}",
+                        reason_code,
+                        stringify!($name)
+                    ))),
+                }
+            }
+        }
+    };
+}
+
+reason_codes!(
+    /// Reason codes of CONNACK.
+    ConnectReason {
+        Success,
+        UnspecifiedError,
+        MalformedPacket,
+        ProtocolError,
+        ImplementationSpecificError,
+        UnsupportedProtocolVersion,
+        ClientIdentifiersNotValid,
+        BadUserNameOrPassword,
+        NotAuthorized,
+        ServerUnavailable,
+        ServerBusy,
+        Banned,
+        BadAuthenticationMethod,
+        TopicNameInvalid,
+        PacketTooLarge,
+        QuotaExceeded,
+        PayloadFormatInvalid,
+        RetainNotSupported,
+        QoSNotSupported,
+        UseAnotherServer,
+        ServerMoved,
+        ConnectionRateExceeded,
+    }
+);
+
+reason_codes!(
+    /// Reason codes of PUBACK and PUBREC.
+    PubAckReason {
+        Success,
+        NoMatchingSubscribers,
+        UnspecifiedError,
+        ImplementationSpecificError,
+        NotAuthorized,
+        TopicNameInvalid,
+        PacketIdentifierInUse,
+        QuotaExceeded,
+        PayloadFormatInvalid,
+    }
+);
+
+reason_codes!(
+    /// Reason codes of PUBREL and PUBCOMP.
+    PubRelReason {
+        Success,
+        PacketIdentifierNotFound,
+    }
+);
+
+reason_codes!(
+    /// Reason codes of SUBACK, `Success` stands for Granted QoS 0.
+    SubAckReason {
+        Success,
+        GrantedQoS1,
+        GrantedQoS2,
+        UnspecifiedError,
+        ImplementationSpecificError,
+        NotAuthorized,
+        TopicFilterInvalid,
+        PacketIdentifierInUse,
+        QuotaExceeded,
+        SharedSubscriptionsNotSupported,
+        SubscriptionIdentifiersNotSupported,
+        WildcardSubscriptionsNotSupported,
+    }
+);
+
+reason_codes!(
+    /// Reason codes of UNSUBACK.
+    UnSubAckReason {
+        Success,
+        NoSubscriptionExisted,
+        UnspecifiedError,
+        ImplementationSpecificError,
+        NotAuthorized,
+        TopicFilterInvalid,
+        PacketIdentifierInUse,
+    }
+);
+
+reason_codes!(
+    /// Reason codes of DISCONNECT, `Success` stands for Normal disconnection.
+    DisconnectReason {
+        Success,
+        DisconnectWithWill,
+        UnspecifiedError,
+        MalformedPacket,
+        ProtocolError,
+        ImplementationSpecificError,
+        NotAuthorized,
+        ServerBusy,
+        ServerShuttingDown,
+        KeepAliveTimeout,
+        SessionTakenOver,
+        TopicFilterInvalid,
+        TopicNameInvalid,
+        ReceiveMaximumExceeded,
+        TopicAliasInvalid,
+        PacketTooLarge,
+        MessageRateTooHigh,
+        QuotaExceeded,
+        AdministrativeAction,
+        PayloadFormatInvalid,
+        RetainNotSupported,
+        QoSNotSupported,
+        UseAnotherServer,
+        ServerMoved,
+        SharedSubscriptionsNotSupported,
+        ConnectionRateExceeded,
+        MaximumConnectTime,
+        SubscriptionIdentifiersNotSupported,
+        WildcardSubscriptionsNotSupported,
+    }
+);
+
+reason_codes!(
+    /// Reason codes of AUTH.
+    AuthReason {
+        Success,
+        ContinueAuthentication,
+        ReAuthenticate,
+    }
+);
+
+impl SubAckReason {
+    /// Maximum QoS granted for a topic filter, `None` when the subscription failed.
+    pub fn granted_qos(self) -> Option<QoS> {
+        match self {
+            SubAckReason::Success => Some(QoS::AtMostOnce),
+            SubAckReason::GrantedQoS1 => Some(QoS::AtLeastOnce),
+            SubAckReason::GrantedQoS2 => Some(QoS::ExactlyOnce),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that the reason codes of `packet` are allowed for its packet type.
+pub fn validate_reason_codes(packet: &ControlPacket) -> Result<(), MqttError> {
+    match packet {
+        ControlPacket::ConnAck(connack) => {
+            ConnectReason::try_from(connack.reason_code)?;
+        }
+        ControlPacket::PubAck(response) | ControlPacket::PubRec(response) => {
+            PubAckReason::try_from(response.reason_code)?;
+        }
+        ControlPacket::PubRel(response) | ControlPacket::PubComp(response) => {
+            PubRelReason::try_from(response.reason_code)?;
+        }
+        ControlPacket::SubAck(suback) => {
+            for reason_code in &suback.reason_codes {
+                SubAckReason::try_from(*reason_code)?;
+            }
+        }
+        ControlPacket::UnSubAck(unsuback) => {
+            for reason_code in &unsuback.reason_codes {
+                UnSubAckReason::try_from(*reason_code)?;
+            }
+        }
+        ControlPacket::Disconnect(disconnect) => {
+            DisconnectReason::try_from(disconnect.reason_code)?;
+        }
+        ControlPacket::Auth(auth) => {
+            AuthReason::try_from(auth.reason_code)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::*;
+
+    use super::*;
+    use crate::v5::types::Disconnect;
+
+    #[test]
+    fn test_reason_code_conversion() {
+        assert_eq!(
+            SubAckReason::GrantedQoS2,
+            assert_ok!(SubAckReason::try_from(ReasonCode::GrantedQoS2))
+        );
+        assert_eq!(
+            ReasonCode::NoMatchingSubscribers,
+            ReasonCode::from(PubAckReason::NoMatchingSubscribers)
+        );
+        assert_err!(ConnectReason::try_from(ReasonCode::NoMatchingSubscribers));
+    }
+
+    #[test]
+    fn test_decode_invalid_reason_code() {
+        // DISCONNECT with Granted QoS 2
+        let bytes = [0xE0, 0x02, 0x02, 0x00];
+        assert_matches!(
+            ControlPacket::from_bytes(&bytes),
+            Err(MqttError::ProtocolError(_))
+        );
+        let disconnect = ControlPacket::Disconnect(Disconnect {
+            reason_code: ReasonCode::GrantedQoS2,
+            properties: Default::default(),
+        });
+        assert_err!(disconnect.to_bytes());
+    }
+}
@@ -0,0 +1,511 @@
+//! `proptest` strategies generating valid packets, enabled by the `proptest` feature.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use bytes::Bytes;
+use proptest::collection::vec;
+use proptest::option::of;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use crate::v5::property::{
+    AuthProperties, ConnAckProperties, ConnectProperties, DisconnectProperties, PublishProperties,
+    ResponseProperties, SubscribeProperties, UnSubscribeProperties, WillProperties,
+};
+use crate::v5::reason::{
+    AuthReason, ConnectReason, DisconnectReason, PubAckReason, PubRelReason, SubAckReason,
+    UnSubAckReason,
+};
+use crate::v5::string::MqttString;
+use crate::v5::types::{
+    Auth, ConnAck, Connect, ControlPacket, Disconnect, Publish, PublishResponse, QoS, ReasonCode,
+    Retain, SubAck, Subscribe, SubscriptionOptions, UnSubscribe, Will,
+};
+
+const MAX_VARIABLE_INTEGER: u32 = 268_435_455;
+
+/// UTF-8 string without control characters, which may be empty.
+pub fn mqtt_string() -> impl Strategy<Value = MqttString> {
+    "\\PC{0,16}".prop_map(|s: String| MqttString::from(s))
+}
+
+/// Non empty UTF-8 string without control characters, for topics and client identifiers.
+pub fn topic() -> impl Strategy<Value = MqttString> {
+    "\\PC{1,16}".prop_map(|s: String| MqttString::from(s))
+}
+
+pub fn binary_data() -> impl Strategy<Value = Bytes> {
+    vec(any::<u8>(), 0..32).prop_map(Bytes::from)
+}
+
+pub fn qos() -> impl Strategy<Value = QoS> {
+    select(&[QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce][..])
+}
+
+fn reason_code<T: Copy + Into<ReasonCode> + core::fmt::Debug + 'static>(
+    all: &'static [T],
+) -> impl Strategy<Value = ReasonCode> {
+    select(all).prop_map(Into::into)
+}
+
+fn user_properties() -> impl Strategy<Value = Vec<(MqttString, MqttString)>> {
+    vec((mqtt_string(), mqtt_string()), 0..3)
+}
+
+fn subscription_identifier() -> impl Strategy<Value = u32> {
+    1..=MAX_VARIABLE_INTEGER
+}
+
+pub fn will_properties() -> impl Strategy<Value = WillProperties> {
+    (
+        any::<u32>(),
+        of(any::<bool>()),
+        of(any::<u32>()),
+        of(mqtt_string()),
+        of(mqtt_string()),
+        of(binary_data()),
+        user_properties(),
+    )
+        .prop_map(
+            |(
+                will_delay_interval,
+                payload_format_indicator,
+                message_expire_interval,
+                content_type,
+                response_topic,
+                correlation_data,
+                user_properties,
+            )| WillProperties {
+                will_delay_interval,
+                payload_format_indicator,
+                message_expire_interval,
+                content_type,
+                response_topic,
+                correlation_data,
+                user_properties,
+            },
+        )
+}
+
+pub fn connect_properties() -> impl Strategy<Value = ConnectProperties> {
+    (
+        of(any::<u32>()),
+        of(any::<u16>()),
+        of(any::<u32>()),
+        of(any::<u16>()),
+        of(any::<bool>()),
+        of(any::<bool>()),
+        user_properties(),
+        of(mqtt_string()),
+        of(binary_data()),
+    )
+        .prop_map(
+            |(
+                session_expire_interval,
+                receive_maximum,
+                maximum_packet_size,
+                topic_alias_maximum,
+                request_response_information,
+                request_problem_information,
+                user_properties,
+                authentication_method,
+                authentication_data,
+            )| ConnectProperties {
+                session_expire_interval,
+                receive_maximum,
+                maximum_packet_size,
+                topic_alias_maximum,
+                request_response_information,
+                request_problem_information,
+                user_properties,
+                authentication_method,
+                authentication_data,
+            },
+        )
+}
+
+pub fn connack_properties() -> impl Strategy<Value = ConnAckProperties> {
+    (
+        (
+            of(any::<u32>()),
+            of(any::<u16>()),
+            of(select(&[QoS::AtMostOnce, QoS::AtLeastOnce][..])),
+            of(any::<bool>()),
+            of(any::<u32>()),
+            of(mqtt_string()),
+            of(any::<u16>()),
+            of(mqtt_string()),
+            user_properties(),
+        ),
+        (
+            of(any::<bool>()),
+            of(any::<bool>()),
+            of(any::<bool>()),
+            of(any::<u16>()),
+            of(mqtt_string()),
+            of(mqtt_string()),
+            of(mqtt_string()),
+            of(binary_data()),
+        ),
+    )
+        .prop_map(
+            |(
+                (
+                    session_expire_interval,
+                    receive_maximum,
+                    maximum_qos,
+                    retain_available,
+                    maximum_packet_size,
+                    assigned_client_identifier,
+                    topic_alias_maximum,
+                    reason_string,
+                    user_properties,
+                ),
+                (
+                    wildcard_subscription_available,
+                    subscription_identifier_available,
+                    shared_subscription_available,
+                    server_keep_alive,
+                    response_information,
+                    server_reference,
+                    authentication_method,
+                    authentication_data,
+                ),
+            )| ConnAckProperties {
+                session_expire_interval,
+                receive_maximum,
+                maximum_qos,
+                retain_available,
+                maximum_packet_size,
+                assigned_client_identifier,
+                topic_alias_maximum,
+                reason_string,
+                user_properties,
+                wildcard_subscription_available,
+                subscription_identifier_available,
+                shared_subscription_available,
+                server_keep_alive,
+                response_information,
+                server_reference,
+                authentication_method,
+                authentication_data,
+            },
+        )
+}
+
+pub fn publish_properties() -> impl Strategy<Value = PublishProperties> {
+    (
+        of(any::<bool>()),
+        of(any::<u32>()),
+        of(1..=u16::MAX),
+        of(mqtt_string()),
+        of(binary_data()),
+        user_properties(),
+        vec(subscription_identifier(), 0..3),
+        of(mqtt_string()),
+    )
+        .prop_map(
+            |(
+                payload_format_indicator,
+                message_expire_interval,
+                topic_alias,
+                response_topic,
+                correlation_data,
+                user_properties,
+                subscription_identifiers,
+                content_type,
+            )| PublishProperties {
+                payload_format_indicator,
+                message_expire_interval,
+                topic_alias,
+                response_topic,
+                correlation_data,
+                user_properties,
+                subscription_identifiers,
+                content_type,
+            },
+        )
+}
+
+pub fn response_properties() -> impl Strategy<Value = ResponseProperties> {
+    (of(mqtt_string()), user_properties()).prop_map(|(reason_string, user_properties)| {
+        ResponseProperties {
+            reason_string,
+            user_properties,
+        }
+    })
+}
+
+pub fn disconnect_properties() -> impl Strategy<Value = DisconnectProperties> {
+    (
+        of(any::<u32>()),
+        of(mqtt_string()),
+        user_properties(),
+        of(mqtt_string()),
+    )
+        .prop_map(
+            |(session_expire_interval, reason_string, user_properties, server_reference)| {
+                DisconnectProperties {
+                    session_expire_interval,
+                    reason_string,
+                    user_properties,
+                    server_reference,
+                }
+            },
+        )
+}
+
+pub fn subscribe_properties() -> impl Strategy<Value = SubscribeProperties> {
+    (of(subscription_identifier()), user_properties()).prop_map(
+        |(subscription_identifier, user_properties)| SubscribeProperties {
+            subscription_identifier,
+            user_properties,
+        },
+    )
+}
+
+pub fn unsubscribe_properties() -> impl Strategy<Value = UnSubscribeProperties> {
+    user_properties().prop_map(|user_properties| UnSubscribeProperties { user_properties })
+}
+
+pub fn auth_properties() -> impl Strategy<Value = AuthProperties> {
+    (
+        of(mqtt_string()),
+        of(binary_data()),
+        of(mqtt_string()),
+        user_properties(),
+    )
+        .prop_map(
+            |(authentication_method, authentication_data, reason_string, user_properties)| {
+                AuthProperties {
+                    authentication_method,
+                    authentication_data,
+                    reason_string,
+                    user_properties,
+                }
+            },
+        )
+}
+
+pub fn will() -> impl Strategy<Value = Will> {
+    (
+        qos(),
+        any::<bool>(),
+        will_properties(),
+        topic(),
+        binary_data(),
+    )
+        .prop_map(|(qos, retain, properties, topic, payload)| Will {
+            qos,
+            retain,
+            properties,
+            topic,
+            payload,
+        })
+}
+
+pub fn connect() -> impl Strategy<Value = Connect> {
+    (
+        any::<bool>(),
+        any::<u16>(),
+        connect_properties(),
+        of(topic()),
+        of(mqtt_string()),
+        of(binary_data()),
+        of(will()),
+    )
+        .prop_map(
+            |(
+                clean_start_flag,
+                keep_alive,
+                properties,
+                client_identifier,
+                username,
+                password,
+                will,
+            )| Connect {
+                reserved: false,
+                clean_start_flag,
+                keep_alive,
+                properties,
+                client_identifier,
+                username,
+                password,
+                will,
+            },
+        )
+}
+
+pub fn connack() -> impl Strategy<Value = ConnAck> {
+    (
+        any::<bool>(),
+        reason_code(ConnectReason::ALL),
+        connack_properties(),
+    )
+        .prop_map(|(session_present, reason_code, properties)| ConnAck {
+            session_present,
+            reason_code,
+            properties,
+        })
+}
+
+pub fn publish() -> impl Strategy<Value = Publish> {
+    (
+        any::<bool>(),
+        qos(),
+        any::<bool>(),
+        topic(),
+        1..=u16::MAX,
+        publish_properties(),
+        vec(any::<u8>(), 0..256),
+    )
+        .prop_map(
+            |(dup, qos, retain, topic_name, packet_identifier, properties, payload)| Publish {
+                dup,
+                qos,
+                retain,
+                topic_name,
+                packet_identifier: (qos != QoS::AtMostOnce).then_some(packet_identifier),
+                properties,
+                payload: Bytes::from(payload),
+            },
+        )
+}
+
+fn publish_response(
+    reason_codes: impl Strategy<Value = ReasonCode>,
+) -> impl Strategy<Value = PublishResponse> {
+    (1..=u16::MAX, reason_codes, response_properties()).prop_map(
+        |(packet_identifier, reason_code, properties)| PublishResponse {
+            packet_identifier,
+            reason_code,
+            properties,
+        },
+    )
+}
+
+pub fn subscription_options() -> impl Strategy<Value = SubscriptionOptions> {
+    (
+        qos(),
+        any::<bool>(),
+        any::<bool>(),
+        select(
+            &[
+                Retain::SendAtTime,
+                Retain::SendAtSubscribe,
+                Retain::DoNotSend,
+            ][..],
+        ),
+    )
+        .prop_map(|(qos, nl, rap, retain)| SubscriptionOptions {
+            qos,
+            nl,
+            rap,
+            retain,
+        })
+}
+
+pub fn subscribe() -> impl Strategy<Value = Subscribe> {
+    (
+        1..=u16::MAX,
+        subscribe_properties(),
+        vec((topic(), subscription_options()), 1..4),
+    )
+        .prop_map(|(packet_identifier, properties, topic_filters)| Subscribe {
+            packet_identifier,
+            properties,
+            topic_filters,
+        })
+}
+
+fn suback(reason_codes: impl Strategy<Value = ReasonCode>) -> impl Strategy<Value = SubAck> {
+    (1..=u16::MAX, response_properties(), vec(reason_codes, 1..4)).prop_map(
+        |(packet_identifier, properties, reason_codes)| SubAck {
+            packet_identifier,
+            properties,
+            reason_codes,
+        },
+    )
+}
+
+pub fn unsubscribe() -> impl Strategy<Value = UnSubscribe> {
+    (1..=u16::MAX, unsubscribe_properties(), vec(topic(), 1..4)).prop_map(
+        |(packet_identifier, properties, topic_filters)| UnSubscribe {
+            packet_identifier,
+            properties,
+            topic_filters,
+        },
+    )
+}
+
+pub fn disconnect() -> impl Strategy<Value = Disconnect> {
+    (reason_code(DisconnectReason::ALL), disconnect_properties()).prop_map(
+        |(reason_code, properties)| Disconnect {
+            reason_code,
+            properties,
+        },
+    )
+}
+
+pub fn auth() -> impl Strategy<Value = Auth> {
+    (reason_code(AuthReason::ALL), auth_properties()).prop_map(|(reason_code, properties)| Auth {
+        reason_code,
+        properties,
+    })
+}
+
+/// Any valid control packet.
+pub fn control_packet() -> impl Strategy<Value = ControlPacket> {
+    prop_oneof![
+        connect().prop_map(ControlPacket::Connect).boxed(),
+        connack().prop_map(ControlPacket::ConnAck).boxed(),
+        publish().prop_map(ControlPacket::Publish).boxed(),
+        publish_response(reason_code(PubAckReason::ALL))
+            .prop_map(ControlPacket::PubAck)
+            .boxed(),
+        publish_response(reason_code(PubAckReason::ALL))
+            .prop_map(ControlPacket::PubRec)
+            .boxed(),
+        publish_response(reason_code(PubRelReason::ALL))
+            .prop_map(ControlPacket::PubRel)
+            .boxed(),
+        publish_response(reason_code(PubRelReason::ALL))
+            .prop_map(ControlPacket::PubComp)
+            .boxed(),
+        subscribe().prop_map(ControlPacket::Subscribe).boxed(),
+        suback(reason_code(SubAckReason::ALL))
+            .prop_map(ControlPacket::SubAck)
+            .boxed(),
+        unsubscribe().prop_map(ControlPacket::UnSubscribe).boxed(),
+        suback(reason_code(UnSubAckReason::ALL))
+            .prop_map(ControlPacket::UnSubAck)
+            .boxed(),
+        Just(ControlPacket::PingReq).boxed(),
+        Just(ControlPacket::PingResp).boxed(),
+        disconnect().prop_map(ControlPacket::Disconnect).boxed(),
+        auth().prop_map(ControlPacket::Auth).boxed(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::v5::decoder::peek_fixed_header;
+    use crate::v5::encoder::RemainingLength;
+
+    proptest! {
+        #[test]
+        fn test_round_trip(packet in control_packet()) {
+            let bytes = packet.to_bytes().unwrap();
+            let (_, remaining, _) = peek_fixed_header(&BytesMut::from(&bytes[..]))
+                .unwrap()
+                .unwrap();
+            prop_assert_eq!(packet.remaining_length(), remaining);
+            prop_assert_eq!(packet.encoded_len(), bytes.len());
+            prop_assert_eq!(packet, ControlPacket::from_bytes(&bytes).unwrap());
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use core::cmp::min;
+
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::v5::codec::{Decoder, Encoder};
+use crate::v5::decoder::{decode_variable_integer, peek_fixed_header};
+use crate::v5::error::MqttError;
+use crate::v5::error::MqttError::{
+    EndOfStream, MalformedPacket, PacketTooLarge, PayloadFormatInvalid,
+};
+use crate::v5::publish::decode_publish_header;
+use crate::v5::types::{ControlPacket, MqttCodec, PacketPart, PacketType, Publish, QoS};
+
+/// Item produced by [`MqttStreamCodec`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum StreamedPacket {
+    /// A packet decoded as a whole.
+    Packet(ControlPacket),
+    /// Header and properties of a large PUBLISH. The `payload` field is empty, the
+    /// `payload_length` bytes of payload follow as `Payload` items.
+    PublishHeader {
+        publish: Publish,
+        payload_length: usize,
+    },
+    /// Next part of the payload of the last `PublishHeader`, `last` marks the final chunk.
+    Payload { chunk: Bytes, last: bool },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StreamPart {
+    Packet,
+    PublishHeader {
+        remaining: usize,
+        dup: bool,
+        qos: QoS,
+        retain: bool,
+    },
+    Payload {
+        remaining: usize,
+        utf8: Option<Utf8Chunks>,
+    },
+}
+
+/// UTF-8 validation of a payload read in chunks, a character may be split between chunks.
+#[derive(Debug, Clone, Copy, Default)]
+struct Utf8Chunks {
+    partial: [u8; 4],
+    len: usize,
+}
+
+impl Utf8Chunks {
+    fn validate(&mut self, mut chunk: &[u8], last: bool) -> Result<(), MqttError> {
+        // complete the character started at the end of the previous chunk
+        while self.len > 0 && !chunk.is_empty() {
+            self.partial[self.len] = chunk[0];
+            self.len += 1;
+            chunk = &chunk[1..];
+            match core::str::from_utf8(&self.partial[..self.len]) {
+                Ok(_) => self.len = 0,
+                Err(err) if err.error_len().is_some() => return Err(PayloadFormatInvalid),
+                Err(_) => {}
+            }
+        }
+        if let Err(err) = core::str::from_utf8(chunk) {
+            ensure!(err.error_len().is_none(), PayloadFormatInvalid);
+            let partial = &chunk[err.valid_up_to()..];
+            self.partial[..partial.len()].copy_from_slice(partial);
+            self.len = partial.len();
+        }
+        ensure!(!last || self.len == 0, PayloadFormatInvalid);
+        Ok(())
+    }
+}
+
+/// Codec which decodes PUBLISH packets larger than `threshold` without buffering the whole
+/// packet: it yields the header and properties first and then the payload as a sequence of
+/// chunks as they are read from the socket. All other packets are decoded by [`MqttCodec`].
+///
+/// With `validate_payload_format` set on the codec, a payload marked as UTF-8 is validated
+/// chunk by chunk, the chunk with invalid UTF-8 is returned as `PayloadFormatInvalid` after
+/// the header and the previous chunks were already yielded.
+#[derive(Debug)]
+pub struct MqttStreamCodec {
+    codec: MqttCodec,
+    threshold: usize,
+    part: StreamPart,
+}
+
+impl MqttStreamCodec {
+    pub fn new(codec: MqttCodec, threshold: usize) -> Self {
+        MqttStreamCodec {
+            codec,
+            threshold,
+            part: StreamPart::Packet,
+        }
+    }
+
+    pub fn codec(&self) -> &MqttCodec {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut MqttCodec {
+        &mut self.codec
+    }
+}
+
+impl Decoder for MqttStreamCodec {
+    type Item = StreamedPacket;
+    type Error = MqttError;
+
+    fn decode(&mut self, reader: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.part {
+            StreamPart::Packet => {
+                if let PacketPart::FixedHeader = self.codec.part {
+                    let (packet_type, remaining, header_length) = match peek_fixed_header(reader)? {
+                        Some(fixed_header) => fixed_header,
+                        None => return Ok(None),
+                    };
+                    if let PacketType::Publish { dup, qos, retain } = packet_type {
+                        if remaining > self.threshold {
+                            if let Some(maximum_packet_size) = self.codec.maximum_packet_size {
+                                if remaining + header_length > maximum_packet_size as usize {
+                                    return Err(PacketTooLarge);
+                                }
+                            }
+                            reader.advance(header_length);
+                            self.part = StreamPart::PublishHeader {
+                                remaining,
+                                dup,
+                                qos,
+                                retain,
+                            };
+                            return self.decode(reader);
+                        }
+                    }
+                }
+                Ok(self.codec.decode(reader)?.map(StreamedPacket::Packet))
+            }
+            StreamPart::PublishHeader {
+                remaining,
+                dup,
+                qos,
+                retain,
+            } => {
+                let header_length = match peek_publish_header(qos, remaining, reader)? {
+                    Some(header_length) => header_length,
+                    None => return Ok(None),
+                };
+                ensure!(header_length <= remaining, MalformedPacket);
+                if header_length > reader.len() {
+                    return Ok(None);
+                }
+                let mut header = reader.split_to(header_length).freeze();
+                let publish = decode_publish_header(dup, qos, retain, &mut header)?;
+                let payload_length = remaining - header_length;
+                let utf8 = (self.codec.validate_payload_format
+                    && publish.properties.payload_format_indicator == Some(true))
+                .then(Utf8Chunks::default);
+                self.part = if payload_length > 0 {
+                    StreamPart::Payload {
+                        remaining: payload_length,
+                        utf8,
+                    }
+                } else {
+                    StreamPart::Packet
+                };
+                Ok(Some(StreamedPacket::PublishHeader {
+                    publish,
+                    payload_length,
+                }))
+            }
+            StreamPart::Payload { remaining, utf8 } => {
+                if reader.is_empty() {
+                    return Ok(None);
+                }
+                let chunk = reader.split_to(min(reader.len(), remaining)).freeze();
+                let remaining = remaining - chunk.len();
+                self.part = if remaining > 0 {
+                    StreamPart::Payload {
+                        remaining,
+                        utf8: None,
+                    }
+                } else {
+                    StreamPart::Packet
+                };
+                if let Some(mut utf8) = utf8 {
+                    utf8.validate(&chunk, remaining == 0)?;
+                    if let StreamPart::Payload { utf8: next, .. } = &mut self.part {
+                        *next = Some(utf8);
+                    }
+                }
+                Ok(Some(StreamedPacket::Payload {
+                    chunk,
+                    last: remaining == 0,
+                }))
+            }
+        }
+    }
+}
+
+impl Encoder<ControlPacket> for MqttStreamCodec {
+    type Error = MqttError;
+
+    fn encode(&mut self, packet: ControlPacket, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        self.codec.encode(packet, writer)
+    }
+}
+
+/// Length of topic name, packet identifier and properties, without consuming the buffer. A
+/// topic name longer than the `remaining` length of the packet is rejected before it arrives.
+fn peek_publish_header(
+    qos: QoS,
+    remaining: usize,
+    reader: &BytesMut,
+) -> Result<Option<usize>, MqttError> {
+    let mut peek = &reader[..];
+    if peek.remaining() < 2 {
+        return Ok(None);
+    }
+    let topic_length = peek.get_u16() as usize;
+    let packet_identifier_length = if qos == QoS::AtMostOnce { 0 } else { 2 };
+    ensure!(
+        2 + topic_length + packet_identifier_length <= remaining,
+        MalformedPacket
+    );
+    if peek.remaining() < topic_length + packet_identifier_length {
+        return Ok(None);
+    }
+    peek.advance(topic_length + packet_identifier_length);
+    match decode_variable_integer(&mut peek) {
+        Ok(properties_length) => Ok(Some(reader.len() - peek.len() + properties_length as usize)),
+        Err(EndOfStream(_)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::*;
+
+    use super::*;
+    use crate::v5::encoder::RemainingLength;
+    use crate::v5::string::MqttString;
+
+    #[test]
+    fn test_stream_publish_payload() {
+        let payload = Bytes::from((0..=255u8).collect::<Vec<u8>>());
+        let mut buf = BytesMut::new();
+        let mut codec = MqttStreamCodec::new(MqttCodec::new(None), 64);
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic_name: MqttString::from("file"),
+            packet_identifier: Some(10),
+            properties: Default::default(),
+            payload: payload.clone(),
+        };
+        codec
+            .encode(ControlPacket::Publish(publish), &mut buf)
+            .unwrap();
+        codec.encode(ControlPacket::PingReq, &mut buf).unwrap();
+
+        let mut reader = BytesMut::new();
+        let mut received = BytesMut::new();
+        let mut items = vec![];
+        for part in buf.chunks(50) {
+            reader.extend_from_slice(part);
+            while let Some(item) = assert_ok!(codec.decode(&mut reader)) {
+                items.push(item);
+            }
+        }
+
+        match items.remove(0) {
+            StreamedPacket::PublishHeader {
+                publish,
+                payload_length,
+            } => {
+                assert_eq!(MqttString::from("file"), publish.topic_name);
+                assert_eq!(Some(10), publish.packet_identifier);
+                assert_eq!(payload.len(), payload_length);
+            }
+            item => panic!("unexpected: {:?}", item),
+        }
+        assert_eq!(
+            StreamedPacket::Packet(ControlPacket::PingReq),
+            assert_some!(items.pop())
+        );
+        let count = items.len();
+        for (i, item) in items.into_iter().enumerate() {
+            match item {
+                StreamedPacket::Payload { chunk, last } => {
+                    assert_eq!(i + 1 == count, last);
+                    received.extend_from_slice(&chunk);
+                }
+                item => panic!("unexpected: {:?}", item),
+            }
+        }
+        assert_eq!(payload, received.freeze());
+    }
+
+    #[test]
+    fn test_stream_maximum_packet_size() {
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic_name: MqttString::from("file"),
+            packet_identifier: None,
+            properties: Default::default(),
+            payload: Bytes::from(vec![0; 200]),
+        };
+        // 2 bytes of remaining length
+        let size = publish.size();
+        assert_eq!(3, size - publish.remaining_length());
+        let mut buf = BytesMut::new();
+        assert_ok!(MqttCodec::new(None).encode(ControlPacket::Publish(publish), &mut buf));
+
+        let mut codec = MqttStreamCodec::new(MqttCodec::new(Some(size as u32)), 64);
+        assert_matches!(
+            codec.decode(&mut buf.clone()),
+            Ok(Some(StreamedPacket::PublishHeader { .. }))
+        );
+        let mut codec = MqttStreamCodec::new(MqttCodec::new(Some(size as u32 - 1)), 64);
+        assert_matches!(codec.decode(&mut buf), Err(PacketTooLarge));
+    }
+
+    fn publish_bytes(payload: &'static [u8]) -> BytesMut {
+        let mut buf = BytesMut::new();
+        let mut publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: false,
+            topic_name: MqttString::from("text"),
+            packet_identifier: None,
+            properties: Default::default(),
+            payload: Bytes::from_static(payload),
+        };
+        publish.properties.payload_format_indicator = Some(true);
+        let mut codec = MqttCodec::new(None);
+        codec
+            .encode(ControlPacket::Publish(publish), &mut buf)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_stream_payload_format() {
+        let stream_codec =
+            || MqttStreamCodec::new(MqttCodec::new(None).validate_payload_format(true), 4);
+        // "é" split between two chunks
+        let buf = publish_bytes("aaaaaaaaaaaaaaaaaaé".as_bytes());
+        let mut codec = stream_codec();
+        let mut reader = BytesMut::new();
+        for part in buf.chunks(buf.len() - 1) {
+            reader.extend_from_slice(part);
+            while assert_ok!(codec.decode(&mut reader)).is_some() {}
+        }
+
+        let buf = publish_bytes(b"aaaaaaaaaaaaaaaaaa\xC3");
+        let mut codec = stream_codec();
+        let mut reader = BytesMut::from(&buf[..]);
+        assert_matches!(
+            assert_ok!(codec.decode(&mut reader)),
+            Some(StreamedPacket::PublishHeader { .. })
+        );
+        assert_matches!(codec.decode(&mut reader), Err(PayloadFormatInvalid));
+
+        // topic name length beyond the remaining length, rejected before the topic arrives
+        let mut buf = publish_bytes(b"aaaaaaaaaaaaaaaaaa");
+        buf.truncate(4);
+        buf[3] = 0xFF;
+        let mut codec = stream_codec();
+        assert_matches!(codec.decode(&mut buf), Err(MalformedPacket));
+    }
+}
@@ -1,12 +1,14 @@
-use std::convert::TryInto;
-use std::ops::Deref;
-use std::string::FromUtf8Error;
+use alloc::string::{FromUtf8Error, String};
+#[cfg(feature = "serde")]
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::ops::Deref;
 
+use crate::v5::codec::Encoder;
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::EndOfStream;
 use crate::v5::types::MqttCodec;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
 #[derive(Clone, Debug, Eq, PartialEq, Default)]
 pub struct MqttString(Bytes);
@@ -82,14 +84,107 @@ impl MqttString {
     }
 }
 
-impl Encoder<MqttString> for MqttCodec {
+impl Encoder<&MqttString> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, s: MqttString, writer: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, s: &MqttString, writer: &mut BytesMut) -> Result<(), Self::Error> {
         end_of_stream!(writer.capacity() < 2, "encode utf2 string len");
         writer.put_u16(s.len() as u16);
         end_of_stream!(writer.capacity() < s.len(), "encode utf2 string");
-        writer.put(s);
+        writer.put_slice(s);
         Ok(())
     }
 }
+
+/// Serialized as bytes, except for human readable formats where a valid UTF-8 value is
+/// serialized as a string.
+#[cfg(feature = "serde")]
+impl serde::Serialize for MqttString {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match core::str::from_utf8(&self.0) {
+            Ok(s) if serializer.is_human_readable() => serializer.serialize_str(s),
+            _ => serializer.serialize_bytes(&self.0),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MqttString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MqttStringVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for MqttStringVisitor {
+            type Value = MqttString;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str("a string or bytes")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(MqttString(Bytes::copy_from_slice(v.as_bytes())))
+            }
+
+            fn visit_string<E: serde::de::Error>(self, v: String) -> Result<Self::Value, E> {
+                Ok(MqttString::from(v))
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(MqttString(Bytes::copy_from_slice(v)))
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(MqttString(Bytes::from(v)))
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                Ok(MqttString(Bytes::from(bytes)))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(MqttStringVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(MqttStringVisitor)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use claims::*;
+
+    use super::*;
+
+    #[test]
+    fn test_serde_not_utf8() {
+        let value = MqttString::from(Bytes::from_static(&[0x61, 0xFF, 0x00]));
+        let json = assert_ok!(serde_json::to_string(&value));
+        assert_eq!("[97,255,0]", json);
+        assert_eq!(value, assert_ok!(serde_json::from_str::<MqttString>(&json)));
+        let bytes = assert_ok!(bincode::serialize(&value));
+        assert_eq!(
+            value,
+            assert_ok!(bincode::deserialize::<MqttString>(&bytes))
+        );
+    }
+
+    #[test]
+    fn test_serde_utf8() {
+        let value = MqttString::from("a/b");
+        let json = assert_ok!(serde_json::to_string(&value));
+        assert_eq!(r#""a/b""#, json);
+        assert_eq!(value, assert_ok!(serde_json::from_str::<MqttString>(&json)));
+        let bytes = assert_ok!(bincode::serialize(&value));
+        assert_eq!(
+            value,
+            assert_ok!(bincode::deserialize::<MqttString>(&bytes))
+        );
+    }
+}
@@ -1,8 +1,11 @@
-use std::convert::{TryFrom, TryInto};
+use alloc::borrow::ToOwned;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
+use crate::v5::codec::Encoder;
 use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
 use crate::v5::encoder::encode_utf8_string;
 use crate::v5::encoder::encode_variable_integer;
@@ -10,7 +13,7 @@ use crate::v5::encoder::RemainingLength;
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::{EndOfStream, TopicFilterInvalid, UnacceptableProperty};
 use crate::v5::property::{
-    PropertiesBuilder, PropertiesSize, Property, ResponseProperties, SubscribeProperties,
+    PropertiesSize, Property, ResponseProperties, SubscribeProperties, SubscribePropertiesBuilder,
 };
 use crate::v5::string::MqttString;
 use crate::v5::types::{
@@ -31,28 +34,24 @@ pub fn decode_subscribe(mut reader: Bytes) -> Result<Option<ControlPacket>, Mqtt
 }
 
 pub fn decode_subscribe_properties(mut reader: Bytes) -> Result<SubscribeProperties, MqttError> {
-    let mut builder = PropertiesBuilder::default();
+    let mut builder = SubscribePropertiesBuilder::default();
     while reader.has_remaining() {
         let id = decode_variable_integer(&mut reader)?;
         let property = id.try_into()?;
         match property {
             Property::SubscriptionIdentifier => {
-                end_of_stream!(reader.remaining() < 4, "subscription identifier");
                 builder = builder.subscription_identifier(decode_variable_integer(&mut reader)?)?;
             }
             Property::UserProperty => {
-                let user_property = (
+                builder = builder.user_property((
                     decode_utf8_string(&mut reader)?,
                     decode_utf8_string(&mut reader)?,
-                );
-                if let (Some(key), Some(value)) = user_property {
-                    builder = builder.user_property((key, value));
-                }
+                ));
             }
             _ => return Err(UnacceptableProperty(property)),
         }
     }
-    Ok(builder.subscribe())
+    Ok(builder.build())
 }
 
 pub fn decode_subscribe_payload(
@@ -60,7 +59,8 @@ pub fn decode_subscribe_payload(
 ) -> Result<Vec<(MqttString, SubscriptionOptions)>, MqttError> {
     let mut topic_filter = vec![];
     while reader.has_remaining() {
-        if let Some(topic) = decode_utf8_string(&mut reader)? {
+        let topic = decode_utf8_string(&mut reader)?;
+        if !topic.is_empty() {
             end_of_stream!(reader.remaining() < 1, "subscription option");
             let subscription_option = SubscriptionOptions::try_from(reader.get_u8())?;
             topic_filter.push((topic, subscription_option))
@@ -79,7 +79,7 @@ impl RemainingLength for Subscribe {
             + self
                 .topic_filters
                 .iter()
-                .map(|(topic_filter, _)| 2 + topic_filter.len() + std::mem::size_of::<u8>())
+                .map(|(topic_filter, _)| 2 + topic_filter.len() + core::mem::size_of::<u8>())
                 .sum::<usize>()
     }
 }
@@ -88,7 +88,7 @@ impl PropertiesSize for SubscribeProperties {
     fn size(&self) -> usize {
         let mut len = 0;
         if let Some(id) = self.subscription_identifier {
-            len += (id as usize).size();
+            len += 1 + (id as usize).size();
         };
         len += self
             .user_properties
@@ -99,13 +99,13 @@ impl PropertiesSize for SubscribeProperties {
     }
 }
 
-impl Encoder<Subscribe> for MqttCodec {
+impl Encoder<&Subscribe> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: Subscribe, writer: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, msg: &Subscribe, writer: &mut BytesMut) -> Result<(), Self::Error> {
         writer.put_u16(msg.packet_identifier);
-        self.encode(msg.properties, writer)?;
-        for (topic_filter, subscription_option) in msg.topic_filters {
+        self.encode(&msg.properties, writer)?;
+        for (topic_filter, subscription_option) in &msg.topic_filters {
             self.encode(topic_filter, writer)?;
             writer.put_u8(u8::from(subscription_option))
         }
@@ -113,12 +113,12 @@ impl Encoder<Subscribe> for MqttCodec {
     }
 }
 
-impl Encoder<SubscribeProperties> for MqttCodec {
+impl Encoder<&SubscribeProperties> for MqttCodec {
     type Error = MqttError;
 
     fn encode(
         &mut self,
-        properties: SubscribeProperties,
+        properties: &SubscribeProperties,
         writer: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.encode(properties.size(), writer)?;
@@ -140,19 +140,19 @@ impl RemainingLength for SubAck {
             + self
                 .reason_codes
                 .iter()
-                .map(|_r| std::mem::size_of::<u8>())
+                .map(|_r| core::mem::size_of::<u8>())
                 .sum::<usize>()
     }
 }
 
-impl Encoder<SubAck> for MqttCodec {
+impl Encoder<&SubAck> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: SubAck, writer: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, msg: &SubAck, writer: &mut BytesMut) -> Result<(), Self::Error> {
         writer.put_u16(msg.packet_identifier);
-        self.encode(msg.properties, writer)?;
-        for reason_code in msg.reason_codes.into_iter() {
-            writer.put_u8(reason_code.into())
+        self.encode(&msg.properties, writer)?;
+        for reason_code in &msg.reason_codes {
+            writer.put_u8((*reason_code).into())
         }
         Ok(())
     }
@@ -165,7 +165,7 @@ impl TryFrom<Bytes> for SubAck {
         end_of_stream!(reader.remaining() < 2, "suback packet_identifier");
         let packet_identifier = reader.get_u16();
         let properties_length = decode_variable_integer(&mut reader)? as usize;
-        let properties = ResponseProperties::try_from(reader.slice(..properties_length))?;
+        let properties = ResponseProperties::try_from(reader.split_to(properties_length))?;
         let mut reason_codes = vec![];
         while reader.has_remaining() {
             reason_codes.push(ReasonCode::try_from(reader.get_u8())?)
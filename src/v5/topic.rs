@@ -0,0 +1,81 @@
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+
+use crate::v5::error::MqttError;
+use crate::v5::error::MqttError::TopicNameInvalid;
+
+/// Prefix of a shared subscription filter, `$share/{ShareName}/{filter}`.
+const SHARE_PREFIX: &[u8] = b"$share/";
+
+/// Whether the topic name `topic` matches the topic filter `filter`, which may contain the
+/// `+` and `#` wildcards and be a shared subscription. Topic names starting with `$` are not
+/// matched by a filter starting with a wildcard.
+pub fn matches(filter: &[u8], topic: &[u8]) -> bool {
+    let filter = strip_share(filter);
+    if topic.first() == Some(&b'$') && matches!(filter.first(), Some(b'+' | b'#')) {
+        return false;
+    }
+    let mut filter_levels = filter.split(|b| *b == b'/');
+    let mut topic_levels = topic.split(|b| *b == b'/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some(b"#"), _) => return true,
+            (Some(b"+"), Some(_)) => {}
+            (Some(filter), Some(topic)) if filter == topic => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// Checks that `topic` is a topic name to publish to: non-empty UTF-8 without wildcards and
+/// null characters.
+pub fn validate_name(topic: &[u8]) -> Result<(), MqttError> {
+    let name = core::str::from_utf8(topic)
+        .map_err(|_| TopicNameInvalid(String::from_utf8_lossy(topic).into_owned()))?;
+    ensure!(
+        !name.is_empty() && !name.contains(['+', '#', '\0']),
+        TopicNameInvalid(name.to_owned())
+    );
+    Ok(())
+}
+
+/// Topic filter of a shared subscription without the `$share/{ShareName}/` prefix.
+pub fn strip_share(filter: &[u8]) -> &[u8] {
+    match filter.strip_prefix(SHARE_PREFIX) {
+        Some(shared) => match shared.iter().position(|b| *b == b'/') {
+            Some(pos) => &shared[pos + 1..],
+            None => shared,
+        },
+        None => filter,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("sport/tennis/player1", "sport/tennis/player1", true ; "exact")]
+    #[test_case("sport/tennis/+", "sport/tennis/player1", true ; "single level")]
+    #[test_case("sport/+", "sport/tennis/player1", false ; "single level only one")]
+    #[test_case("sport/+/player1", "sport//player1", true ; "single level empty")]
+    #[test_case("sport/#", "sport", true ; "multi level parent")]
+    #[test_case("sport/#", "sport/tennis/player1", true ; "multi level")]
+    #[test_case("#", "$SYS/uptime", false ; "dollar topic wildcard")]
+    #[test_case("$SYS/#", "$SYS/uptime", true ; "dollar topic")]
+    #[test_case("$share/group/sport/+", "sport/tennis", true ; "shared")]
+    #[test_case("sport/tennis", "sport/tennis/player1", false ; "longer topic")]
+    fn test_matches(filter: &str, topic: &str, expected: bool) {
+        assert_eq!(expected, matches(filter.as_bytes(), topic.as_bytes()));
+    }
+
+    #[test_case("sport/tennis", true ; "valid")]
+    #[test_case("", false ; "empty")]
+    #[test_case("sport/+", false ; "single level wildcard")]
+    #[test_case("sport/#", false ; "multi level wildcard")]
+    fn test_validate_name(topic: &str, valid: bool) {
+        assert_eq!(valid, validate_name(topic.as_bytes()).is_ok());
+    }
+}
@@ -1,5 +1,6 @@
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
 use core::fmt;
-use std::convert::{TryFrom, TryInto};
 
 use bytes::Bytes;
 
@@ -30,6 +31,7 @@ pub const MQTT: &str = "MQTT";
 pub const VERSION: u8 = 5;
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QoS {
     AtMostOnce,
     AtLeastOnce,
@@ -66,6 +68,7 @@ impl fmt::Display for QoS {
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Retain {
     SendAtTime,
     SendAtSubscribe,
@@ -168,6 +171,7 @@ impl From<PacketType> for u8 {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReasonCode {
     Success = 0x00,
     GrantedQoS1 = 0x01,
@@ -175,6 +179,8 @@ pub enum ReasonCode {
     DisconnectWithWill = 0x04,
     NoMatchingSubscribers = 0x10,
     NoSubscriptionExisted = 0x11,
+    ContinueAuthentication = 0x18,
+    ReAuthenticate = 0x19,
     UnspecifiedError = 0x80,
     MalformedPacket = 0x81,
     ProtocolError = 0x82,
@@ -195,14 +201,21 @@ pub enum ReasonCode {
     PacketIdentifierInUse = 0x91,
     PacketIdentifierNotFound = 0x92,
     ReceiveMaximumExceeded = 0x93,
+    TopicAliasInvalid = 0x94,
     PacketTooLarge = 0x95,
+    MessageRateTooHigh = 0x96,
     QuotaExceeded = 0x97,
+    AdministrativeAction = 0x98,
     PayloadFormatInvalid = 0x99,
     RetainNotSupported = 0x9A,
     QoSNotSupported = 0x9B,
     UseAnotherServer = 0x9C,
     ServerMoved = 0x9D,
+    SharedSubscriptionsNotSupported = 0x9E,
     ConnectionRateExceeded = 0x9F,
+    MaximumConnectTime = 0xA0,
+    SubscriptionIdentifiersNotSupported = 0xA1,
+    WildcardSubscriptionsNotSupported = 0xA2,
 }
 
 impl TryFrom<u8> for ReasonCode {
@@ -216,6 +229,8 @@ impl TryFrom<u8> for ReasonCode {
             0x04 => Ok(ReasonCode::DisconnectWithWill),
             0x10 => Ok(ReasonCode::NoMatchingSubscribers),
             0x11 => Ok(ReasonCode::NoSubscriptionExisted),
+            0x18 => Ok(ReasonCode::ContinueAuthentication),
+            0x19 => Ok(ReasonCode::ReAuthenticate),
             0x80 => Ok(ReasonCode::UnspecifiedError),
             0x81 => Ok(ReasonCode::MalformedPacket),
             0x82 => Ok(ReasonCode::ProtocolError),
@@ -236,14 +251,21 @@ impl TryFrom<u8> for ReasonCode {
             0x91 => Ok(ReasonCode::PacketIdentifierInUse),
             0x92 => Ok(ReasonCode::PacketIdentifierNotFound),
             0x93 => Ok(ReasonCode::ReceiveMaximumExceeded),
+            0x94 => Ok(ReasonCode::TopicAliasInvalid),
             0x95 => Ok(ReasonCode::PacketTooLarge),
+            0x96 => Ok(ReasonCode::MessageRateTooHigh),
             0x97 => Ok(ReasonCode::QuotaExceeded),
+            0x98 => Ok(ReasonCode::AdministrativeAction),
             0x99 => Ok(ReasonCode::PayloadFormatInvalid),
             0x9A => Ok(ReasonCode::RetainNotSupported),
             0x9B => Ok(ReasonCode::QoSNotSupported),
             0x9C => Ok(ReasonCode::UseAnotherServer),
             0x9D => Ok(ReasonCode::ServerMoved),
+            0x9E => Ok(ReasonCode::SharedSubscriptionsNotSupported),
             0x9F => Ok(ReasonCode::ConnectionRateExceeded),
+            0xA0 => Ok(ReasonCode::MaximumConnectTime),
+            0xA1 => Ok(ReasonCode::SubscriptionIdentifiersNotSupported),
+            0xA2 => Ok(ReasonCode::WildcardSubscriptionsNotSupported),
             _ => Err(MalformedReasonCode(b)),
         }
     }
@@ -258,6 +280,8 @@ impl From<ReasonCode> for u8 {
             ReasonCode::DisconnectWithWill => 0x04,
             ReasonCode::NoMatchingSubscribers => 0x10,
             ReasonCode::NoSubscriptionExisted => 0x11,
+            ReasonCode::ContinueAuthentication => 0x18,
+            ReasonCode::ReAuthenticate => 0x19,
             ReasonCode::UnspecifiedError => 0x80,
             ReasonCode::MalformedPacket => 0x81,
             ReasonCode::ProtocolError => 0x82,
@@ -278,19 +302,27 @@ impl From<ReasonCode> for u8 {
             ReasonCode::PacketIdentifierInUse => 0x91,
             ReasonCode::PacketIdentifierNotFound => 0x92,
             ReasonCode::ReceiveMaximumExceeded => 0x93,
+            ReasonCode::TopicAliasInvalid => 0x94,
             ReasonCode::PacketTooLarge => 0x95,
+            ReasonCode::MessageRateTooHigh => 0x96,
             ReasonCode::QuotaExceeded => 0x97,
+            ReasonCode::AdministrativeAction => 0x98,
             ReasonCode::PayloadFormatInvalid => 0x99,
             ReasonCode::RetainNotSupported => 0x9A,
             ReasonCode::QoSNotSupported => 0x9B,
             ReasonCode::UseAnotherServer => 0x9C,
             ReasonCode::ServerMoved => 0x9D,
+            ReasonCode::SharedSubscriptionsNotSupported => 0x9E,
             ReasonCode::ConnectionRateExceeded => 0x9F,
+            ReasonCode::MaximumConnectTime => 0xA0,
+            ReasonCode::SubscriptionIdentifiersNotSupported => 0xA1,
+            ReasonCode::WildcardSubscriptionsNotSupported => 0xA2,
         }
     }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Will {
     pub qos: QoS,
     pub retain: bool,
@@ -299,7 +331,8 @@ pub struct Will {
     pub payload: Bytes,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Connect {
     pub reserved: bool,
     pub clean_start_flag: bool,
@@ -311,14 +344,16 @@ pub struct Connect {
     pub will: Option<Will>,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnAck {
     pub session_present: bool,
     pub reason_code: ReasonCode,
     pub properties: ConnAckProperties,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Publish {
     pub dup: bool,
     pub qos: QoS,
@@ -329,20 +364,23 @@ pub struct Publish {
     pub payload: Bytes,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublishResponse {
     pub packet_identifier: u16,
     pub reason_code: ReasonCode,
     pub properties: ResponseProperties,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Disconnect {
     pub reason_code: ReasonCode,
     pub properties: DisconnectProperties,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubscriptionOptions {
     pub qos: QoS,
     pub nl: bool,
@@ -350,34 +388,39 @@ pub struct SubscriptionOptions {
     pub retain: Retain,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Subscribe {
     pub packet_identifier: u16,
     pub properties: SubscribeProperties,
     pub topic_filters: Vec<(MqttString, SubscriptionOptions)>,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubAck {
     pub packet_identifier: u16,
     pub properties: ResponseProperties,
     pub reason_codes: Vec<ReasonCode>,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnSubscribe {
     pub packet_identifier: u16,
     pub properties: UnSubscribeProperties,
     pub topic_filters: Vec<MqttString>,
 }
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Auth {
     pub reason_code: ReasonCode,
     pub properties: AuthProperties,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ControlPacket {
     Connect(Connect),
     ConnAck(ConnAck),
@@ -396,6 +439,20 @@ pub enum ControlPacket {
     Auth(Auth),
 }
 
+/// Payload of a PUBLISH or Will as text when its Payload Format Indicator marks it as UTF-8.
+pub(crate) fn payload_str(
+    payload_format_indicator: Option<bool>,
+    payload: &[u8],
+) -> Result<Option<&str>, MqttError> {
+    if let Some(true) = payload_format_indicator {
+        core::str::from_utf8(payload)
+            .map(Some)
+            .map_err(|_| MqttError::PayloadFormatInvalid)
+    } else {
+        Ok(None)
+    }
+}
+
 pub enum PacketPart {
     FixedHeader,
     VariableHeader {
@@ -407,6 +464,7 @@ pub enum PacketPart {
 #[derive(Debug)]
 pub struct MqttCodec {
     pub maximum_packet_size: Option<u32>,
+    pub validate_payload_format: bool,
     pub part: PacketPart,
 }
 
@@ -414,9 +472,17 @@ impl MqttCodec {
     pub fn new(maximum_packet_size: Option<u32>) -> Self {
         MqttCodec {
             maximum_packet_size,
+            validate_payload_format: false,
             part: PacketPart::FixedHeader,
         }
     }
+
+    /// Reject PUBLISH and Will payloads that are marked as UTF-8 by the Payload Format
+    /// Indicator but are not valid UTF-8 with `PayloadFormatInvalid`.
+    pub fn validate_payload_format(mut self, value: bool) -> Self {
+        self.validate_payload_format = value;
+        self
+    }
 }
 
 impl Connect {
@@ -434,6 +500,7 @@ impl Connect {
         flags
     }
 
+    #[allow(clippy::type_complexity)]
     pub(crate) fn set_flags(
         flags: u8,
     ) -> Result<(bool, bool, bool, QoS, bool, bool, bool), MqttError> {
@@ -624,6 +691,12 @@ impl TryFrom<u8> for SubscriptionOptions {
 
 impl From<SubscriptionOptions> for u8 {
     fn from(value: SubscriptionOptions) -> Self {
+        u8::from(&value)
+    }
+}
+
+impl From<&SubscriptionOptions> for u8 {
+    fn from(value: &SubscriptionOptions) -> Self {
         let mut option = 0b0000_0000;
         option |= u8::from(value.qos);
         option |= (value.nl as u8) << 2;
@@ -632,3 +705,45 @@ impl From<SubscriptionOptions> for u8 {
         option
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use claims::*;
+
+    use super::*;
+
+    fn publish() -> ControlPacket {
+        ControlPacket::Publish(Publish {
+            dup: false,
+            qos: QoS::AtLeastOnce,
+            retain: true,
+            topic_name: MqttString::from("topic"),
+            packet_identifier: Some(1),
+            properties: PublishProperties {
+                content_type: Some(MqttString::from("text/plain")),
+                ..Default::default()
+            },
+            payload: Bytes::from_static(b"payload"),
+        })
+    }
+
+    #[test]
+    fn test_serde_json() {
+        let json = assert_ok!(serde_json::to_string(&publish()));
+        assert!(json.contains(r#""topic_name":"topic""#), "{}", json);
+        assert!(json.contains(r#""qos":"AtLeastOnce""#), "{}", json);
+        assert_eq!(
+            publish(),
+            assert_ok!(serde_json::from_str::<ControlPacket>(&json))
+        );
+    }
+
+    #[test]
+    fn test_serde_bincode() {
+        let bytes = assert_ok!(bincode::serialize(&publish()));
+        assert_eq!(
+            publish(),
+            assert_ok!(bincode::deserialize::<ControlPacket>(&bytes))
+        );
+    }
+}
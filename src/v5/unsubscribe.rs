@@ -1,13 +1,18 @@
-use std::convert::TryInto;
+use alloc::borrow::ToOwned;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
 
-use bytes::{Buf, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
+use crate::v5::codec::Encoder;
 use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
-use crate::v5::encoder::RemainingLength;
+use crate::v5::encoder::{encode_utf8_string, RemainingLength};
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::{EndOfStream, TopicFilterInvalid, UnacceptableProperty};
-use crate::v5::property::{PropertiesBuilder, Property, UnSubscribeProperties};
+use crate::v5::property::{
+    PropertiesSize, Property, UnSubscribeProperties, UnSubscribePropertiesBuilder,
+};
 use crate::v5::string::MqttString;
 use crate::v5::types::{ControlPacket, MqttCodec, UnSubscribe};
 
@@ -27,30 +32,28 @@ pub fn decode_unsubscribe(mut reader: Bytes) -> Result<Option<ControlPacket>, Mq
 pub fn decode_unsubscribe_properties(
     mut reader: Bytes,
 ) -> Result<UnSubscribeProperties, MqttError> {
-    let mut builder = PropertiesBuilder::default();
+    let mut builder = UnSubscribePropertiesBuilder::default();
     while reader.has_remaining() {
         let id = decode_variable_integer(&mut reader)?;
         let property = id.try_into()?;
         match property {
             Property::UserProperty => {
-                let user_property = (
+                builder = builder.user_property((
                     decode_utf8_string(&mut reader)?,
                     decode_utf8_string(&mut reader)?,
-                );
-                if let (Some(key), Some(value)) = user_property {
-                    builder = builder.user_property((key, value));
-                }
+                ));
             }
             _ => return Err(UnacceptableProperty(property)),
         }
     }
-    Ok(builder.unsubscribe())
+    Ok(builder.build())
 }
 
 pub fn decode_unsubscribe_payload(mut reader: Bytes) -> Result<Vec<MqttString>, MqttError> {
     let mut topic_filter = vec![];
     while reader.has_remaining() {
-        if let Some(topic) = decode_utf8_string(&mut reader)? {
+        let topic = decode_utf8_string(&mut reader)?;
+        if !topic.is_empty() {
             topic_filter.push(topic)
         } else {
             return Err(TopicFilterInvalid("".to_owned()));
@@ -59,16 +62,51 @@ pub fn decode_unsubscribe_payload(mut reader: Bytes) -> Result<Vec<MqttString>,
     Ok(topic_filter)
 }
 
-impl Encoder<UnSubscribe> for MqttCodec {
+impl Encoder<&UnSubscribe> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, _item: UnSubscribe, _dst: &mut BytesMut) -> Result<(), Self::Error> {
-        unimplemented!()
+    fn encode(&mut self, msg: &UnSubscribe, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        writer.put_u16(msg.packet_identifier);
+        self.encode(&msg.properties, writer)?;
+        for topic_filter in &msg.topic_filters {
+            self.encode(topic_filter, writer)?;
+        }
+        Ok(())
     }
 }
 
 impl RemainingLength for UnSubscribe {
     fn remaining_length(&self) -> usize {
-        unimplemented!()
+        let len = self.properties.size();
+        2 + len.size()
+            + len
+            + self
+                .topic_filters
+                .iter()
+                .map(|topic_filter| 2 + topic_filter.len())
+                .sum::<usize>()
+    }
+}
+
+impl PropertiesSize for UnSubscribeProperties {
+    fn size(&self) -> usize {
+        self.user_properties
+            .iter()
+            .map(|(x, y)| 5 + x.len() + y.len())
+            .sum::<usize>()
+    }
+}
+
+impl Encoder<&UnSubscribeProperties> for MqttCodec {
+    type Error = MqttError;
+
+    fn encode(
+        &mut self,
+        properties: &UnSubscribeProperties,
+        writer: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        self.encode(properties.size(), writer)?;
+        encode_property_user_properties!(writer, UserProperty, properties.user_properties);
+        Ok(())
     }
 }
@@ -1,22 +1,26 @@
-use std::convert::{TryFrom, TryInto};
-use std::mem::size_of_val;
+use core::convert::{TryFrom, TryInto};
+use core::mem::size_of_val;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use tokio_util::codec::Encoder;
 
-use crate::v5::decoder::{decode_utf8_string, decode_variable_integer};
+use crate::v5::codec::Encoder;
+use crate::v5::decoder::{
+    decode_binary_data, decode_bool, decode_utf8_string, decode_variable_integer,
+};
 use crate::v5::encoder::encode_utf8_string;
 use crate::v5::error::MqttError;
 use crate::v5::error::MqttError::EndOfStream;
 use crate::v5::error::MqttError::UnacceptableProperty;
-use crate::v5::property::{PropertiesBuilder, PropertiesSize, Property, WillProperties};
-use crate::v5::types::{MqttCodec, Will};
+use crate::v5::property::{PropertiesSize, Property, WillProperties, WillPropertiesBuilder};
+use crate::v5::string::MqttString;
+use crate::v5::topic;
+use crate::v5::types::{payload_str, MqttCodec, QoS, Will};
 
 impl TryFrom<Bytes> for WillProperties {
     type Error = MqttError;
 
     fn try_from(mut reader: Bytes) -> Result<Self, Self::Error> {
-        let mut builder = PropertiesBuilder::default();
+        let mut builder = WillPropertiesBuilder::default();
         while reader.has_remaining() {
             let id = decode_variable_integer(&mut reader)?;
             let property = id.try_into()?;
@@ -26,8 +30,10 @@ impl TryFrom<Bytes> for WillProperties {
                     builder = builder.will_delay_interval(reader.get_u32())?;
                 }
                 Property::PayloadFormatIndicator => {
-                    end_of_stream!(reader.remaining() < 1, "will payload format indicator");
-                    builder = builder.payload_format_indicator(reader.get_u8())?;
+                    builder = builder.payload_format_indicator(decode_bool(
+                        &mut reader,
+                        "will payload format indicator",
+                    )?)?;
                 }
                 Property::MessageExpireInterval => {
                     end_of_stream!(reader.remaining() < 4, "will message expire interval");
@@ -39,20 +45,117 @@ impl TryFrom<Bytes> for WillProperties {
                 Property::ResponseTopic => {
                     builder = builder.response_topic(decode_utf8_string(&mut reader)?)?;
                 }
-                Property::CorrelationData => unimplemented!(),
+                Property::CorrelationData => {
+                    builder = builder.correlation_data(decode_binary_data(&mut reader)?)?;
+                }
                 Property::UserProperty => {
-                    let user_property = (
+                    builder = builder.user_property((
                         decode_utf8_string(&mut reader)?,
                         decode_utf8_string(&mut reader)?,
-                    );
-                    if let (Some(key), Some(value)) = user_property {
-                        builder = builder.user_property((key, value));
-                    }
+                    ));
                 }
                 _ => return Err(UnacceptableProperty(property)),
             }
         }
-        Ok(builder.will())
+        Ok(builder.build())
+    }
+}
+
+/// Fluent builder of a [`Will`], validated by [`WillBuilder::build`].
+#[derive(Debug, Clone)]
+pub struct WillBuilder {
+    will: Will,
+}
+
+impl WillBuilder {
+    pub fn qos(mut self, value: QoS) -> Self {
+        self.will.qos = value;
+        self
+    }
+
+    pub fn retain(mut self, value: bool) -> Self {
+        self.will.retain = value;
+        self
+    }
+
+    /// Seconds the server waits after the connection is lost before publishing the will.
+    pub fn will_delay_interval(mut self, value: u32) -> Self {
+        self.will.properties.will_delay_interval = value;
+        self
+    }
+
+    pub fn message_expire_interval(mut self, value: u32) -> Self {
+        self.will.properties.message_expire_interval = Some(value);
+        self
+    }
+
+    /// Marks the payload as UTF-8 text.
+    pub fn payload_format_indicator(mut self, value: bool) -> Self {
+        self.will.properties.payload_format_indicator = Some(value);
+        self
+    }
+
+    pub fn content_type(mut self, value: impl Into<MqttString>) -> Self {
+        self.will.properties.content_type = Some(value.into());
+        self
+    }
+
+    pub fn response_topic(mut self, value: impl Into<MqttString>) -> Self {
+        self.will.properties.response_topic = Some(value.into());
+        self
+    }
+
+    pub fn correlation_data(mut self, value: impl Into<Bytes>) -> Self {
+        self.will.properties.correlation_data = Some(value.into());
+        self
+    }
+
+    pub fn user_property(
+        mut self,
+        (key, value): (impl Into<MqttString>, impl Into<MqttString>),
+    ) -> Self {
+        self.will
+            .properties
+            .user_properties
+            .push((key.into(), value.into()));
+        self
+    }
+
+    pub fn build(self) -> Result<Will, MqttError> {
+        self.will.validate()?;
+        Ok(self.will)
+    }
+}
+
+impl Will {
+    /// Builder of a QoS 0, not retained will message.
+    pub fn builder(topic: impl Into<MqttString>, payload: impl Into<Bytes>) -> WillBuilder {
+        WillBuilder {
+            will: Will {
+                qos: QoS::AtMostOnce,
+                retain: false,
+                properties: Default::default(),
+                topic: topic.into(),
+                payload: payload.into(),
+            },
+        }
+    }
+
+    /// Checks that the will topic and response topic are topic names without wildcards and
+    /// that a payload marked as UTF-8 is valid UTF-8.
+    pub fn validate(&self) -> Result<(), MqttError> {
+        topic::validate_name(&self.topic)?;
+        if let Some(response_topic) = &self.properties.response_topic {
+            topic::validate_name(response_topic)?;
+        }
+        self.payload_str()?;
+        Ok(())
+    }
+
+    /// Payload as text when the Payload Format Indicator marks it as UTF-8, `None` for
+    /// unspecified bytes, `PayloadFormatInvalid` if the marked payload is not valid UTF-8.
+    pub fn payload_str(&self) -> Result<Option<&str>, MqttError> {
+        payload_str(self.properties.payload_format_indicator, &self.payload)
     }
 }
 
@@ -63,14 +166,14 @@ impl PropertiesSize for Will {
     }
 }
 
-impl Encoder<Will> for MqttCodec {
+impl Encoder<&Will> for MqttCodec {
     type Error = MqttError;
 
-    fn encode(&mut self, msg: Will, writer: &mut BytesMut) -> Result<(), Self::Error> {
-        self.encode(msg.properties, writer)?;
-        self.encode(msg.topic, writer)?;
+    fn encode(&mut self, msg: &Will, writer: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&msg.properties, writer)?;
+        self.encode(&msg.topic, writer)?;
         writer.put_u16(msg.payload.len() as u16);
-        self.encode(msg.payload, writer)
+        self.encode(&msg.payload, writer)
     }
 }
 
@@ -91,12 +194,12 @@ impl PropertiesSize for WillProperties {
     }
 }
 
-impl Encoder<WillProperties> for MqttCodec {
+impl Encoder<&WillProperties> for MqttCodec {
     type Error = MqttError;
 
     fn encode(
         &mut self,
-        properties: WillProperties,
+        properties: &WillProperties,
         writer: &mut BytesMut,
     ) -> Result<(), Self::Error> {
         self.encode(properties.size(), writer)?;
@@ -122,3 +225,35 @@ impl Encoder<WillProperties> for MqttCodec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use claims::*;
+
+    use super::*;
+    use crate::v5::error::MqttError::{PayloadFormatInvalid, TopicNameInvalid};
+
+    #[test]
+    fn test_will_builder() {
+        let will = assert_ok!(Will::builder("device/1/status", "offline")
+            .qos(QoS::AtLeastOnce)
+            .retain(true)
+            .will_delay_interval(10)
+            .payload_format_indicator(true)
+            .user_property(("device", "1"))
+            .build());
+        assert_eq!(10, will.properties.will_delay_interval);
+        assert_eq!(Some("offline"), assert_ok!(will.payload_str()));
+
+        assert_matches!(
+            Will::builder("device/+/status", "offline").build(),
+            Err(TopicNameInvalid(_))
+        );
+        assert_matches!(
+            Will::builder("device/1/status", Bytes::from_static(&[0xff]))
+                .payload_format_indicator(true)
+                .build(),
+            Err(PayloadFormatInvalid)
+        );
+    }
+}